@@ -1,19 +1,9 @@
 fn main() {
-    // -------------------------
-    // Platform-specific OpenSSL
-    // -------------------------
-    if cfg!(target_os = "windows") {
-        println!("cargo:rustc-link-lib=ssl");
-        println!("cargo:rustc-link-lib=crypto");
-    } else if cfg!(target_os = "macos") {
-        println!("cargo:rustc-link-lib=ssl");
-        println!("cargo:rustc-link-lib=crypto");
-        // Homebrew OpenSSL path
-        println!("cargo:rustc-link-search=native=/usr/local/opt/openssl/lib");
-    } else if cfg!(target_os = "linux") {
-        println!("cargo:rustc-link-lib=ssl");
-        println!("cargo:rustc-link-lib=crypto");
-    }
+    let target_os = std::env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+
+    // Hashing uses OpenSSL (through verify.c) unless the pure-Rust backend is selected
+    let use_openssl = std::env::var_os("CARGO_FEATURE_OPENSSL").is_some()
+        && std::env::var_os("CARGO_FEATURE_RUST_HASH").is_none();
 
     // -------------------------
     // Build flash.c
@@ -30,35 +20,51 @@ fn main() {
     println!("cargo:rustc-link-lib=static=flash");
 
     // -------------------------
-    // Build verify.c
+    // Build verify.c (OpenSSL backend only)
     // -------------------------
-    let mut verify_build = cc::Build::new();
-    verify_build
-        .file("c_utils/verify.c")
-        .include("c_utils")
-        .opt_level(3)
-        .flag("-march=native")
-        .flag("-funroll-loops")
-        .flag("-Ofast");
+    if use_openssl {
+        let mut verify_build = cc::Build::new();
+        verify_build
+            .file("c_utils/verify.c")
+            .include("c_utils")
+            .opt_level(3)
+            .flag("-march=native")
+            .flag("-funroll-loops")
+            .flag("-Ofast");
 
-    // macOS Homebrew OpenSSL headers
-    if cfg!(target_os = "macos") {
-        verify_build.include("/usr/local/opt/openssl/include");
-    }
+        // -------------------------
+        // Platform-specific OpenSSL location
+        // -------------------------
+        // OPENSSL_DIR overrides everything; on macOS fall back to the Homebrew prefixes
+        let openssl_dirs: Vec<String> = match std::env::var("OPENSSL_DIR") {
+            Ok(dir) => vec![dir],
+            Err(_) if target_os == "macos" => vec![
+                "/opt/homebrew/opt/openssl".to_string(),
+                "/usr/local/opt/openssl".to_string(),
+            ],
+            Err(_) => Vec::new(),
+        };
+        for dir in &openssl_dirs {
+            verify_build.include(format!("{}/include", dir));
+            println!("cargo:rustc-link-search=native={}/lib", dir);
+        }
 
-    verify_build.compile("verify");
+        verify_build.compile("verify");
 
-    // Link the static verify library
-    println!("cargo:rustc-link-lib=static=verify");
+        // Link the static verify library
+        println!("cargo:rustc-link-lib=static=verify");
 
-    // Make sure OpenSSL is linked for verify.c
-    println!("cargo:rustc-link-lib=ssl");
-    println!("cargo:rustc-link-lib=crypto");
+        // Make sure OpenSSL is linked for verify.c
+        println!("cargo:rustc-link-lib=ssl");
+        println!("cargo:rustc-link-lib=crypto");
+    }
 
     // -------------------------
     // Re-run build.rs if C files change
     // -------------------------
     println!("cargo:rerun-if-changed=c_utils/flash.c");
+    println!("cargo:rerun-if-changed=c_utils/flash.h");
     println!("cargo:rerun-if-changed=c_utils/verify.c");
     println!("cargo:rerun-if-changed=c_utils/verify.h");
+    println!("cargo:rerun-if-env-changed=OPENSSL_DIR");
 }
@@ -7,11 +7,49 @@
 #include <errno.h>
 #include <string.h>
 #include <stdbool.h>
+#include "flash.h"
 
 #define BUFFER_SIZE (128 * 1024 * 1024)
 #define ALIGNMENT   4096
 
-void flash(const char *iso_path, const char *dev_path) {
+// Reads the image file for flash()
+static ssize_t read_fd(void *ctx, unsigned char *buf, size_t len) {
+    ssize_t n = read(*(int *)ctx, buf, len);
+    if (n < 0)
+        perror("read");
+    return n;
+}
+
+// on_chunk, if not NULL, is called with every chunk read from the image,
+// so the caller can hash the image while it is being written
+// Returns 0 on success, -1 on failure (the reason is printed with perror)
+int flash(const char *iso_path, const char *dev_path,
+          void (*on_chunk)(void *ctx, const unsigned char *buf, size_t len), void *ctx) {
+    int fd_iso = open(iso_path, O_RDONLY);
+    if (fd_iso < 0) {
+        perror("open");
+        return -1;
+    }
+
+    // Determine ISO size for progress calculation
+    struct stat st;
+    if (fstat(fd_iso, &st) != 0) {
+        perror("fstat");
+        close(fd_iso);
+        return -1;
+    }
+
+    int status = flash_stream(read_fd, &fd_iso, (long long)st.st_size, dev_path, on_chunk, ctx);
+    close(fd_iso);
+    return status;
+}
+
+// Write the image produced by read_chunk to dev_path
+// read_chunk returns the number of bytes it put in buf, 0 at the end of the image or -1 on error
+// (it reports its own errors); total_size is only used for the progress bar
+int flash_stream(ssize_t (*read_chunk)(void *read_ctx, unsigned char *buf, size_t len), void *read_ctx,
+                 long long total_size, const char *dev_path,
+                 void (*on_chunk)(void *ctx, const unsigned char *buf, size_t len), void *ctx) {
     #ifdef __WIN32
         system("cls");
     #else
@@ -19,15 +57,23 @@ void flash(const char *iso_path, const char *dev_path) {
     #endif
 
     // -------------------------------
-    // Open files
+    // Open the device
     // -------------------------------
-    int fd_iso = open(iso_path, O_RDONLY);
-    int fd_dev = open(dev_path, O_WRONLY);
-    if (fd_iso < 0 || fd_dev < 0) {
-        perror("open");
-        if (fd_iso >= 0) close(fd_iso);
-        if (fd_dev >= 0) close(fd_dev);
-        exit(EXIT_FAILURE);
+    // On Linux, O_EXCL on a block device claims it: the open fails with EBUSY while a
+    // filesystem on it is mounted, it is part of a dm/md/LVM stack, or another program
+    // (a second tEtcher, a partitioning tool) holds it exclusively
+    #ifdef __linux__
+        int fd_dev = open(dev_path, O_WRONLY | O_EXCL);
+    #else
+        int fd_dev = open(dev_path, O_WRONLY);
+    #endif
+    if (fd_dev < 0) {
+        if (errno == EBUSY) {
+            fprintf(stderr, "%s is busy: unmount its partitions and close any program using it, then try again\n", dev_path);
+        } else {
+            perror("open");
+        }
+        return -1;
     }
 
     // -------------------------------
@@ -36,25 +82,11 @@ void flash(const char *iso_path, const char *dev_path) {
     void *buffer = NULL;
     if (posix_memalign(&buffer, ALIGNMENT, BUFFER_SIZE) != 0) {
         fprintf(stderr, "Failed to allocate aligned buffer\n");
-        close(fd_iso);
-        close(fd_dev);
-        exit(EXIT_FAILURE);
-    }
-
-    // -------------------------------
-    // Determine ISO size for progress calculation
-    // -------------------------------
-    struct stat st;
-    if (fstat(fd_iso, &st) != 0) {
-        perror("fstat");
-        free(buffer);
-        close(fd_iso);
         close(fd_dev);
-        exit(EXIT_FAILURE);
+        return -1;
     }
-    off_t iso_size = st.st_size;
 
-    off_t total_copied = 0;
+    long long total_copied = 0;
     ssize_t read_bytes, written_bytes;
     int last_percent = -1;
 
@@ -64,7 +96,7 @@ void flash(const char *iso_path, const char *dev_path) {
     // -------------------------------
     // Copy loop
     // -------------------------------
-    while ((read_bytes = read(fd_iso, buffer, BUFFER_SIZE)) > 0) {
+    while ((read_bytes = read_chunk(read_ctx, (unsigned char *)buffer, BUFFER_SIZE)) > 0) {
         ssize_t total_written = 0;
 
         // Write entire buffer to device
@@ -73,19 +105,22 @@ void flash(const char *iso_path, const char *dev_path) {
             if (written_bytes < 0) {
                 perror("write");
                 free(buffer);
-                close(fd_iso);
                 close(fd_dev);
-                exit(EXIT_FAILURE);
+                return -1;
             }
             total_written += written_bytes;
         }
 
+        if (on_chunk)
+            on_chunk(ctx, (const unsigned char *)buffer, (size_t)read_bytes);
+
         total_copied += read_bytes;
 
         // -------------------------------
         // Progress display with bar
         // -------------------------------
-        int percent = (int)((total_copied * 100LL) / iso_size);
+        int percent = total_size > 0 ? (int)((total_copied * 100LL) / total_size) : 100;
+        if (percent > 100) percent = 100;
         if (percent != last_percent) {
             int progress_chars = (percent * bar_width) / 100;
             fprintf(stderr, "\rProgress: [%-*.*s] %3d%%", bar_width, progress_chars, bar, percent);
@@ -94,8 +129,11 @@ void flash(const char *iso_path, const char *dev_path) {
         }
     }
 
-    if (read_bytes < 0)
-        perror("read");
+    int status = 0;
+    if (read_bytes < 0) {
+        fprintf(stderr, "\nFailed to read the image\n");
+        status = -1;
+    }
 
     // -------------------------------
     // Flush to device BEFORE returning
@@ -103,20 +141,23 @@ void flash(const char *iso_path, const char *dev_path) {
     fprintf(stderr, "\nFlushing data to disk... (this may take a while)\n");
     fflush(stderr);
 
-    if (fsync(fd_dev) != 0)
+    if (fsync(fd_dev) != 0) {
         perror("fsync");
+        status = -1;
+    }
 
     // -------------------------------
-    // Final message after flush completes
+    // Final message after flush completes, only if everything reached the device
     // -------------------------------
-    fprintf(stderr, "Finished flashing!\n");
-    fflush(stderr);
+    if (status == 0) {
+        fprintf(stderr, "Finished flashing!\n");
+        fflush(stderr);
+    }
 
     // -------------------------------
     // Cleanup
     // -------------------------------
     close(fd_dev);
-    close(fd_iso);
     free(buffer);
+    return status;
 }
-
@@ -1,6 +1,14 @@
 #ifndef FLASH_H
 #define FLASH_H
 
-void flash(char *iso_path, char *dev_path);
+#include <stddef.h>
+#include <sys/types.h>
+
+int flash(const char *iso_path, const char *dev_path,
+          void (*on_chunk)(void *ctx, const unsigned char *buf, size_t len), void *ctx);
+
+int flash_stream(ssize_t (*read_chunk)(void *read_ctx, unsigned char *buf, size_t len), void *read_ctx,
+                 long long total_size, const char *dev_path,
+                 void (*on_chunk)(void *ctx, const unsigned char *buf, size_t len), void *ctx);
 
 #endif
\ No newline at end of file
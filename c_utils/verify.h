@@ -1,10 +1,12 @@
-#include <stdbool.h>
-
 #ifndef VERIFY_H
 #define VERIFY_H
 
-static long get_file_size(const char *filename);
-int compute_sha256(const char *filename, unsigned char hash[EVP_MAX_MD_SIZE], unsigned int *hash_len, long max_bytes);
-bool verify(const char *iso_path, const char *dev_path);
+#include <stddef.h>
+#include <openssl/evp.h>
+
+void *digest_new(const char *algo);
+int digest_update(void *ctx, const unsigned char *buf, size_t len);
+int digest_final(void *ctx, unsigned char hash[EVP_MAX_MD_SIZE], unsigned int *hash_len);
+void digest_free(void *ctx);
 
-#endif
\ No newline at end of file
+#endif
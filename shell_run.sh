@@ -1 +1 @@
-cargo build --release && sudo ./target/release/tEtcher2
+cargo build --release && sudo ./target/release/tEtcher2 "$@"
@@ -0,0 +1,148 @@
+use std::path::Path;
+
+use crossterm::style::{Color, Stylize};
+
+use crate::iso9660::{self, BootPlatform};
+use crate::parttable;
+
+/// How an image will boot once it is written raw to a USB drive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boot {
+    /// MBR boot code and an EFI System Partition: boots on BIOS and UEFI machines
+    Hybrid,
+    /// EFI System Partition but no MBR boot code
+    UefiOnly,
+    /// MBR boot code but no EFI System Partition
+    BiosOnly,
+    /// Only an El Torito boot catalog: boots from a CD/DVD, not from a USB drive
+    OpticalOnly,
+    /// A partition table with nothing a PC can boot, e.g. a Raspberry Pi image
+    NotPcBootable,
+}
+
+impl Boot {
+    /// Lines for the confirm screens; optical-only images get a prominent warning
+    pub fn lines(&self) -> Vec<String> {
+        let boot = "[ BOOT ]";
+        match self {
+            Boot::Hybrid => vec![format!(
+                "{} hybrid USB-bootable: boots on both BIOS and UEFI machines",
+                boot.with(Color::Green).bold()
+            )],
+            Boot::UefiOnly => vec![format!(
+                "{} UEFI-only: will not boot on machines using legacy BIOS / CSM boot",
+                boot.with(Color::Yellow).bold()
+            )],
+            Boot::BiosOnly => vec![format!(
+                "{} BIOS-only: needs legacy boot (CSM) enabled, will not boot in pure UEFI mode",
+                boot.with(Color::Yellow).bold()
+            )],
+            Boot::OpticalOnly => vec![
+                format!(
+                    "{} optical-only: this image boots from a CD/DVD, but written to a USB drive it will NOT boot",
+                    "[ WARN ]".with(Color::Red).bold()
+                ),
+                "  It has an El Torito boot catalog but no MBR boot code or EFI System Partition".to_string(),
+                "  To boot it from USB, use a tool that builds the drive from the image's files".to_string(),
+            ],
+            Boot::NotPcBootable => vec![format!(
+                "{} no PC boot code or EFI System Partition: fine for boards that boot from their own firmware",
+                boot.with(Color::Yellow).bold()
+            )],
+        }
+    }
+}
+
+/// Classify `image` from its MBR boot code, partition table and El Torito catalog
+///
+/// Returns None for files with none of these, which say nothing about booting
+pub fn classify(image: &Path) -> Option<Boot> {
+    let mbr = parttable::mbr(image);
+    let table = parttable::read(image);
+    let el_torito = iso9660::read(image).map(|v| v.el_torito).unwrap_or_default();
+
+    // Anything in the first 440 bytes (before the disk signature) is boot code
+    let bios = mbr.is_some_and(|sector| sector[..440].iter().any(|&b| b != 0));
+    let uefi = table.as_ref().is_some_and(|t| t.has_esp());
+
+    match (bios, uefi) {
+        (true, true) => Some(Boot::Hybrid),
+        (false, true) => Some(Boot::UefiOnly),
+        (true, false) => Some(Boot::BiosOnly),
+        (false, false) if el_torito.iter().any(|p| matches!(p, BootPlatform::Bios | BootPlatform::Efi)) => {
+            Some(Boot::OpticalOnly)
+        }
+        (false, false) if table.is_some() => Some(Boot::NotPcBootable),
+        (false, false) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testimg::{self, ESP, Iso, LINUX};
+
+    fn boot(name: &str, image: &[u8]) -> Option<Boot> {
+        classify(&testimg::file(name, image))
+    }
+
+    /// An ISO with El Torito entries for `platforms` and, if given, `mbr` over its system area
+    fn iso(platforms: &[u8], mbr: Option<Vec<u8>>) -> Vec<u8> {
+        let mut image = Iso { el_torito: platforms.to_vec(), ..Default::default() }.build();
+        if let Some(mbr) = mbr {
+            image[..512].copy_from_slice(&mbr);
+        }
+        image
+    }
+
+    #[test]
+    fn classifies_isos_by_what_they_boot_from() {
+        let isohybrid = testimg::mbr(true, &[(0x00, 0, 48), (0xEF, 48, 8)]);
+        assert_eq!(boot("boot-hybrid.iso", &iso(&[0x00, 0xEF], Some(isohybrid))), Some(Boot::Hybrid));
+        let bios = testimg::mbr(true, &[(0x17, 0, 48)]);
+        assert_eq!(boot("boot-bios.iso", &iso(&[0x00], Some(bios))), Some(Boot::BiosOnly));
+        assert_eq!(boot("boot-optical.iso", &iso(&[0x00, 0xEF], None)), Some(Boot::OpticalOnly));
+        assert_eq!(boot("boot-efi-optical.iso", &iso(&[0xEF], None)), Some(Boot::OpticalOnly));
+        // Only PowerPC and Mac entries: nothing a PC boots, and no partition table either
+        assert_eq!(boot("boot-ppc.iso", &iso(&[0x01, 0x02], None)), None);
+        assert_eq!(boot("boot-data.iso", &iso(&[], None)), None);
+        assert!(Boot::OpticalOnly.lines()[0].contains("will NOT boot"));
+    }
+
+    #[test]
+    fn classifies_disk_images_by_their_tables() {
+        let esp = (ESP, 34, 97, "EFI system partition");
+        let root = (LINUX, 98, 255, "root");
+        assert_eq!(boot("boot-gpt.img", &testimg::gpt(256, &[esp, root])), Some(Boot::UefiOnly));
+        let mut hybrid = testimg::gpt(256, &[esp, root]);
+        hybrid[..2].copy_from_slice(&[0xEB, 0x63]);
+        assert_eq!(boot("boot-gpt-hybrid.img", &hybrid), Some(Boot::Hybrid));
+        assert_eq!(boot("boot-gpt-data.img", &testimg::gpt(256, &[root])), Some(Boot::NotPcBootable));
+
+        let mut disk = vec![0u8; 64 * 512];
+        disk[..512].copy_from_slice(&testimg::mbr(true, &[(0x83, 1, 63)]));
+        assert_eq!(boot("boot-mbr.img", &disk), Some(Boot::BiosOnly));
+        // A Raspberry Pi image: FAT boot partition, firmware in the SoC
+        disk[..512].copy_from_slice(&testimg::mbr(false, &[(0x0C, 1, 15), (0x83, 16, 48)]));
+        assert_eq!(boot("boot-rpi.img", &disk), Some(Boot::NotPcBootable));
+        assert_eq!(boot("boot-zeros.img", &[0; 4096]), None);
+    }
+
+    #[test]
+    fn classifies_truncated_images_by_what_is_left() {
+        let isohybrid = iso(&[0x00, 0xEF], Some(testimg::mbr(true, &[(0xEF, 48, 8)])));
+        assert_eq!(boot("boot-cut.iso", &isohybrid[..300]), None);
+        assert_eq!(boot("boot-cut.iso", &isohybrid[..512]), Some(Boot::Hybrid));
+        assert_eq!(boot("boot-cut.iso", &isohybrid[..17 * 2048]), Some(Boot::Hybrid));
+
+        let optical = iso(&[0x00], None);
+        for len in [0, 17 * 2048 + 100, 23 * 2048] {
+            assert_eq!(boot("boot-cut.iso", &optical[..len]), None, "{} bytes", len);
+        }
+        assert_eq!(boot("boot-cut.iso", &optical[..24 * 2048]), Some(Boot::OpticalOnly));
+
+        // A GPT whose entries are cut off still has its protective MBR
+        let disk = testimg::gpt(256, &[(ESP, 34, 255, "EFI system partition")]);
+        assert_eq!(boot("boot-cut.img", &disk[..1536]), Some(Boot::NotPcBootable));
+    }
+}
@@ -0,0 +1,241 @@
+use std::path::Path;
+
+use crossterm::style::Stylize;
+
+/// Lines for the confirm screen naming whatever is using `dev`; empty when it is free
+pub fn lines(root: &Path, dev: &str) -> Vec<String> {
+    let users = users(root, dev);
+    if users.is_empty() {
+        return Vec::new();
+    }
+    let mut lines = vec![format!(
+        "{} {} is in use, so writing to it will fail until it is released:",
+        "[ BUSY ]".with(crossterm::style::Color::Red).bold(),
+        dev
+    )];
+    lines.extend(users.into_iter().map(|user| format!("  {}", user)));
+    lines
+}
+
+/// Mounts, swap, device-mapper/md holders and processes using `dev` or its partitions
+///
+/// /sys, /proc and /dev are looked up under `root`, which is "/" except when testing.
+#[cfg(target_os = "linux")]
+pub fn users(root: &Path, dev: &str) -> Vec<String> {
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    use crate::sysdisk;
+
+    let dev_path = root.join(dev.trim_start_matches('/'));
+    let Some(name) = fs::canonicalize(&dev_path).ok().and_then(|p| Some(p.file_name()?.to_string_lossy().to_string()))
+    else {
+        return Vec::new();
+    };
+    let class = root.join("sys/class/block");
+    let sys = class.join(&name);
+
+    // The drive and its partitions, by kernel name and by "major:minor"
+    let mut nodes = vec![name.clone()];
+    if let Ok(entries) = fs::read_dir(&sys) {
+        for entry in entries.flatten() {
+            let child = entry.file_name().to_string_lossy().to_string();
+            if fs::metadata(sys.join(&child).join("partition")).is_ok() {
+                nodes.push(child);
+            }
+        }
+    }
+    nodes.sort();
+    let by_majmin: BTreeMap<String, &str> = nodes
+        .iter()
+        .filter_map(|node| {
+            let majmin = fs::read_to_string(class.join(node).join("dev")).ok()?;
+            Some((majmin.trim().to_string(), node.as_str()))
+        })
+        .collect();
+    let dev_dir = fs::canonicalize(root.join("dev")).ok();
+    let node_of_path = |path: &str| {
+        let real = fs::canonicalize(root.join(path.trim_start_matches('/'))).ok()?;
+        let file = real.file_name()?.to_string_lossy().to_string();
+        (real.parent() == dev_dir.as_deref()).then(|| nodes.iter().find(|n| **n == file))?
+    };
+
+    let mut users = Vec::new();
+
+    if let Ok(mountinfo) = fs::read_to_string(root.join("proc/self/mountinfo")) {
+        for line in mountinfo.lines() {
+            let Some((mount, rest)) = line.split_once(" - ") else { continue };
+            let fields: Vec<&str> = mount.split(' ').collect();
+            let (Some(majmin), Some(point)) = (fields.get(2), fields.get(4)) else { continue };
+            let source = rest.split(' ').nth(1).unwrap_or("");
+            let node = by_majmin.get(*majmin).copied().or_else(|| node_of_path(source).map(String::as_str));
+            if let Some(node) = node {
+                // Spaces and tabs in mount points are octal-escaped
+                let point = point.replace("\\040", " ").replace("\\011", "\t");
+                users.push(format!("{} is mounted at {}", node, point));
+            }
+        }
+    }
+
+    if let Ok(swaps) = fs::read_to_string(root.join("proc/swaps")) {
+        for line in swaps.lines().skip(1) {
+            if let Some(node) = line.split_whitespace().next().and_then(node_of_path) {
+                users.push(format!("{} is in use as swap", node));
+            }
+        }
+    }
+
+    for node in &nodes {
+        if let Ok(holders) = fs::read_dir(class.join(node).join("holders")) {
+            for holder in holders.flatten() {
+                let holder = holder.file_name().to_string_lossy().to_string();
+                users.push(format!("{} is held by {}", node, sysdisk::describe(root, &holder)));
+            }
+        }
+    }
+
+    // Open file descriptors; other users' processes are only visible to root. The fd links
+    // name devices as /dev/..., not as paths under `root`
+    let me = std::process::id().to_string();
+    if let Ok(procs) = fs::read_dir(root.join("proc")) {
+        for proc in procs.flatten() {
+            let pid = proc.file_name().to_string_lossy().to_string();
+            if !pid.bytes().all(|b| b.is_ascii_digit()) || pid == me {
+                continue;
+            }
+            let Ok(fds) = fs::read_dir(proc.path().join("fd")) else { continue };
+            let mut open: Vec<&str> = fds
+                .flatten()
+                .filter_map(|fd| fs::read_link(fd.path()).ok())
+                .filter_map(|target| {
+                    let file = target.strip_prefix("/dev").ok()?.to_str()?.to_string();
+                    nodes.iter().find(|n| **n == file).map(String::as_str)
+                })
+                .collect();
+            open.sort();
+            open.dedup();
+            if !open.is_empty() {
+                let comm = fs::read_to_string(proc.path().join("comm")).unwrap_or_default();
+                let devices: Vec<String> = open.iter().map(|n| format!("/dev/{}", n)).collect();
+                users.push(format!("process {} ({}) has {} open", pid, comm.trim(), devices.join(", ")));
+            }
+        }
+    }
+
+    // Something can still hold the drive without showing up above, e.g. another
+    // program's exclusive open; the kernel refuses a second exclusive open then
+    if users.is_empty() && exclusive_open_refused(&dev_path) {
+        users.push("another program holds it exclusively".to_string());
+    }
+    users
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn users(_root: &Path, _dev: &str) -> Vec<String> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+fn exclusive_open_refused(dev: &Path) -> bool {
+    use std::os::unix::fs::OpenOptionsExt;
+    match std::fs::OpenOptions::new().read(true).custom_flags(libc::O_EXCL).open(dev) {
+        Ok(_) => false,
+        Err(e) => e.raw_os_error() == Some(libc::EBUSY),
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+
+    /// A fake root with /dev/sdb, its partitions sdb1 and sdb2, and their sysfs entries;
+    /// sda1 is there too, so its users can be told apart
+    fn tree(test: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("tetcher-busy-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["dev", "proc/self", "sys/class/block"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("proc/self/mountinfo"), "").unwrap();
+        fs::write(root.join("proc/swaps"), "Filename\tType\tSize\tUsed\tPriority\n").unwrap();
+
+        let class = root.join("sys/class/block");
+        let write = |path: PathBuf, contents: &str| {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+        for (name, majmin) in [("sda", "8:0"), ("sdb", "8:16")] {
+            fs::write(root.join("dev").join(name), "").unwrap();
+            write(class.join(name).join("dev"), &format!("{}\n", majmin));
+        }
+        for (disk, part, majmin) in [("sda", "sda1", "8:1"), ("sdb", "sdb1", "8:17"), ("sdb", "sdb2", "8:18")] {
+            fs::write(root.join("dev").join(part), "").unwrap();
+            write(class.join(disk).join(part).join("dev"), &format!("{}\n", majmin));
+            write(class.join(disk).join(part).join("partition"), "1\n");
+            // The kernel links every block device into /sys/class/block
+            symlink(format!("{}/{}", disk, part), class.join(part)).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn finds_mounts_swap_holders_and_open_files() {
+        let root = tree("users");
+        fs::write(
+            root.join("proc/self/mountinfo"),
+            "22 1 8:1 / / rw,relatime - ext4 /dev/sda1 rw\n\
+             36 22 8:17 / /media/user/My\\040Stick rw,nosuid - vfat /dev/sdb1 rw\n\
+             40 22 0:45 / /mnt/data rw - btrfs /dev/sdb2 rw\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("proc/swaps"),
+            "Filename\tType\tSize\tUsed\tPriority\n/dev/sdb2 partition\t1048572\t0\t-2\n/dev/sda1 partition\t1\t0\t-3\n",
+        )
+        .unwrap();
+
+        fs::create_dir_all(root.join("sys/class/block/sdb/sdb2/holders/dm-0")).unwrap();
+        fs::create_dir_all(root.join("sys/class/block/dm-0/dm")).unwrap();
+        fs::write(root.join("sys/class/block/dm-0/dm/name"), "vg-data\n").unwrap();
+
+        for (pid, comm, targets) in [
+            ("1234", "udisksd", &["/dev/sdb1", "/dev/sdb", "/dev/sdb1"][..]),
+            ("2345", "bash", &["/dev/null", "/dev/sda1", "/dev/sdb10"][..]),
+        ] {
+            let proc = root.join("proc").join(pid);
+            fs::create_dir_all(proc.join("fd")).unwrap();
+            fs::write(proc.join("comm"), format!("{}\n", comm)).unwrap();
+            for (fd, target) in targets.iter().enumerate() {
+                symlink(target, proc.join("fd").join(fd.to_string())).unwrap();
+            }
+        }
+
+        assert_eq!(
+            users(&root, "/dev/sdb"),
+            [
+                "sdb1 is mounted at /media/user/My Stick",
+                "sdb2 is mounted at /mnt/data",
+                "sdb2 is in use as swap",
+                "sdb2 is held by dm-0 (vg-data)",
+                "process 1234 (udisksd) has /dev/sdb, /dev/sdb1 open",
+            ]
+        );
+        let lines = lines(&root, "/dev/sdb");
+        assert!(lines[0].contains("/dev/sdb is in use"), "{}", lines[0]);
+        assert_eq!(lines[1], "  sdb1 is mounted at /media/user/My Stick");
+
+        // The same tables say nothing about a drive none of it is on
+        assert_eq!(users(&root, "/dev/sda"), ["sda1 is mounted at /", "sda1 is in use as swap", "process 2345 (bash) has /dev/sda1 open"]);
+    }
+
+    #[test]
+    fn reports_nothing_for_a_free_or_missing_drive() {
+        let root = tree("free");
+        assert_eq!(users(&root, "/dev/sdb"), Vec::<String>::new());
+        assert_eq!(lines(&root, "/dev/sdb"), Vec::<String>::new());
+        assert_eq!(users(&root, "/dev/sdz"), Vec::<String>::new());
+    }
+}
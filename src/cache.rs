@@ -0,0 +1,369 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde_json::{Value, json};
+
+use crate::digest::{self, Algo, Digest};
+
+/// Identity of an image file: if any of these change, its cached digests are stale
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stamp {
+    path: String,
+    size: u64,
+    mtime_ns: u128,
+    inode: u64,
+}
+
+impl Stamp {
+    fn of(image: &Path) -> Option<Stamp> {
+        let path = fs::canonicalize(image).ok()?;
+        let meta = fs::metadata(&path).ok()?;
+        let mtime_ns = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(&meta);
+        #[cfg(not(unix))]
+        let inode = 0;
+
+        Some(Stamp { path: path.display().to_string(), size: meta.len(), mtime_ns, inode })
+    }
+
+    fn from_json(value: &Value) -> Option<Stamp> {
+        Some(Stamp {
+            path: value.get("path")?.as_str()?.to_string(),
+            size: value.get("size")?.as_u64()?,
+            mtime_ns: value.get("mtime_ns")?.as_str()?.parse().ok()?,
+            inode: value.get("inode")?.as_u64()?,
+        })
+    }
+}
+
+/// Per-user cache directory: $XDG_CACHE_HOME/tetcher/digests or ~/.cache/tetcher/digests.
+/// Under sudo this is the invoking user's ~/.cache/tetcher/digests, not root's.
+pub fn dir() -> Option<PathBuf> {
+    dir_for(sudo_user().as_ref(), std::env::var_os("XDG_CACHE_HOME"), std::env::var_os("HOME"))
+}
+
+fn dir_for(sudo: Option<&SudoUser>, xdg_cache_home: Option<OsString>, home: Option<OsString>) -> Option<PathBuf> {
+    if let Some(user) = sudo {
+        return Some(user.home.join(".cache").join("tetcher").join("digests"));
+    }
+    let base = xdg_cache_home
+        .map(PathBuf::from)
+        .or_else(|| home.map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("tetcher").join("digests"))
+}
+
+/// The user who ran tEtcher2 through sudo, whose cache should be used and who should own it
+struct SudoUser {
+    home: PathBuf,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    uid: u32,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    gid: u32,
+}
+
+#[cfg(unix)]
+fn sudo_user() -> Option<SudoUser> {
+    use std::ffi::{CStr, CString, OsStr};
+    use std::os::unix::ffi::OsStrExt;
+
+    if unsafe { libc::geteuid() } != 0 {
+        return None;
+    }
+    let name = CString::new(std::env::var("SUDO_USER").ok()?).ok()?;
+
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    let rc = unsafe { libc::getpwnam_r(name.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if rc != 0 || found.is_null() || pwd.pw_uid == 0 {
+        return None;
+    }
+
+    let home = unsafe { CStr::from_ptr(pwd.pw_dir) };
+    Some(SudoUser {
+        home: PathBuf::from(OsStr::from_bytes(home.to_bytes())),
+        uid: pwd.pw_uid,
+        gid: pwd.pw_gid,
+    })
+}
+
+#[cfg(not(unix))]
+fn sudo_user() -> Option<SudoUser> {
+    None
+}
+
+/// Hand `paths` to the sudo user, so root does not leave files they cannot change in their home
+#[cfg(unix)]
+fn give_to(owner: Option<&SudoUser>, paths: &[PathBuf]) {
+    if let Some(user) = owner {
+        for path in paths {
+            let _ = std::os::unix::fs::lchown(path, Some(user.uid), Some(user.gid));
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn give_to(_owner: Option<&SudoUser>, _paths: &[PathBuf]) {}
+
+/// Sidecar file in `dir` holding the digests of the image at `path`
+fn entry_file(dir: &Path, path: &str) -> Option<PathBuf> {
+    let mut name = Digest::new(Algo::Sha256).ok()?;
+    name.update(path.as_bytes());
+    let name = digest::to_hex(&name.finish().ok()?);
+    Some(dir.join(format!("{}.json", &name[..32])))
+}
+
+fn read_entry(file: &Path) -> Option<Value> {
+    serde_json::from_str(&fs::read_to_string(file).ok()?).ok()
+}
+
+/// Cached `algo` digest of `image`, if the image has not changed since it was stored
+pub fn lookup(image: &Path, algo: Algo) -> Option<Vec<u8>> {
+    lookup_in(&dir()?, image, algo)
+}
+
+fn lookup_in(dir: &Path, image: &Path, algo: Algo) -> Option<Vec<u8>> {
+    let stamp = Stamp::of(image)?;
+    let file = entry_file(dir, &stamp.path)?;
+    let entry = read_entry(&file)?;
+
+    if Stamp::from_json(&entry).as_ref() != Some(&stamp) {
+        // The image was modified or replaced: forget what we knew about it
+        let _ = fs::remove_file(&file);
+        return None;
+    }
+
+    let hex = entry.get("digests")?.get(algo.to_string())?.as_str()?;
+    from_hex(hex)
+}
+
+/// Remember the `algo` digest of `image`. Failures are ignored: the cache is only an optimisation
+pub fn store(image: &Path, algo: Algo, hash: &[u8]) {
+    if let Some(dir) = dir() {
+        store_in(&dir, sudo_user().as_ref(), image, algo, hash);
+    }
+}
+
+/// Store into `dir`, handing anything created to `owner`
+fn store_in(dir: &Path, owner: Option<&SudoUser>, image: &Path, algo: Algo, hash: &[u8]) {
+    let Some(stamp) = Stamp::of(image) else { return };
+    let Some(file) = entry_file(dir, &stamp.path) else { return };
+
+    // Keep digests of other algorithms if the image is unchanged
+    let mut digests = read_entry(&file)
+        .filter(|entry| Stamp::from_json(entry).as_ref() == Some(&stamp))
+        .and_then(|entry| entry.get("digests").and_then(Value::as_object).cloned())
+        .unwrap_or_default();
+    digests.insert(algo.to_string(), Value::String(digest::to_hex(hash)));
+
+    let entry = json!({
+        "path": stamp.path,
+        "size": stamp.size,
+        // Nanoseconds do not fit in a JSON-safe integer, so store them as a string
+        "mtime_ns": stamp.mtime_ns.to_string(),
+        "inode": stamp.inode,
+        "digests": digests,
+    });
+
+    // Note the directories that get created, so their ownership can be fixed along with the file
+    let mut created: Vec<PathBuf> =
+        file.ancestors().skip(1).take_while(|dir| !dir.exists()).map(Path::to_path_buf).collect();
+    if let Some(parent) = file.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    if let Ok(text) = serde_json::to_string_pretty(&entry)
+        && fs::write(&file, text + "\n").is_ok()
+    {
+        created.push(file);
+    }
+    give_to(owner, &created);
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn entries() -> io::Result<Vec<PathBuf>> {
+    let Some(dir) = dir() else { return Ok(Vec::new()) };
+    let read = match fs::read_dir(&dir) {
+        Ok(read) => read,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut files: Vec<PathBuf> = read
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// `tEtcher2 cache show`: list cached digests and whether they are still valid
+pub fn show() -> io::Result<()> {
+    let files = entries()?;
+    if let Some(dir) = dir() {
+        println!("Digest cache: {}", dir.display());
+    }
+    if files.is_empty() {
+        println!("The cache is empty");
+        return Ok(());
+    }
+
+    for file in files {
+        let Some(entry) = read_entry(&file) else {
+            println!("{} (unreadable)", file.display());
+            continue;
+        };
+        let Some(stamp) = Stamp::from_json(&entry) else { continue };
+
+        let state = match Stamp::of(Path::new(&stamp.path)) {
+            Some(now) if now == stamp => "valid",
+            Some(_) => "stale (image changed)",
+            None => "stale (image missing)",
+        };
+        println!("{} [{} bytes, {}]", stamp.path, stamp.size, state);
+
+        if let Some(digests) = entry.get("digests").and_then(Value::as_object) {
+            for (algo, hex) in digests {
+                println!("  {:8} {}", algo, hex.as_str().unwrap_or(""));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `tEtcher2 cache clear`: delete every cached digest
+pub fn clear() -> io::Result<()> {
+    let files = entries()?;
+    for file in &files {
+        fs::remove_file(file)?;
+    }
+    println!("Removed {} cached digest file(s)", files.len());
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// An empty scratch directory holding an image and a cache directory below it
+    fn scratch(test: &str) -> (PathBuf, PathBuf) {
+        let root = std::env::temp_dir().join(format!("tetcher-cache-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let image = root.join("image.iso");
+        fs::write(&image, b"first image").unwrap();
+        (root, image)
+    }
+
+    #[test]
+    fn keys_entries_by_the_canonical_image_path() {
+        let (root, image) = scratch("key");
+        let stamp = Stamp::of(&image).unwrap();
+        assert_eq!(stamp.size, 11);
+
+        // Every way of naming the image leads to the same entry
+        std::os::unix::fs::symlink(&image, root.join("link.iso")).unwrap();
+        let other = root.join(".").join("link.iso");
+        assert_eq!(Stamp::of(&other), Some(stamp.clone()));
+
+        let cache = root.join("cache");
+        let file = entry_file(&cache, &stamp.path).unwrap();
+        assert_eq!(file.parent(), Some(cache.as_path()));
+        assert_eq!(file.file_name().unwrap().len(), 32 + ".json".len());
+        assert_ne!(entry_file(&cache, "/elsewhere/image.iso"), Some(file));
+
+        // The stored stamp reads back unchanged, nanoseconds included
+        store_in(&cache, None, &other, Algo::Sha256, &[0xAB; 32]);
+        let entry = read_entry(&entry_file(&cache, &stamp.path).unwrap()).unwrap();
+        assert_eq!(Stamp::from_json(&entry), Some(stamp));
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn forgets_digests_of_changed_images() {
+        let (root, image) = scratch("lookup");
+        let cache = root.join("cache");
+        assert_eq!(lookup_in(&cache, &image, Algo::Sha256), None);
+
+        store_in(&cache, None, &image, Algo::Sha256, &[1; 32]);
+        store_in(&cache, None, &image, Algo::Md5, &[2; 16]);
+        assert_eq!(lookup_in(&cache, &image, Algo::Sha256), Some(vec![1; 32]));
+        assert_eq!(lookup_in(&cache, &image, Algo::Md5), Some(vec![2; 16]));
+        assert_eq!(lookup_in(&cache, &image, Algo::Sha512), None);
+
+        // Replacing the file gives a new inode, even with the same size and contents
+        let file = entry_file(&cache, &Stamp::of(&image).unwrap().path).unwrap();
+        let replacement = root.join("replacement.iso");
+        fs::write(&replacement, b"first image").unwrap();
+        fs::rename(&replacement, &image).unwrap();
+        assert_eq!(lookup_in(&cache, &image, Algo::Sha256), None);
+        assert!(!file.exists(), "stale entries are removed");
+
+        // So does rewriting it in place
+        store_in(&cache, None, &image, Algo::Sha256, &[1; 32]);
+        fs::write(&image, b"second image").unwrap();
+        assert_eq!(lookup_in(&cache, &image, Algo::Sha256), None);
+
+        // Storing over a stale entry does not keep the digests of other algorithms
+        store_in(&cache, None, &image, Algo::Sha256, &[4; 32]);
+        fs::write(&image, b"third image").unwrap();
+        store_in(&cache, None, &image, Algo::Md5, &[3; 16]);
+        assert_eq!(lookup_in(&cache, &image, Algo::Md5), Some(vec![3; 16]));
+        assert_eq!(lookup_in(&cache, &image, Algo::Sha256), None);
+
+        fs::remove_file(&image).unwrap();
+        assert_eq!(lookup_in(&cache, &image, Algo::Md5), None);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn uses_and_hands_files_to_the_sudo_user() {
+        use std::os::unix::fs::MetadataExt;
+
+        let user = |home: &str, uid, gid| SudoUser { home: PathBuf::from(home), uid, gid };
+        let alice = user("/home/alice", 1000, 1000);
+        assert_eq!(
+            dir_for(Some(&alice), Some("/root/.xdg".into()), Some("/root".into())),
+            Some(PathBuf::from("/home/alice/.cache/tetcher/digests"))
+        );
+        assert_eq!(
+            dir_for(None, Some("/home/bob/.xdg".into()), Some("/home/bob".into())),
+            Some(PathBuf::from("/home/bob/.xdg/tetcher/digests"))
+        );
+        assert_eq!(dir_for(None, None, Some("/home/bob".into())), Some(PathBuf::from("/home/bob/.cache/tetcher/digests")));
+        assert_eq!(dir_for(None, None, None), None);
+
+        // Only root can give files away; anyone else can only hand them to themselves
+        let (root, image) = scratch("owner");
+        let (uid, gid) = match unsafe { libc::geteuid() } {
+            0 => (65534, 65534),
+            _ => unsafe { (libc::getuid(), libc::getgid()) },
+        };
+        let existing = root.join("home");
+        fs::create_dir(&existing).unwrap();
+        let cache = existing.join(".cache/tetcher/digests");
+        store_in(&cache, Some(&user("/unused", uid, gid)), &image, Algo::Sha256, &[1; 32]);
+
+        let file = entry_file(&cache, &Stamp::of(&image).unwrap().path).unwrap();
+        for path in [file.as_path(), &cache, cache.parent().unwrap(), cache.parent().unwrap().parent().unwrap()] {
+            let meta = fs::metadata(path).unwrap();
+            assert_eq!((meta.uid(), meta.gid()), (uid, gid), "{} is not handed over", path.display());
+        }
+        // Directories that were already there are left alone
+        assert_eq!(fs::metadata(&existing).unwrap().uid(), unsafe { libc::geteuid() });
+        let _ = fs::remove_dir_all(root);
+    }
+}
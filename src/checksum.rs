@@ -0,0 +1,473 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use crossterm::{
+    cursor, execute,
+    terminal::{self, ClearType},
+};
+
+use crate::digest::{self, Algo};
+use crate::signature::{self, Verdict};
+use crate::{cache, verify};
+
+/// One line of a checksum file: the expected digest of a named file
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub algo: Algo,
+    pub digest: String,
+    /// File name as listed, or None for bare `<image>.sha256` style sidecars
+    pub name: Option<String>,
+}
+
+/// Outcome of checking an image against its published checksum
+#[derive(Debug, Clone)]
+pub enum Status {
+    Pass,
+    Fail { actual: String },
+    /// A matching entry was found but the image could not be hashed
+    Error(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub checksum_file: PathBuf,
+    /// Whether `checksum_file` itself could be authenticated
+    pub signature: Verdict,
+    pub expected: Entry,
+    pub status: Status,
+}
+
+impl CheckResult {
+    /// Short coloured badge for the confirmation screens
+    pub fn badge(&self) -> String {
+        use crossterm::style::{Color, Stylize};
+
+        let file = self
+            .checksum_file
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        match &self.status {
+            Status::Pass => format!(
+                "{} {} matches {}",
+                "[ PASS ]".with(Color::Green).bold(),
+                self.expected.algo,
+                file
+            ),
+            Status::Fail { .. } => format!(
+                "{} {} does NOT match {} - the download may be corrupt",
+                "[ FAIL ]".with(Color::Red).bold(),
+                self.expected.algo,
+                file
+            ),
+            Status::Error(why) => format!(
+                "{} could not check against {}: {}",
+                "[ ERROR ]".with(Color::Yellow).bold(),
+                file,
+                why
+            ),
+        }
+    }
+
+    /// Badge plus, on a mismatch, the expected and actual digests
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![self.badge()];
+        if let Status::Fail { actual } = &self.status {
+            lines.push(format!("  expected: {}", self.expected.digest));
+            lines.push(format!("  actual:   {}", actual));
+        }
+        lines
+    }
+}
+
+/// Badge shown when no checksum file lists the image
+pub fn missing_badge() -> String {
+    use crossterm::style::{Color, Stylize};
+    format!("{} no published checksum found next to the image", "[ NONE ]".with(Color::Yellow).bold())
+}
+
+/// Parse the contents of a checksum file
+///
+/// Understands GNU coreutils (`<hex>  name` / `<hex> *name`), BSD tagged
+/// (`SHA256 (name) = <hex>`), Fedora CHECKSUM (BSD tagged inside a PGP clearsign wrapper)
+/// and Gentoo DIGESTS (`# SHA512 HASH` headings followed by GNU lines)
+/// `default_algo` comes from the checksum file's name, e.g. SHA256SUMS or image.iso.sha512
+///
+/// In a clearsigned file only the signed text counts: lines before the message or after
+/// its signature are not covered by it, so they could have been added by anyone.
+pub fn parse(text: &str, default_algo: Option<Algo>) -> Vec<Entry> {
+    let signed;
+    let text = if text.lines().any(|l| l.trim_end() == CLEARSIGN_BEGIN) {
+        signed = clearsigned_text(text).unwrap_or_default();
+        &signed
+    } else {
+        text
+    };
+
+    let mut entries = Vec::new();
+    let mut heading_algo: Option<Algo> = None;
+
+    for raw in text.lines() {
+        let line = raw.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        // Gentoo DIGESTS headings: "# SHA512 HASH", "# BLAKE2B HASH"
+        if let Some(comment) = line.strip_prefix('#') {
+            let words: Vec<&str> = comment.split_whitespace().collect();
+            if words.len() == 2 && words[1].eq_ignore_ascii_case("HASH") {
+                heading_algo = Algo::from_tag(words[0]);
+            }
+            continue;
+        }
+
+        if let Some(entry) = parse_bsd_line(line).or_else(|| parse_gnu_line(line, heading_algo.or(default_algo))) {
+            entries.push(entry);
+        }
+    }
+
+    entries
+}
+
+const CLEARSIGN_BEGIN: &str = "-----BEGIN PGP SIGNED MESSAGE-----";
+const SIGNATURE_BEGIN: &str = "-----BEGIN PGP SIGNATURE-----";
+
+/// The text a PGP clearsigned message signs, with dash-escaping undone (RFC 4880 7.1)
+///
+/// None when the message is cut short before its signature.
+fn clearsigned_text(text: &str) -> Option<String> {
+    let mut lines = text.lines().map(|l| l.trim_end_matches('\r'));
+    lines.find(|l| l.trim_end() == CLEARSIGN_BEGIN)?;
+    // Armor headers ("Hash: SHA256") end at the first blank line
+    lines.find(|l| l.trim().is_empty())?;
+
+    let mut signed = String::new();
+    for line in lines {
+        if line.trim_end() == SIGNATURE_BEGIN {
+            return Some(signed);
+        }
+        signed.push_str(line.strip_prefix("- ").unwrap_or(line));
+        signed.push('\n');
+    }
+    None
+}
+
+/// `SHA256 (name) = <hex>`
+fn parse_bsd_line(line: &str) -> Option<Entry> {
+    let (tag, rest) = line.split_once(" (")?;
+    let (name, digest) = rest.rsplit_once(") = ")?;
+    let algo = Algo::from_tag(tag.trim())?;
+    let digest = digest.trim().to_ascii_lowercase();
+    if !is_hex(&digest) {
+        return None;
+    }
+    Some(Entry { algo, digest, name: Some(name.to_string()) })
+}
+
+/// `<hex>  name`, `<hex> *name`, or a bare `<hex>`
+fn parse_gnu_line(line: &str, hint: Option<Algo>) -> Option<Entry> {
+    // A leading backslash means the file name contains escaped characters
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+
+    let (digest, name) = match line.split_once(char::is_whitespace) {
+        Some((digest, name)) => (digest, Some(name.trim_start())),
+        None => (line, None),
+    };
+
+    let digest = digest.to_ascii_lowercase();
+    if !is_hex(&digest) {
+        return None;
+    }
+
+    // Trust the hint only when the length agrees with it (DIGESTS files mix algorithms)
+    let algo = match hint {
+        Some(algo) if algo.hex_len() == digest.len() => algo,
+        _ => Algo::from_hex_len(digest.len())?,
+    };
+
+    let name = name.filter(|n| !n.is_empty()).map(|n| {
+        let n = n.strip_prefix('*').unwrap_or(n);
+        if escaped {
+            n.replace("\\n", "\n").replace("\\\\", "\\")
+        } else {
+            n.to_string()
+        }
+    });
+
+    Some(Entry { algo, digest, name })
+}
+
+fn is_hex(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Is this directory entry name something that looks like a checksum file?
+fn is_checksum_file_name(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    if lower.ends_with(".asc") || lower.ends_with(".sig") || lower.ends_with(".gpg") || lower.ends_with(".minisig") {
+        return false;
+    }
+    lower.contains("sums")
+        || lower.contains("checksum")
+        || lower.ends_with(".digests")
+        || lower.ends_with("sum.txt")
+        || algo_from_file_name(&lower).is_some()
+}
+
+/// Algorithm implied by a checksum file's name, if any
+fn algo_from_file_name(name: &str) -> Option<Algo> {
+    let lower = name.to_ascii_lowercase();
+    if let Some((_, ext)) = lower.rsplit_once('.')
+        && let Some(algo) = Algo::from_tag(ext.trim_end_matches("sum"))
+    {
+        return Some(algo);
+    }
+    ["sha512", "sha384", "sha256", "sha224", "sha1", "md5", "blake2b"]
+        .iter()
+        .find(|tag| lower.starts_with(*tag))
+        .and_then(|tag| Algo::from_tag(tag))
+}
+
+/// Does `entry` describe the image at `image_name`, given the checksum file it came from?
+fn entry_matches(entry: &Entry, image_name: &str, checksum_name: &str) -> bool {
+    match &entry.name {
+        Some(name) => {
+            let listed = name.trim_start_matches("./");
+            let listed = Path::new(listed).file_name().map(|n| n.to_string_lossy().to_string());
+            listed.as_deref() == Some(image_name)
+        }
+        // A bare digest is only meaningful in an `<image>.<ext>` sidecar
+        None => checksum_name
+            .rsplit_once('.')
+            .is_some_and(|(stem, _)| stem == image_name),
+    }
+}
+
+/// Look through the image's directory for checksum files listing it
+///
+/// Returns every matching (checksum file, entry) pair, strongest algorithm first
+pub fn find(image: &Path) -> Vec<(PathBuf, Entry)> {
+    let Some(dir) = image.parent() else { return Vec::new() };
+    let Some(image_name) = image.file_name().map(|n| n.to_string_lossy().to_string()) else {
+        return Vec::new();
+    };
+
+    let mut found = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else { return found };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        if name == image_name || !path.is_file() || !is_checksum_file_name(&name) {
+            continue;
+        }
+
+        // Checksum files are small; anything large is not one
+        if entry.metadata().map(|m| m.len() > 1024 * 1024).unwrap_or(true) {
+            continue;
+        }
+
+        let Ok(text) = fs::read_to_string(&path) else { continue };
+        for e in parse(&text, algo_from_file_name(&name)) {
+            if entry_matches(&e, &image_name, &name) {
+                found.push((path.clone(), e));
+            }
+        }
+    }
+
+    found.sort_by(|a, b| b.1.algo.cmp(&a.1.algo).then_with(|| a.0.cmp(&b.0)));
+    found
+}
+
+/// Hash `path` with `algo`, redrawing a progress line on `row` as it goes
+///
+/// Like verification, the hashing runs on a worker thread while this one draws, and the
+/// screen is addressed by row since the file browser leaves the terminal in raw mode.
+///
+/// The image is always read: a published checksum is only worth checking against the bytes
+/// actually on disk, not a digest remembered from an earlier run. The result is still cached
+/// for the flash and verify steps.
+pub fn hash_file(path: &Path, algo: Algo, row: u16) -> Result<String, String> {
+    let size = fs::metadata(path).map_err(|e| e.to_string())?.len();
+    let done = AtomicU64::new(0);
+
+    let hash = thread::scope(|s| {
+        let worker = s.spawn(|| digest::hash_file(path, algo, size, |n| done.store(n, Ordering::Relaxed)));
+        loop {
+            let finished = worker.is_finished();
+            let percent = done.load(Ordering::Relaxed) as f64 / size.max(1) as f64 * 100.0;
+            status_line(row, &format!("Progress: {:6.2}%", percent));
+            if finished {
+                break;
+            }
+            thread::sleep(verify::PROGRESS_INTERVAL);
+        }
+        worker.join().unwrap()
+    });
+
+    let hash = hash.map_err(|e| e.to_string())?;
+    cache::store(path, algo, &hash);
+    Ok(digest::to_hex(&hash))
+}
+
+/// Replace line `row` of the screen with `text`
+fn status_line(row: u16, text: &str) {
+    let mut stdout = io::stdout();
+    let _ = execute!(stdout, cursor::MoveTo(0, row), terminal::Clear(ClearType::CurrentLine));
+    print!("{}", text);
+    let _ = stdout.flush();
+}
+
+/// Pick the entry to check against from `found` (strongest algorithm first)
+///
+/// Files with a valid signature win over everything else, whatever their algorithm: a stray
+/// unsigned `image.iso.sha512` must not outrank a signed SHA256SUMS. Next come files whose
+/// signature is bad, so tampering is reported rather than hidden behind an unsigned sidecar,
+/// and only then unsigned files. Each file is authenticated once.
+fn choose(found: Vec<(PathBuf, Entry)>, mut authenticate: impl FnMut(&Path) -> Verdict) -> Option<(PathBuf, Entry, Verdict)> {
+    let mut verdicts: Vec<(PathBuf, Verdict)> = Vec::new();
+    let mut best: Option<(u8, PathBuf, Entry, Verdict)> = None;
+    for (file, entry) in found {
+        let verdict = match verdicts.iter().find(|(f, _)| *f == file) {
+            Some((_, verdict)) => verdict.clone(),
+            None => {
+                let verdict = authenticate(&file);
+                verdicts.push((file.clone(), verdict.clone()));
+                verdict
+            }
+        };
+        let rank = match verdict {
+            Verdict::Verified { .. } => 0,
+            Verdict::Bad { .. } => 1,
+            Verdict::Unverified(_) => 2,
+        };
+        if best.as_ref().is_none_or(|(best, ..)| rank < *best) {
+            best = Some((rank, file, entry, verdict));
+        }
+    }
+    best.map(|(_, file, entry, verdict)| (file, entry, verdict))
+}
+
+/// Find a published checksum for `image`, preferring signed checksum files, and verify the
+/// image against it
+///
+/// Returns None when no checksum file next to the image lists it
+pub fn check(image: &Path) -> Option<CheckResult> {
+    let (checksum_file, expected, signature) = choose(find(image), signature::check)?;
+
+    status_line(
+        0,
+        &format!("Checking {} against {} ({})", image.display(), checksum_file.display(), expected.algo),
+    );
+
+    let status = match hash_file(image, expected.algo, 1) {
+        Ok(actual) if actual == expected.digest => Status::Pass,
+        Ok(actual) => Status::Fail { actual },
+        Err(why) => Status::Error(why),
+    };
+
+    Some(CheckResult { checksum_file, signature, expected, status })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIGNED: &str = "\
+-----BEGIN PGP SIGNED MESSAGE-----
+Hash: SHA256
+
+# Fedora-Workstation-Live-x86_64-40-1.14.iso: 2295853056 bytes
+SHA256 (Fedora-Workstation-Live-x86_64-40-1.14.iso) = dd1faca950d1a8c3d169adf2df4c3644ebb62f8aac04c401f2393e521395d613
+- -----not armour, dash-escaped
+-----BEGIN PGP SIGNATURE-----
+
+iQIzBAEBCAAdFiEE...
+-----END PGP SIGNATURE-----
+";
+
+    #[test]
+    fn reads_only_the_signed_part_of_a_clearsigned_file() {
+        let entries = parse(SIGNED, None);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].algo, Algo::Sha256);
+
+        // Lines added around the signed message are not covered by the signature
+        let tampered = format!(
+            "SHA512 (Fedora-Workstation-Live-x86_64-40-1.14.iso) = {}\n{}Hash: x\nSHA512 (Fedora-Workstation-Live-x86_64-40-1.14.iso) = {}\n",
+            "ab".repeat(64),
+            SIGNED,
+            "cd".repeat(64)
+        );
+        let entries = parse(&tampered, None);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].algo, Algo::Sha256);
+
+        // Without its signature nothing of it can be trusted
+        let truncated = &SIGNED[..SIGNED.find(SIGNATURE_BEGIN).unwrap()];
+        assert!(parse(truncated, None).is_empty());
+    }
+
+    #[test]
+    fn reads_unsigned_files_whole() {
+        let text = format!("{}  ubuntu-24.04-desktop-amd64.iso\n{} *ubuntu-24.04-live-server-amd64.iso\n", "ab".repeat(32), "cd".repeat(32));
+        let entries = parse(&text, Some(Algo::Sha256));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].name.as_deref(), Some("ubuntu-24.04-live-server-amd64.iso"));
+    }
+
+    #[test]
+    fn prefers_signed_checksum_files_over_stronger_unsigned_ones() {
+        let entry = |algo: Algo| Entry { algo, digest: String::new(), name: None };
+        let found = || {
+            vec![
+                (PathBuf::from("image.iso.sha512"), entry(Algo::Sha512)),
+                (PathBuf::from("SHA256SUMS"), entry(Algo::Sha256)),
+                (PathBuf::from("SHA256SUMS"), entry(Algo::Sha256)),
+                (PathBuf::from("MD5SUMS"), entry(Algo::Md5)),
+            ]
+        };
+        let signed = |file: &Path| Verdict::Verified {
+            signer: "distro".to_string(),
+            method: "OpenPGP",
+            signature: file.with_extension("gpg"),
+        };
+        let unsigned = |_: &Path| Verdict::Unverified("not signed".to_string());
+
+        let mut checked = Vec::new();
+        let (file, entry, verdict) = choose(found(), |file| {
+            checked.push(file.to_path_buf());
+            if file == Path::new("SHA256SUMS") { signed(file) } else { unsigned(file) }
+        })
+        .unwrap();
+        assert_eq!(file, Path::new("SHA256SUMS"));
+        assert_eq!(entry.algo, Algo::Sha256);
+        assert!(matches!(verdict, Verdict::Verified { .. }));
+        assert_eq!(checked.len(), 3, "each file is authenticated once");
+
+        // A bad signature is shown rather than an unsigned sidecar
+        let (file, _, verdict) = choose(found(), |file| {
+            if file == Path::new("MD5SUMS") {
+                Verdict::Bad { signature: file.with_extension("gpg"), reason: "bad signature".to_string() }
+            } else {
+                unsigned(file)
+            }
+        })
+        .unwrap();
+        assert_eq!(file, Path::new("MD5SUMS"));
+        assert!(matches!(verdict, Verdict::Bad { .. }));
+
+        // With nothing signed, the strongest algorithm wins as before
+        let (file, ..) = choose(found(), unsigned).unwrap();
+        assert_eq!(file, Path::new("image.iso.sha512"));
+        assert!(choose(Vec::new(), unsigned).is_none());
+    }
+}
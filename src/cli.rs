@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+use crate::mode::Mode;
+
+pub const USAGE: &str = "\
+Usage: tEtcher2 [COMMAND] [OPTIONS]
+
+Commands:
+  flash         Write an image to a drive, then optionally verify it
+  verify        Compare an already flashed drive against an image, without writing
+  cache show    List the cached image digests and whether they are still valid
+  cache clear   Delete all cached image digests
+
+Options:
+  --report <PATH>       Where to write the JSON run report
+                        (default: tetcher-report-<time>.json in the current directory)
+  --allow-system-disk   Also list drives the running system uses (root, boot, swap,
+                        LVM/RAID members); these are hidden by default
+
+With no command, tEtcher asks what you would like to do.";
+
+/// `cache` subcommands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheCommand {
+    Show,
+    Clear,
+}
+
+/// Options parsed from the command line
+#[derive(Debug, Default)]
+pub struct Args {
+    /// Operation to run, or None to ask in a menu
+    pub mode: Option<Mode>,
+    /// Where to write the JSON run report
+    pub report: Option<PathBuf>,
+    /// Digest cache maintenance instead of a flash or verify run
+    pub cache: Option<CacheCommand>,
+    /// Offer drives that back the running system as targets
+    pub allow_system_disk: bool,
+}
+
+/// Parse the command line, returning an error message for anything unrecognised
+pub fn parse() -> Result<Args, String> {
+    let mut args = Args::default();
+
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "flash" if args.mode.is_none() => args.mode = Some(Mode::Flash),
+            "verify" if args.mode.is_none() => args.mode = Some(Mode::Verify),
+            "cache" if args.mode.is_none() && args.cache.is_none() => {
+                args.cache = Some(match argv.next().as_deref() {
+                    Some("show") => CacheCommand::Show,
+                    Some("clear") => CacheCommand::Clear,
+                    _ => return Err("cache needs 'show' or 'clear'".to_string()),
+                });
+            }
+            "--report" => {
+                let path = argv.next().ok_or("--report needs a path")?;
+                args.report = Some(PathBuf::from(path));
+            }
+            "--allow-system-disk" => args.allow_system_disk = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            other => return Err(format!("unexpected argument '{}'", other)),
+        }
+    }
+
+    Ok(args)
+}
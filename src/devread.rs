@@ -0,0 +1,178 @@
+use std::alloc::{self, Layout};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Alignment required for O_DIRECT buffers, offsets and lengths
+const ALIGNMENT: usize = 4096;
+/// Size of each read from the device
+const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// How the device is being read back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadMethod {
+    /// O_DIRECT (Linux) or F_NOCACHE (macOS): every read goes to the media
+    Direct,
+    /// Cached pages were dropped first (BLKFLSBUF + POSIX_FADV_DONTNEED)
+    CacheDropped,
+    /// Plain buffered reads: data may come from the page cache
+    Buffered,
+}
+
+impl std::fmt::Display for ReadMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            ReadMethod::Direct if cfg!(target_os = "macos") => "F_NOCACHE (bypassing the buffer cache)",
+            ReadMethod::Direct => "O_DIRECT (bypassing the page cache)",
+            ReadMethod::CacheDropped => "buffered, after dropping the device's cached pages",
+            ReadMethod::Buffered => "buffered (data may have come from the page cache)",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// Heap buffer aligned for O_DIRECT
+struct AlignedBuf {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl AlignedBuf {
+    fn new(size: usize) -> AlignedBuf {
+        let layout = Layout::from_size_align(size, ALIGNMENT).unwrap();
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        AlignedBuf { ptr, layout }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.layout.size()) }
+    }
+}
+
+// The buffer is uniquely owned, like a Vec<u8>
+unsafe impl Send for AlignedBuf {}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr, self.layout) }
+    }
+}
+
+/// Reads a device while keeping the page cache out of the way, so verification
+/// checks what is on the media rather than what was just written to memory
+pub struct DeviceReader {
+    file: File,
+    method: ReadMethod,
+    buf: AlignedBuf,
+    pos: usize,
+    len: usize,
+}
+
+impl DeviceReader {
+    pub fn method(&self) -> ReadMethod {
+        self.method
+    }
+
+    /// Continue reading from `offset`, which must be a multiple of 4096
+    pub fn seek(&mut self, offset: u64) -> io::Result<()> {
+        self.pos = 0;
+        self.len = 0;
+        self.file.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    /// Read up to `out.len()` bytes at `offset` with a single read, so an unreadable area
+    /// fails only the block it is in. `offset` must be a multiple of 4096.
+    pub fn read_at(&mut self, offset: u64, out: &mut [u8]) -> io::Result<usize> {
+        self.seek(offset)?;
+        let want = out.len().min(CHUNK_SIZE).next_multiple_of(ALIGNMENT);
+        let n = self.file.read(&mut self.buf.as_mut_slice()[..want])?.min(out.len());
+        out[..n].copy_from_slice(&self.buf.as_slice()[..n]);
+        Ok(n)
+    }
+}
+
+/// Open `path` for uncached reading, falling back as far as plain buffered reads
+pub fn open(path: &Path) -> io::Result<DeviceReader> {
+    let buf = AlignedBuf::new(CHUNK_SIZE);
+
+    if let Some(file) = open_direct(path) {
+        return Ok(DeviceReader { file, method: ReadMethod::Direct, buf, pos: 0, len: 0 });
+    }
+
+    let file = File::open(path)?;
+    let method = if drop_cache(&file) { ReadMethod::CacheDropped } else { ReadMethod::Buffered };
+    Ok(DeviceReader { file, method, buf, pos: 0, len: 0 })
+}
+
+/// Open with O_DIRECT and probe one aligned read, since some filesystems
+/// accept the flag at open time but reject the reads
+#[cfg(target_os = "linux")]
+fn open_direct(path: &Path) -> Option<File> {
+    use std::os::unix::fs::{FileExt, OpenOptionsExt};
+
+    let file = OpenOptions::new().read(true).custom_flags(libc::O_DIRECT).open(path).ok()?;
+    let mut probe = AlignedBuf::new(ALIGNMENT);
+    file.read_at(probe.as_mut_slice(), 0).ok()?;
+    Some(file)
+}
+
+#[cfg(target_os = "macos")]
+fn open_direct(path: &Path) -> Option<File> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = OpenOptions::new().read(true).open(path).ok()?;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1) } != 0 {
+        return None;
+    }
+    Some(file)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn open_direct(_path: &Path) -> Option<File> {
+    None
+}
+
+/// Flush the block device's buffers and drop its cached pages
+#[cfg(target_os = "linux")]
+fn drop_cache(file: &File) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    // _IO(0x12, 97): flush buffer cache (block devices only, needs root)
+    const BLKFLSBUF: libc::c_ulong = 0x1261;
+
+    let fd = file.as_raw_fd();
+    let flushed = unsafe { libc::ioctl(fd, BLKFLSBUF as _, 0) } == 0;
+    let advised = unsafe { libc::posix_fadvise(fd, 0, 0, libc::POSIX_FADV_DONTNEED) } == 0;
+    flushed || advised
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_cache(_file: &File) -> bool {
+    false
+}
+
+impl Read for DeviceReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.len {
+            // Always read whole aligned chunks; only the final read may come back short
+            self.len = self.file.read(self.buf.as_mut_slice())?;
+            self.pos = 0;
+            if self.len == 0 {
+                return Ok(0);
+            }
+        }
+
+        let n = out.len().min(self.len - self.pos);
+        out[..n].copy_from_slice(&self.buf.as_slice()[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,243 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+#[cfg(not(any(feature = "openssl", feature = "rust-hash")))]
+compile_error!("enable the \"openssl\" or the \"rust-hash\" feature to choose a hashing backend");
+
+/// Size of each read when hashing a file or device
+const READ_SIZE: usize = 16 * 1024 * 1024;
+
+/// Digest algorithms that appear in vendor checksum files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Algo {
+    Md5,
+    Sha1,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+    Blake2b,
+}
+
+impl Algo {
+    /// Name of the algorithm as understood by OpenSSL's EVP_get_digestbyname
+    fn openssl_name(self) -> &'static str {
+        match self {
+            Algo::Md5 => "MD5",
+            Algo::Sha1 => "SHA1",
+            Algo::Sha224 => "SHA224",
+            Algo::Sha256 => "SHA256",
+            Algo::Sha384 => "SHA384",
+            Algo::Sha512 => "SHA512",
+            Algo::Blake2b => "BLAKE2b512",
+        }
+    }
+
+    /// Parse a tag such as "SHA256" (BSD format) or "sha512" (file extension)
+    pub fn from_tag(tag: &str) -> Option<Algo> {
+        match tag.to_ascii_lowercase().replace('-', "").as_str() {
+            "md5" => Some(Algo::Md5),
+            "sha1" => Some(Algo::Sha1),
+            "sha224" => Some(Algo::Sha224),
+            "sha256" => Some(Algo::Sha256),
+            "sha384" => Some(Algo::Sha384),
+            "sha512" => Some(Algo::Sha512),
+            "blake2b" | "blake2b512" => Some(Algo::Blake2b),
+            _ => None,
+        }
+    }
+
+    /// Guess the algorithm from the length of a hex digest
+    pub fn from_hex_len(len: usize) -> Option<Algo> {
+        match len {
+            32 => Some(Algo::Md5),
+            40 => Some(Algo::Sha1),
+            56 => Some(Algo::Sha224),
+            64 => Some(Algo::Sha256),
+            96 => Some(Algo::Sha384),
+            128 => Some(Algo::Sha512),
+            _ => None,
+        }
+    }
+
+    pub fn hex_len(self) -> usize {
+        match self {
+            Algo::Md5 => 32,
+            Algo::Sha1 => 40,
+            Algo::Sha224 => 56,
+            Algo::Sha256 => 64,
+            Algo::Sha384 => 96,
+            Algo::Sha512 | Algo::Blake2b => 128,
+        }
+    }
+}
+
+impl std::fmt::Display for Algo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Algo::Blake2b => "BLAKE2b",
+            other => other.openssl_name(),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A running digest, backed by OpenSSL or by pure Rust depending on the enabled features
+pub struct Digest {
+    ctx: backend::Context,
+}
+
+impl Digest {
+    pub fn new(algo: Algo) -> io::Result<Digest> {
+        Ok(Digest { ctx: backend::Context::new(algo)? })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.ctx.update(data);
+    }
+
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        self.ctx.finish()
+    }
+}
+
+/// OpenSSL's EVP API, through the incremental digest functions in verify.c
+#[cfg(not(feature = "rust-hash"))]
+mod backend {
+    use super::Algo;
+    use std::ffi::CString;
+    use std::io;
+    use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+    // Largest digest OpenSSL can produce (EVP_MAX_MD_SIZE)
+    const MAX_DIGEST_SIZE: usize = 64;
+
+    unsafe extern "C" {
+        fn digest_new(algo: *const c_char) -> *mut c_void;
+        fn digest_update(ctx: *mut c_void, buf: *const u8, len: usize) -> c_int;
+        fn digest_final(ctx: *mut c_void, hash: *mut u8, hash_len: *mut c_uint) -> c_int;
+        fn digest_free(ctx: *mut c_void);
+    }
+
+    pub struct Context {
+        ctx: *mut c_void,
+        /// An update failed, so the digest no longer covers all the data
+        failed: bool,
+    }
+
+    impl Context {
+        pub fn new(algo: Algo) -> io::Result<Context> {
+            let name = CString::new(algo.openssl_name()).unwrap();
+            let ctx = unsafe { digest_new(name.as_ptr()) };
+            if ctx.is_null() {
+                return Err(io::Error::other(format!("{} is not supported by OpenSSL", algo)));
+            }
+            Ok(Context { ctx, failed: false })
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            let ok = unsafe { digest_update(self.ctx, data.as_ptr(), data.len()) };
+            self.failed |= ok == 0;
+        }
+
+        pub fn finish(self) -> io::Result<Vec<u8>> {
+            let mut hash = [0u8; MAX_DIGEST_SIZE];
+            let mut hash_len: c_uint = 0;
+
+            // digest_final frees the context, so Drop must not free it again
+            let ctx = self.ctx;
+            let failed = self.failed;
+            std::mem::forget(self);
+
+            let ok = unsafe { digest_final(ctx, hash.as_mut_ptr(), &mut hash_len) };
+            if failed {
+                return Err(io::Error::other("OpenSSL failed to hash part of the data"));
+            }
+            if ok == 0 {
+                return Err(io::Error::other("failed to finish digest"));
+            }
+            Ok(hash[..hash_len as usize].to_vec())
+        }
+    }
+
+    impl Drop for Context {
+        fn drop(&mut self) {
+            unsafe { digest_free(self.ctx) }
+        }
+    }
+}
+
+/// Pure-Rust hashing from the RustCrypto crates
+#[cfg(feature = "rust-hash")]
+mod backend {
+    use super::Algo;
+    use sha2::digest::DynDigest;
+    use std::io;
+
+    pub struct Context {
+        hasher: Box<dyn DynDigest + Send>,
+    }
+
+    impl Context {
+        pub fn new(algo: Algo) -> io::Result<Context> {
+            let hasher: Box<dyn DynDigest + Send> = match algo {
+                Algo::Md5 => Box::new(md5::Md5::default()),
+                Algo::Sha1 => Box::new(sha1::Sha1::default()),
+                Algo::Sha224 => Box::new(sha2::Sha224::default()),
+                Algo::Sha256 => Box::new(sha2::Sha256::default()),
+                Algo::Sha384 => Box::new(sha2::Sha384::default()),
+                Algo::Sha512 => Box::new(sha2::Sha512::default()),
+                Algo::Blake2b => Box::new(blake2::Blake2b512::default()),
+            };
+            Ok(Context { hasher })
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            self.hasher.update(data);
+        }
+
+        pub fn finish(self) -> io::Result<Vec<u8>> {
+            Ok(self.hasher.finalize().to_vec())
+        }
+    }
+}
+
+/// Lowercase hex encoding of a digest
+pub fn to_hex(hash: &[u8]) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hash the first `max_bytes` of a file or device
+///
+/// `on_progress` is called with the number of bytes hashed so far after every read
+pub fn hash_file(path: &Path, algo: Algo, max_bytes: u64, on_progress: impl FnMut(u64)) -> io::Result<Vec<u8>> {
+    hash_reader(File::open(path)?, algo, max_bytes, on_progress)
+}
+
+/// Hash the first `max_bytes` read from `reader`
+pub fn hash_reader(
+    mut file: impl Read,
+    algo: Algo,
+    max_bytes: u64,
+    mut on_progress: impl FnMut(u64),
+) -> io::Result<Vec<u8>> {
+    let mut digest = Digest::new(algo)?;
+    let mut buf = vec![0u8; READ_SIZE];
+    let mut total: u64 = 0;
+
+    while total < max_bytes {
+        let want = READ_SIZE.min((max_bytes - total) as usize);
+        let read = match file.read(&mut buf[..want]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        digest.update(&buf[..read]);
+        total += read as u64;
+        on_progress(total);
+    }
+
+    digest.finish()
+}
@@ -0,0 +1,270 @@
+use std::fs::File;
+use std::io;
+
+use bzip2::read::BzDecoder;
+use flate2::read::ZlibDecoder;
+
+use crate::lzfse;
+use crate::vdisk::{Disk, be32, be64, corrupt, inflate, read_exact_at, unsupported};
+
+/// One run of sectors in a blkx table
+struct Chunk {
+    kind: u32,
+    /// Position in the raw disk, in bytes
+    start: u64,
+    len: u64,
+    /// Position of the stored data in the file
+    offset: u64,
+    stored: u64,
+}
+
+/// Apple Universal Disk Image Format (UDIF), as produced by hdiutil
+pub struct Dmg {
+    file: File,
+    size: u64,
+    /// Sorted by `start`; gaps between chunks read as zeros
+    chunks: Vec<Chunk>,
+    /// Last decompressed chunk, by index
+    cached: Option<(usize, Vec<u8>)>,
+}
+
+impl Dmg {
+    const ZERO: u32 = 0;
+    const RAW: u32 = 1;
+    const IGNORE: u32 = 2;
+    const ADC: u32 = 0x8000_0004;
+    const ZLIB: u32 = 0x8000_0005;
+    const BZIP2: u32 = 0x8000_0006;
+    const LZFSE: u32 = 0x8000_0007;
+    const LZMA: u32 = 0x8000_0008;
+    const COMMENT: u32 = 0x7FFF_FFFE;
+    const END: u32 = 0xFFFF_FFFF;
+    /// hdiutil writes chunks of at most a few MiB; anything far larger is a corrupt table
+    const MAX_CHUNK: u64 = 64 << 20;
+
+    pub fn open(mut file: File) -> io::Result<Dmg> {
+        let len = file.metadata()?.len();
+        if len < 512 {
+            return Err(corrupt("file too short for a DMG trailer"));
+        }
+        let mut koly = [0u8; 512];
+        read_exact_at(&mut file, len - 512, &mut koly)?;
+        if &koly[0..4] != b"koly" {
+            return Err(corrupt("missing DMG koly trailer"));
+        }
+        let data_fork = be64(&koly, 24);
+        let xml_offset = be64(&koly, 216);
+        let xml_len = be64(&koly, 224);
+        let size = be64(&koly, 492).checked_mul(512).ok_or_else(|| corrupt("invalid DMG sector count"))?;
+        if xml_len == 0 {
+            return Err(unsupported("DMG images without an XML block table are not supported"));
+        }
+        if xml_offset.checked_add(xml_len).is_none_or(|end| end > len) {
+            return Err(corrupt("DMG block table lies past the end of the file"));
+        }
+
+        let mut xml = vec![0u8; xml_len as usize];
+        read_exact_at(&mut file, xml_offset, &mut xml)?;
+        let plist = plist::Value::from_reader_xml(&xml[..]).map_err(|e| corrupt(format!("bad DMG property list: {}", e)))?;
+        let tables = plist
+            .as_dictionary()
+            .and_then(|d| d.get("resource-fork"))
+            .and_then(|v| v.as_dictionary())
+            .and_then(|d| d.get("blkx"))
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| corrupt("DMG property list has no blkx tables"))?;
+
+        let mut chunks = Vec::new();
+        for table in tables {
+            let mish = table
+                .as_dictionary()
+                .and_then(|d| d.get("Data"))
+                .and_then(|v| v.as_data())
+                .ok_or_else(|| corrupt("DMG blkx entry has no data"))?;
+            if mish.len() < 204 || &mish[0..4] != b"mish" {
+                return Err(corrupt("bad DMG blkx table"));
+            }
+            let first_sector = be64(mish, 8);
+            let data_offset = be64(mish, 24);
+            let count = be32(mish, 200) as usize;
+            if mish.len() < 204 + count * 40 {
+                return Err(corrupt("truncated DMG blkx table"));
+            }
+            for raw in mish[204..204 + count * 40].chunks_exact(40) {
+                let kind = be32(raw, 0);
+                match kind {
+                    Self::COMMENT | Self::END => continue,
+                    Self::LZMA => return Err(unsupported("LZMA-compressed DMG images are not supported")),
+                    Self::ZERO | Self::RAW | Self::IGNORE | Self::ADC | Self::ZLIB | Self::BZIP2 | Self::LZFSE => {}
+                    other => return Err(unsupported(format!("DMG chunk type {:#x} is not supported", other))),
+                }
+                // Positions come straight from the file, so a corrupt table can overflow any of them
+                let overflow = || corrupt("DMG chunk position overflows");
+                let start = first_sector.checked_add(be64(raw, 8)).and_then(|s| s.checked_mul(512)).ok_or_else(overflow)?;
+                let chunk = Chunk {
+                    kind,
+                    start,
+                    len: be64(raw, 16).checked_mul(512).filter(|l| start.checked_add(*l).is_some()).ok_or_else(overflow)?,
+                    offset: data_fork.checked_add(data_offset).and_then(|o| o.checked_add(be64(raw, 24))).ok_or_else(overflow)?,
+                    stored: be64(raw, 32),
+                };
+                let compressed = !matches!(kind, Self::ZERO | Self::RAW | Self::IGNORE);
+                if compressed && (chunk.len > Self::MAX_CHUNK || chunk.stored > Self::MAX_CHUNK) {
+                    return Err(corrupt("DMG chunk is implausibly large"));
+                }
+                if kind != Self::ZERO && kind != Self::IGNORE && chunk.offset.checked_add(chunk.stored).is_none_or(|end| end > len) {
+                    return Err(corrupt("DMG chunk lies past the end of the file"));
+                }
+                chunks.push(chunk);
+            }
+        }
+        chunks.retain(|c| c.len > 0);
+        chunks.sort_by_key(|c| c.start);
+
+        Ok(Dmg { file, size, chunks, cached: None })
+    }
+
+    /// Decompress chunk `index` in full
+    fn expand(&mut self, index: usize) -> io::Result<&[u8]> {
+        if self.cached.as_ref().is_none_or(|(cached, _)| *cached != index) {
+            let chunk = &self.chunks[index];
+            let mut data = vec![0u8; chunk.stored as usize];
+            read_exact_at(&mut self.file, chunk.offset, &mut data)?;
+            let mut out = vec![0u8; chunk.len as usize];
+            match chunk.kind {
+                Self::ZLIB => inflate(ZlibDecoder::new(&data[..]), &mut out)?,
+                Self::BZIP2 => inflate(BzDecoder::new(&data[..]), &mut out)?,
+                Self::ADC => {
+                    let mut raw = adc(&data, out.len())?;
+                    raw.resize(out.len(), 0);
+                    out = raw;
+                }
+                Self::LZFSE => {
+                    let mut raw = Vec::with_capacity(out.len());
+                    lzfse::decompress(&data, &mut raw, out.len())?;
+                    raw.resize(out.len(), 0);
+                    out = raw;
+                }
+                _ => unreachable!("only compressed chunks are expanded"),
+            }
+            self.cached = Some((index, out));
+        }
+        Ok(&self.cached.as_ref().unwrap().1)
+    }
+
+    /// Fill `out` from chunk `index`, starting `within` bytes into it
+    fn read_chunk(&mut self, index: usize, within: u64, out: &mut [u8]) -> io::Result<()> {
+        let chunk = &self.chunks[index];
+        match chunk.kind {
+            Self::ZERO | Self::IGNORE => out.fill(0),
+            Self::RAW => {
+                // Raw chunks may be shorter than their sector run; the rest is zeros
+                let stored = chunk.stored.saturating_sub(within).min(out.len() as u64) as usize;
+                let offset = chunk.offset + within;
+                read_exact_at(&mut self.file, offset, &mut out[..stored])?;
+                out[stored..].fill(0);
+            }
+            _ => {
+                let data = self.expand(index)?;
+                out.copy_from_slice(&data[within as usize..within as usize + out.len()]);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Disk for Dmg {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            // Last chunk starting at or before pos
+            let index = self.chunks.partition_point(|c| c.start <= pos);
+            let current = index.checked_sub(1).filter(|&i| pos < self.chunks[i].start + self.chunks[i].len);
+            match current {
+                Some(i) => {
+                    let within = pos - self.chunks[i].start;
+                    let n = ((self.chunks[i].len - within) as usize).min(buf.len() - done);
+                    self.read_chunk(i, within, &mut buf[done..done + n])?;
+                    done += n;
+                }
+                None => {
+                    // A gap: zeros up to the next chunk
+                    let next = self.chunks.get(index).map_or(u64::MAX, |c| c.start);
+                    let n = ((next - pos).min((buf.len() - done) as u64)) as usize;
+                    buf[done..done + n].fill(0);
+                    done += n;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Apple Data Compression: an LZ77 variant with 1-, 2- and 3-byte opcodes
+///
+/// Fails as soon as the output would pass `limit`, the chunk's sector run.
+fn adc(src: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+    let truncated = || corrupt("truncated ADC chunk");
+    let overrun = || corrupt("ADC chunk expands past its sector run");
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < src.len() {
+        let op = src[pos];
+        let (len, distance) = if op & 0x80 != 0 {
+            let len = (op & 0x7F) as usize + 1;
+            if out.len() + len > limit {
+                return Err(overrun());
+            }
+            let literals = src.get(pos + 1..pos + 1 + len).ok_or_else(truncated)?;
+            out.extend_from_slice(literals);
+            pos += 1 + len;
+            continue;
+        } else if op & 0x40 != 0 {
+            let hi = *src.get(pos + 1).ok_or_else(truncated)? as usize;
+            let lo = *src.get(pos + 2).ok_or_else(truncated)? as usize;
+            pos += 3;
+            ((op & 0x3F) as usize + 4, (hi << 8 | lo) + 1)
+        } else {
+            let lo = *src.get(pos + 1).ok_or_else(truncated)? as usize;
+            pos += 2;
+            (((op & 0x3C) >> 2) as usize + 3, ((op as usize & 3) << 8 | lo) + 1)
+        };
+        if distance > out.len() {
+            return Err(corrupt("ADC match reaches before the start of the chunk"));
+        }
+        if out.len() + len > limit {
+            return Err(overrun());
+        }
+        let start = out.len() - distance;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+    }
+    Ok(out)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_adc_chunks() {
+        // Literal "abc", a 9-byte match 3 back, literal "XYZ", then a 3-byte opcode for 20 bytes 15 back
+        let chunk = [0x82, b'a', b'b', b'c', 0x18, 0x02, 0x82, b'X', b'Y', b'Z', 0x50, 0x00, 0x0E];
+        assert_eq!(adc(&chunk, 35).unwrap(), b"abcabcabcabcXYZabcabcabcabcXYZabcab");
+
+        // A match before any output, and a literal run cut short
+        assert!(adc(&[0x18, 0x02], 512).is_err());
+        assert!(adc(&[0x85, b'a'], 512).is_err());
+
+        // Output past the sector run, whether from a match or from literals
+        assert!(adc(&chunk, 34).is_err());
+        assert!(adc(&chunk, 10).is_err());
+    }
+}
@@ -22,11 +22,13 @@ use std::io::{
 };
 
 
-pub fn menu(iso: &str, dev: &str) -> bool {
+/// `details` are shown under the question, e.g. the image's volume label and size,
+/// followed by `drive`, what is on the target now and is about to be lost
+pub fn menu(iso: &str, dev: &str, details: &[String], drive: &[String]) -> bool {
     enable_raw_mode().unwrap();
     let mut stdout = stdout();
 
-    let warn = vec!["Yes", "No"];
+    let warn = ["Yes", "No"];
     let mut selected = 0;
 
     loop {
@@ -36,8 +38,21 @@ pub fn menu(iso: &str, dev: &str) -> bool {
             iso, dev
         );
 
-        for (i, item) in warn.iter().enumerate() {
+        // One blank line between the image's details and the drive's contents
+        let mut lines: Vec<&str> = details.iter().map(String::as_str).collect();
+        if !details.is_empty() && !drive.is_empty() {
+            lines.push("");
+        }
+        lines.extend(drive.iter().map(String::as_str));
+
+        for (i, line) in lines.iter().enumerate() {
             execute!(stdout, cursor::MoveTo(0, (i + 1) as u16)).unwrap();
+            print!("{}", line);
+        }
+        let options_row = if lines.is_empty() { 1 } else { lines.len() + 2 };
+
+        for (i, item) in warn.iter().enumerate() {
+            execute!(stdout, cursor::MoveTo(0, (i + options_row) as u16)).unwrap();
             execute!(stdout, terminal::Clear(ClearType::CurrentLine)).unwrap();
 
             if i == selected {
@@ -51,8 +66,8 @@ pub fn menu(iso: &str, dev: &str) -> bool {
 
         if let Event::Key(key) = event::read().unwrap() {
             match key.code {
-                KeyCode::Up => if selected > 0 { selected -= 1 },
-                KeyCode::Down => if selected < warn.len() - 1 { selected += 1 },
+                KeyCode::Up => selected = selected.saturating_sub(1),
+                KeyCode::Down if selected < warn.len() - 1 => selected += 1,
                 KeyCode::Enter => {
                     disable_raw_mode().unwrap();
                     return selected == 0;
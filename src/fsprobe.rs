@@ -0,0 +1,273 @@
+use std::io::{Read, Seek, SeekFrom};
+
+/// Enough to reach the btrfs superblock at 64 KiB
+const PROBE_SIZE: usize = 0x10000 + 0x1000;
+
+/// A filesystem recognised from its superblock
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filesystem {
+    /// Short name as used by blkid, e.g. "vfat" or "ext4"
+    pub kind: &'static str,
+    pub label: Option<String>,
+    /// Space in use according to the superblock, for filesystems that record it there
+    pub used: Option<u64>,
+}
+
+impl std::fmt::Display for Filesystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.label {
+            Some(label) => write!(f, "{} \"{}\"", self.kind, label),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+/// Identify the filesystem starting at byte `offset` of `file` from its magic numbers
+pub fn probe(file: &mut (impl Read + Seek), offset: u64) -> Option<Filesystem> {
+    let mut buf = vec![0u8; PROBE_SIZE];
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        }
+    }
+    identify(&buf[..len])
+}
+
+/// Identify a filesystem from the first bytes of a partition
+fn identify(buf: &[u8]) -> Option<Filesystem> {
+    let at = |offset: usize, magic: &[u8]| buf.get(offset..offset + magic.len()) == Some(magic);
+    let fs = |kind, label, used| Some(Filesystem { kind, label, used });
+
+    if at(0x10040, b"_BHRfS_M") {
+        return fs("btrfs", text(buf, 0x1012B, 256), le64(buf, 0x10078));
+    }
+    if at(0x438, &[0x53, 0xEF]) {
+        // ext4 if extents are in use, ext3 if it has a journal, else ext2
+        let compat = le32(buf, 0x45C)?;
+        let incompat = le32(buf, 0x460)?;
+        let kind = if incompat & 0x40 != 0 {
+            "ext4"
+        } else if compat & 0x4 != 0 {
+            "ext3"
+        } else {
+            "ext2"
+        };
+        return fs(kind, text(buf, 0x478, 16), ext_used(buf, incompat));
+    }
+    if at(0, b"hsqs") {
+        // bytes_used is the size of the (read-only) filesystem itself
+        return fs("squashfs", None, le64(buf, 40));
+    }
+    if at(3, b"NTFS    ") {
+        // The label lives in the $Volume MFT record, which is not worth chasing here
+        return fs("ntfs", None, None);
+    }
+    if at(3, b"EXFAT   ") {
+        return fs("exfat", None, exfat_used(buf));
+    }
+    if at(510, &[0x55, 0xAA]) {
+        if at(0x52, b"FAT32   ") {
+            return fs("vfat", fat_label(buf, 0x47), fat32_used(buf));
+        }
+        if at(0x36, b"FAT16   ") || at(0x36, b"FAT12   ") || at(0x36, b"FAT     ") {
+            return fs("vfat", fat_label(buf, 0x2B), None);
+        }
+    }
+    if at(0x8001, b"CD001") {
+        return fs("iso9660", text(buf, 0x8028, 32), None);
+    }
+    if at(0, b"XFSB") {
+        return fs("xfs", text(buf, 108, 12), xfs_used(buf));
+    }
+    if at(0, b"LUKS\xBA\xBE") {
+        return fs("crypto_LUKS", None, None);
+    }
+    if at(4086, b"SWAPSPACE2") {
+        return fs("swap", text(buf, 1024 + 28, 16), None);
+    }
+    if at(1024, b"H+") || at(1024, b"HX") {
+        return fs("hfsplus", None, None);
+    }
+    if at(32, b"NXSB") {
+        return fs("apfs", None, None);
+    }
+    None
+}
+
+/// (blocks - free blocks) * block size, with the high halves when the 64bit feature is on
+fn ext_used(buf: &[u8], incompat: u32) -> Option<u64> {
+    let sb = 1024;
+    let mut blocks = le32(buf, sb + 4)? as u64;
+    let mut free = le32(buf, sb + 12)? as u64;
+    if incompat & 0x80 != 0 {
+        blocks |= (le32(buf, sb + 0x150)? as u64) << 32;
+        free |= (le32(buf, sb + 0x158)? as u64) << 32;
+    }
+    let block_size = 1024u64 << le32(buf, sb + 24)?.min(16);
+    blocks.saturating_sub(free).checked_mul(block_size)
+}
+
+/// The free block count in an XFS superblock can lag while it is mounted, but is close enough here
+fn xfs_used(buf: &[u8]) -> Option<u64> {
+    let block_size = be32(buf, 4)? as u64;
+    let blocks = be64(buf, 8)?;
+    let free = be64(buf, 144)?;
+    blocks.saturating_sub(free).checked_mul(block_size)
+}
+
+/// From the free cluster count in the FSInfo sector, when it has been kept up to date
+fn fat32_used(buf: &[u8]) -> Option<u64> {
+    let sector = le16(buf, 0x0B)? as u64;
+    let per_cluster = *buf.get(0x0D)? as u64;
+    let reserved = le16(buf, 0x0E)? as u64;
+    let fats = *buf.get(0x10)? as u64;
+    let total = le32(buf, 0x20)? as u64;
+    let fat_size = le32(buf, 0x24)? as u64;
+    if per_cluster == 0 {
+        return None;
+    }
+    let clusters = total.checked_sub(reserved + fats * fat_size)? / per_cluster;
+
+    let info = (le16(buf, 0x30)? as u64 * sector) as usize;
+    if buf.get(info..info + 4)? != b"RRaA" {
+        return None;
+    }
+    let free = le32(buf, info + 488)? as u64;
+    (free <= clusters).then(|| (clusters - free) * per_cluster * sector)
+}
+
+/// exFAT keeps a rounded percentage of the volume in use, 0xFF when unknown
+fn exfat_used(buf: &[u8]) -> Option<u64> {
+    let percent = *buf.get(112)? as u64;
+    if percent > 100 {
+        return None;
+    }
+    let size = le64(buf, 72)? << (*buf.get(108)?).min(12);
+    Some(size / 100 * percent)
+}
+
+fn le16(buf: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(buf.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn le32(buf: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(buf.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn le64(buf: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(buf.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+fn be32(buf: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(buf.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn be64(buf: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(buf.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+/// NUL or space padded label
+fn text(buf: &[u8], offset: usize, len: usize) -> Option<String> {
+    let field = buf.get(offset..offset + len)?;
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    let label = String::from_utf8_lossy(&field[..end]).trim().to_string();
+    (!label.is_empty()).then_some(label)
+}
+
+/// FAT boot sector labels default to "NO NAME"
+fn fat_label(buf: &[u8], offset: usize) -> Option<String> {
+    text(buf, offset, 11).filter(|label| label != "NO NAME")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testimg::{self, Iso};
+
+    /// Superblocks of each kind, with what they should be identified as
+    fn samples() -> Vec<(Vec<u8>, &'static str, Option<&'static str>)> {
+        let mut samples = vec![
+            (testimg::ext4("rootfs", 1000, 400), "ext4", Some("rootfs")),
+            (testimg::fat32("EFI", 2048, 100), "vfat", Some("EFI")),
+            (testimg::fat32("NO NAME", 2048, 100), "vfat", None),
+            (Iso::default().build(), "iso9660", Some("UBUNTU 24.04 LTS")),
+        ];
+
+        let mut ext3 = testimg::ext4("", 1000, 400);
+        ext3[0x460] = 0;
+        samples.push((ext3.clone(), "ext3", None));
+        ext3[0x45C] = 0;
+        samples.push((ext3, "ext2", None));
+
+        let mut fat16 = vec![0u8; 512];
+        fat16[0x2B..0x36].copy_from_slice(b"BOOT       ");
+        fat16[0x36..0x3E].copy_from_slice(b"FAT16   ");
+        fat16[510..512].copy_from_slice(&[0x55, 0xAA]);
+        samples.push((fat16, "vfat", Some("BOOT")));
+
+        let mut btrfs = vec![0u8; PROBE_SIZE];
+        btrfs[0x10040..0x10048].copy_from_slice(b"_BHRfS_M");
+        btrfs[0x1012B..0x10131].copy_from_slice(b"fedora");
+        samples.push((btrfs, "btrfs", Some("fedora")));
+
+        let mut xfs = vec![0u8; 512];
+        xfs[..4].copy_from_slice(b"XFSB");
+        xfs[108..112].copy_from_slice(b"data");
+        samples.push((xfs, "xfs", Some("data")));
+
+        let mut swap = vec![0u8; 4096];
+        swap[1024 + 28..1024 + 32].copy_from_slice(b"swap");
+        swap[4086..4096].copy_from_slice(b"SWAPSPACE2");
+        samples.push((swap, "swap", Some("swap")));
+
+        for (magic, at, kind) in [
+            (&b"hsqs"[..], 0, "squashfs"),
+            (b"NTFS    ", 3, "ntfs"),
+            (b"EXFAT   ", 3, "exfat"),
+            (b"LUKS\xBA\xBE", 0, "crypto_LUKS"),
+            (b"H+", 1024, "hfsplus"),
+            (b"NXSB", 32, "apfs"),
+        ] {
+            let mut fs = vec![0u8; 2048];
+            fs[at..at + magic.len()].copy_from_slice(magic);
+            samples.push((fs, kind, None));
+        }
+        samples
+    }
+
+    #[test]
+    fn identifies_filesystems_by_their_magic() {
+        for (fs, kind, label) in samples() {
+            let found = identify(&fs).unwrap_or_else(|| panic!("{} not found", kind));
+            assert_eq!((found.kind, found.label.as_deref()), (kind, label));
+        }
+        assert_eq!(identify(&[0; 4096]), None);
+
+        // Probing reads from the partition's offset, and copes with it being near the end
+        let mut disk = vec![0u8; 1 << 20];
+        disk[512 * 1024..512 * 1024 + 2048].copy_from_slice(&testimg::ext4("rootfs", 1000, 400));
+        let mut file = std::io::Cursor::new(disk);
+        assert_eq!(probe(&mut file, 512 * 1024).unwrap().to_string(), "ext4 \"rootfs\"");
+        assert_eq!(probe(&mut file, 0), None);
+        assert_eq!(probe(&mut file, (1 << 20) - 10), None);
+        assert_eq!(probe(&mut file, u64::MAX), None);
+    }
+
+    #[test]
+    fn survives_superblocks_cut_anywhere() {
+        for (fs, kind, _) in samples() {
+            for len in 0..=fs.len() {
+                if let Some(found) = identify(&fs[..len]) {
+                    assert_eq!(found.kind, kind, "{} cut to {} bytes", kind, len);
+                }
+            }
+        }
+        // Cut after the ext magic but before the feature flags: not enough to say which ext
+        assert_eq!(identify(&testimg::ext4("rootfs", 1000, 400)[..0x440]), None);
+    }
+}
@@ -0,0 +1,140 @@
+use std::time::{Duration, Instant};
+
+/// How often to look again when no event source is available
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Without sysfs, listing drives means running diskutil or PowerShell, so do it less often
+#[cfg(not(target_os = "linux"))]
+const RELIST_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Notices drives being plugged in, removed, or having their media changed
+///
+/// On Linux this listens to kernel uevents over netlink, falling back to polling
+/// /sys/block when the socket cannot be opened or fails. Elsewhere it just asks for a fresh
+/// listing every few seconds.
+pub struct Watcher {
+    #[cfg(target_os = "linux")]
+    socket: Option<std::os::fd::OwnedFd>,
+    #[cfg(target_os = "linux")]
+    snapshot: Vec<(String, String)>,
+    last_check: Instant,
+}
+
+impl Watcher {
+    pub fn new() -> Watcher {
+        Watcher {
+            #[cfg(target_os = "linux")]
+            socket: uevent_socket(),
+            #[cfg(target_os = "linux")]
+            snapshot: sys_block(),
+            last_check: Instant::now(),
+        }
+    }
+
+    /// Whether the set of block devices may have changed since the last call
+    #[cfg(target_os = "linux")]
+    pub fn changed(&mut self) -> bool {
+        use std::os::fd::AsRawFd;
+
+        if let Some(fd) = self.socket.as_ref().map(|socket| socket.as_raw_fd()) {
+            let mut changed = false;
+            let mut buf = [0u8; 8192];
+            loop {
+                let n = unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+                if n == 0 {
+                    break;
+                }
+                if n < 0 {
+                    match std::io::Error::last_os_error().raw_os_error() {
+                        // Nothing more queued
+                        Some(libc::EAGAIN) => break,
+                        Some(libc::EINTR) => continue,
+                        // The receive buffer overflowed and events were lost: assume a change
+                        Some(libc::ENOBUFS) => {
+                            changed = true;
+                            continue;
+                        }
+                        // The socket is no use any more: poll /sys/block from now on
+                        _ => {
+                            self.socket = None;
+                            self.snapshot = sys_block();
+                            self.last_check = Instant::now();
+                            return true;
+                        }
+                    }
+                }
+                // "add@/devices/...\0ACTION=add\0...\0SUBSYSTEM=block\0..."
+                changed |= buf[..n as usize].split(|&b| b == 0).any(|field| field == b"SUBSYSTEM=block");
+            }
+            return changed;
+        }
+
+        if self.last_check.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+        let snapshot = sys_block();
+        let changed = snapshot != self.snapshot;
+        self.snapshot = snapshot;
+        changed
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < RELIST_INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+        true
+    }
+}
+
+/// Non-blocking socket subscribed to the kernel's uevent broadcasts
+#[cfg(target_os = "linux")]
+fn uevent_socket() -> Option<std::os::fd::OwnedFd> {
+    use std::os::fd::FromRawFd;
+
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            libc::NETLINK_KOBJECT_UEVENT,
+        )
+    };
+    if fd < 0 {
+        return None;
+    }
+    // Owned from here on, so it is closed if binding fails
+    let socket = unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) };
+
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    // Group 1 carries the kernel's own events (udev rebroadcasts on group 2)
+    addr.nl_groups = 1;
+    let bound = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    (bound == 0).then_some(socket)
+}
+
+/// Block devices and their sizes, so both hotplug and media changes show up
+#[cfg(target_os = "linux")]
+fn sys_block() -> Vec<(String, String)> {
+    let mut devices: Vec<(String, String)> = std::fs::read_dir("/sys/block")
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| {
+                    let name = e.file_name().to_string_lossy().to_string();
+                    let size = std::fs::read_to_string(e.path().join("size")).unwrap_or_default();
+                    (name, size.trim().to_string())
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    devices.sort();
+    devices
+}
@@ -0,0 +1,194 @@
+use std::path::Path;
+
+use crate::targ;
+use crate::units::human;
+
+/// What makes the chosen drive that drive, recorded when it is picked
+///
+/// Device names are reused: if the stick is pulled and another plugged in, the new one
+/// may well become /dev/sdb too. Checking these again before writing catches that.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub serial: Option<String>,
+    /// World Wide Name, reported by most SATA, SAS and NVMe drives
+    pub wwn: Option<String>,
+    /// Name of the drive's link in /dev/disk/by-id
+    pub by_id: Option<String>,
+    pub size: Option<u64>,
+    /// Kernel counter bumped on every attach or media change; only compared for drives
+    /// without a serial, WWN or by-id name, since it also changes when the same drive is re-plugged
+    pub diskseq: Option<u64>,
+}
+
+impl Identity {
+    fn is_anonymous(&self) -> bool {
+        self.serial.is_none() && self.wwn.is_none() && self.by_id.is_none()
+    }
+
+    /// Make sure `dev` is still the drive that was pinned, explaining what changed if not
+    ///
+    /// sysfs and /dev are looked up under `root`, which is "/" except when testing.
+    pub fn check(&self, root: &Path, dev: &str) -> Result<(), String> {
+        if !root.join(dev.trim_start_matches('/')).exists() {
+            return Err(format!("{} has disappeared since it was picked", dev));
+        }
+        let now = pin(root, dev);
+        let changed = |what: &str, was: Option<String>, is: Option<String>| match (&was, &is) {
+            (Some(was), is) if Some(was) != is.as_ref() => Err(format!(
+                "{} is no longer the drive you picked: its {} was {}, now {}",
+                dev,
+                what,
+                was,
+                is.as_deref().unwrap_or("unknown")
+            )),
+            _ => Ok(()),
+        };
+        changed("serial number", self.serial.clone(), now.serial)?;
+        changed("WWN", self.wwn.clone(), now.wwn)?;
+        changed("/dev/disk/by-id name", self.by_id.clone(), now.by_id)?;
+        if let Some(size) = self.size
+            && now.size != Some(size)
+        {
+            let now = now.size.map(human).unwrap_or_else(|| "unknown".to_string());
+            return Err(format!("{} is no longer the drive you picked: its size was {}, now {}", dev, human(size), now));
+        }
+        if self.is_anonymous() {
+            changed("attach sequence number", self.diskseq.map(|s| s.to_string()), now.diskseq.map(|s| s.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Record the identity of `dev` as it is now, looking it up under `root`
+pub fn pin(root: &Path, dev: &str) -> Identity {
+    let details = targ::device_details(root, dev);
+    let mut identity = Identity { serial: details.serial, size: details.size, ..Identity::default() };
+
+    #[cfg(target_os = "linux")]
+    {
+        let name = dev.trim_start_matches("/dev/");
+        let sys = root.join("sys/block").join(name);
+        let read = |file: &str| {
+            std::fs::read_to_string(sys.join(file))
+                .ok()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        // SCSI/SATA drives have it under device/, NVMe namespaces on the block device itself
+        identity.wwn = read("device/wwid").or_else(|| read("wwid"));
+        identity.by_id = by_id(root, dev);
+        identity.diskseq = read("diskseq").and_then(|s| s.parse().ok());
+    }
+
+    identity
+}
+
+/// The drive's /dev/disk/by-id link, preferring the bus-and-serial names over wwn-/eui. ones
+#[cfg(target_os = "linux")]
+fn by_id(root: &Path, dev: &str) -> Option<String> {
+    let target = std::fs::canonicalize(root.join(dev.trim_start_matches('/'))).ok()?;
+    let mut names: Vec<String> = std::fs::read_dir(root.join("dev/disk/by-id"))
+        .ok()?
+        .flatten()
+        .filter(|e| std::fs::canonicalize(e.path()).is_ok_and(|p| p == target))
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort_by_key(|n| (n.starts_with("wwn-") || n.contains("eui."), n.clone()));
+    names.into_iter().next()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    /// A fake root with /dev/`name` and its sysfs directory holding `files`, and `by_id`
+    /// links to it in /dev/disk/by-id
+    fn tree(test: &str, name: &str, files: &[(&str, &str)], by_id: &[&str]) -> std::path::PathBuf {
+        let root = std::env::temp_dir().join(format!("tetcher-identity-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["dev/disk/by-id", "sys/devices", "run/udev/data"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("dev").join(name), "").unwrap();
+        let sys = root.join("sys/block").join(name);
+        for (file, contents) in files {
+            fs::create_dir_all(sys.join(file).parent().unwrap()).unwrap();
+            fs::write(sys.join(file), format!("{}\n", contents)).unwrap();
+        }
+        for link in by_id {
+            symlink(format!("../../{}", name), root.join("dev/disk/by-id").join(link)).unwrap();
+        }
+        root
+    }
+
+    fn udev_serial(root: &Path, serial: &str) {
+        fs::write(root.join("run/udev/data/b8:16"), format!("E:ID_SERIAL_SHORT={}\n", serial)).unwrap();
+    }
+
+    #[test]
+    fn pins_what_sysfs_udev_and_by_id_say() {
+        let files = [("size", "2048"), ("dev", "8:16"), ("diskseq", "7"), ("device/wwid", "naa.5000c500a1b2c3d4")];
+        let by_id = ["wwn-0x5000c500a1b2c3d4", "usb-Kingston_DataTraveler_AA0011-0:0"];
+        let root = tree("pin", "sdb", &files, &by_id);
+        fs::write(root.join("dev/sdb1"), "").unwrap();
+        symlink("../../sdb1", root.join("dev/disk/by-id/usb-Kingston_DataTraveler_AA0011-0:0-part1")).unwrap();
+        udev_serial(&root, "AA0011");
+
+        assert_eq!(
+            pin(&root, "/dev/sdb"),
+            Identity {
+                serial: Some("AA0011".to_string()),
+                wwn: Some("naa.5000c500a1b2c3d4".to_string()),
+                by_id: Some("usb-Kingston_DataTraveler_AA0011-0:0".to_string()),
+                size: Some(2048 * 512),
+                diskseq: Some(7),
+            }
+        );
+
+        // NVMe namespaces keep their WWID on the block device itself
+        let root = tree("pin-nvme", "nvme0n1", &[("size", "4096"), ("wwid", "eui.0025388b71b2c3d4")], &[]);
+        let nvme = pin(&root, "/dev/nvme0n1");
+        assert_eq!(nvme.wwn.as_deref(), Some("eui.0025388b71b2c3d4"));
+        assert_eq!((nvme.serial, nvme.by_id, nvme.size), (None, None, Some(4096 * 512)));
+    }
+
+    #[test]
+    fn notices_a_different_drive_under_the_same_name() {
+        let files = [("size", "2048"), ("dev", "8:16"), ("diskseq", "7")];
+        let root = tree("check", "sdb", &files, &["usb-Kingston_DataTraveler_AA0011-0:0"]);
+        udev_serial(&root, "AA0011");
+        let picked = pin(&root, "/dev/sdb");
+        assert_eq!(picked.check(&root, "/dev/sdb"), Ok(()));
+
+        // Re-plugging the same drive bumps diskseq, which is fine for a drive with a serial
+        fs::write(root.join("sys/block/sdb/diskseq"), "8\n").unwrap();
+        assert_eq!(picked.check(&root, "/dev/sdb"), Ok(()));
+
+        udev_serial(&root, "BB2233");
+        let why = picked.check(&root, "/dev/sdb").unwrap_err();
+        assert!(why.contains("serial number was AA0011, now BB2233"), "{}", why);
+        udev_serial(&root, "AA0011");
+
+        fs::write(root.join("sys/block/sdb/size"), "4096\n").unwrap();
+        let why = picked.check(&root, "/dev/sdb").unwrap_err();
+        assert!(why.contains("its size was 1.00 MiB, now 2.00 MiB"), "{}", why);
+
+        fs::remove_file(root.join("dev/sdb")).unwrap();
+        let why = picked.check(&root, "/dev/sdb").unwrap_err();
+        assert!(why.contains("has disappeared"), "{}", why);
+    }
+
+    #[test]
+    fn tells_anonymous_drives_apart_by_attach_sequence() {
+        let root = tree("anonymous", "mmcblk0", &[("size", "2048"), ("diskseq", "3")], &[]);
+        let picked = pin(&root, "/dev/mmcblk0");
+        assert!(picked.is_anonymous());
+        assert_eq!(picked.check(&root, "/dev/mmcblk0"), Ok(()));
+
+        fs::write(root.join("sys/block/mmcblk0/diskseq"), "4\n").unwrap();
+        let why = picked.check(&root, "/dev/mmcblk0").unwrap_err();
+        assert!(why.contains("attach sequence number was 3, now 4"), "{}", why);
+    }
+}
@@ -0,0 +1,326 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crossterm::style::{Color, Stylize};
+
+use crate::units::human;
+use crate::{fsprobe, iso9660, parttable, vdisk};
+
+/// What a file turned out to be, judged by its content rather than its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Iso9660,
+    /// Raw disk image with an MBR or GPT partition table
+    RawDisk,
+    /// Raw image of a single filesystem, without a partition table
+    RawFilesystem(&'static str),
+    /// Fixed-size VHD: raw data followed by a 512-byte footer
+    FixedVhd,
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+    Lz4,
+    Zip,
+    Qcow2,
+    /// Dynamic or differencing VHD
+    Vhd,
+    Vhdx,
+    Vmdk,
+    Dmg,
+    AndroidSparse,
+    Unknown,
+}
+
+/// Whether the file can be written to a drive as it is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    FlashableAsIs,
+    /// A virtual disk that is expanded to this many bytes of raw data while flashing
+    Expanded(u64),
+    /// Needs converting first; the text says how
+    NeedsConversion(String),
+    NotDiskImage,
+}
+
+impl Verdict {
+    pub fn is_flashable(&self) -> bool {
+        matches!(self, Verdict::FlashableAsIs | Verdict::Expanded(_))
+    }
+}
+
+impl Format {
+    pub fn description(&self) -> String {
+        match self {
+            Format::Iso9660 => "ISO9660 / UDF image".to_string(),
+            Format::RawDisk => "raw disk image".to_string(),
+            Format::RawFilesystem(kind) => format!("raw {} filesystem image", kind),
+            Format::FixedVhd => "fixed-size VHD".to_string(),
+            Format::Gzip => "gzip-compressed file".to_string(),
+            Format::Xz => "xz-compressed file".to_string(),
+            Format::Zstd => "zstd-compressed file".to_string(),
+            Format::Bzip2 => "bzip2-compressed file".to_string(),
+            Format::Lz4 => "lz4-compressed file".to_string(),
+            Format::Zip => "zip archive".to_string(),
+            Format::Qcow2 => "qcow2 virtual disk".to_string(),
+            Format::Vhd => "dynamic VHD virtual disk".to_string(),
+            Format::Vhdx => "VHDX virtual disk".to_string(),
+            Format::Vmdk => "VMDK virtual disk".to_string(),
+            Format::Dmg => "Apple disk image (DMG)".to_string(),
+            Format::AndroidSparse => "Android sparse image".to_string(),
+            Format::Unknown => "unrecognised file".to_string(),
+        }
+    }
+
+    /// How to turn the file into something flashable, for formats that need it
+    fn conversion(&self) -> &'static str {
+        match self {
+            Format::Gzip => "decompress it first, e.g. gunzip -k",
+            Format::Xz => "decompress it first, e.g. xz -dk",
+            Format::Zstd => "decompress it first, e.g. zstd -d",
+            Format::Bzip2 => "decompress it first, e.g. bunzip2 -k",
+            Format::Lz4 => "decompress it first, e.g. lz4 -d",
+            Format::Zip => "extract the image from the archive first",
+            Format::Qcow2 | Format::Vhd | Format::Vhdx | Format::Vmdk => {
+                "convert it to raw first, e.g. qemu-img convert -O raw"
+            }
+            Format::Dmg => "convert it to raw first, e.g. hdiutil convert -format UDTO",
+            Format::AndroidSparse => "convert it to raw first, e.g. simg2img",
+            _ => "",
+        }
+    }
+}
+
+/// Identify `path` and decide whether it can be flashed
+pub fn assess(path: &Path) -> (Format, Verdict) {
+    let format = detect(path);
+    let verdict = match format {
+        Format::Iso9660 | Format::RawDisk | Format::RawFilesystem(_) | Format::FixedVhd => Verdict::FlashableAsIs,
+        Format::Qcow2 | Format::Vhd | Format::Vhdx | Format::Vmdk | Format::Dmg => match vdisk::open(path, format) {
+            Ok(disk) => Verdict::Expanded(disk.size()),
+            Err(why) => Verdict::NeedsConversion(format!("{}; {}", why, format.conversion())),
+        },
+        Format::Unknown => Verdict::NotDiskImage,
+        other => Verdict::NeedsConversion(other.conversion().to_string()),
+    };
+    (format, verdict)
+}
+
+/// Badge line for the confirm screens
+pub fn badge(path: &Path) -> String {
+    let (format, verdict) = assess(path);
+    match verdict {
+        Verdict::FlashableAsIs => format!(
+            "{} {}: flashable as-is",
+            "[ IMAGE ]".with(Color::Green).bold(),
+            format.description()
+        ),
+        Verdict::Expanded(size) => format!(
+            "{} {}: flashable, expanded to {} of raw data while writing",
+            "[ IMAGE ]".with(Color::Green).bold(),
+            format.description(),
+            human(size)
+        ),
+        Verdict::NeedsConversion(how) => format!(
+            "{} {}: needs conversion ({})",
+            "[ CONVERT ]".with(Color::Yellow).bold(),
+            format.description(),
+            how
+        ),
+        Verdict::NotDiskImage => format!(
+            "{} not a disk image \u{2014} are you sure?",
+            "[ UNKNOWN ]".with(Color::Red).bold()
+        ),
+    }
+}
+
+/// Identify `path` from its magic numbers
+pub fn detect(path: &Path) -> Format {
+    let Ok(mut file) = File::open(path) else { return Format::Unknown };
+    let mut head = [0u8; 512];
+    let mut len = 0;
+    while len < head.len() {
+        match file.read(&mut head[len..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => len += n,
+        }
+    }
+    let head = &head[..len];
+    let starts = |magic: &[u8]| head.starts_with(magic);
+
+    // Containers and compressed streams first: their payload may look like anything
+    if starts(&[0x3A, 0xFF, 0x26, 0xED]) {
+        return Format::AndroidSparse;
+    }
+    if starts(b"QFI\xFB") {
+        return Format::Qcow2;
+    }
+    if starts(b"vhdxfile") {
+        return Format::Vhdx;
+    }
+    if starts(b"KDMV") || starts(b"# Disk DescriptorFile") {
+        return Format::Vmdk;
+    }
+    if starts(b"conectix") {
+        // Dynamic and differencing VHDs start with a copy of the footer
+        return Format::Vhd;
+    }
+    if starts(&[0x1F, 0x8B]) {
+        return Format::Gzip;
+    }
+    if starts(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+        return Format::Xz;
+    }
+    if starts(&[0x28, 0xB5, 0x2F, 0xFD]) {
+        return Format::Zstd;
+    }
+    if starts(b"BZh") {
+        return Format::Bzip2;
+    }
+    if starts(&[0x04, 0x22, 0x4D, 0x18]) {
+        return Format::Lz4;
+    }
+    if starts(b"PK\x03\x04") {
+        return Format::Zip;
+    }
+
+    // DMG and fixed VHD are recognised by a 512-byte trailer
+    let trailer = trailer(&mut file);
+    if trailer.as_ref().is_some_and(|t| t.starts_with(b"koly")) {
+        return Format::Dmg;
+    }
+
+    if iso9660::read(path).is_some() {
+        return Format::Iso9660;
+    }
+    if parttable::read(path).is_some() {
+        return Format::RawDisk;
+    }
+    if let Some(fs) = fsprobe::probe(&mut file, 0) {
+        return Format::RawFilesystem(fs.kind);
+    }
+    if trailer.as_ref().is_some_and(|t| t.starts_with(b"conectix")) {
+        return Format::FixedVhd;
+    }
+    Format::Unknown
+}
+
+/// Last 512 bytes of the file
+fn trailer(file: &mut File) -> Option<[u8; 512]> {
+    let mut buf = [0u8; 512];
+    file.seek(SeekFrom::End(-512)).ok()?;
+    file.read_exact(&mut buf).ok()?;
+    Some(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testimg::{self, ESP, Iso};
+
+    fn assess_bytes(name: &str, data: &[u8]) -> (Format, Verdict) {
+        assess(&testimg::file(name, data))
+    }
+
+    /// Raw data with a fixed-size VHD footer after it
+    fn fixed_vhd() -> Vec<u8> {
+        let mut vhd = vec![0u8; 64 * 1024 + 512];
+        vhd[64 * 1024..64 * 1024 + 8].copy_from_slice(b"conectix");
+        vhd
+    }
+
+    #[test]
+    fn recognises_flashable_images_and_what_needs_converting() {
+        let mut ext4 = vec![0u8; 64 * 1024];
+        ext4[..2048].copy_from_slice(&testimg::ext4("rootfs", 16, 4));
+        let flashable = [
+            ("imgformat.iso", Iso::default().build(), Format::Iso9660),
+            ("imgformat-gpt.img", testimg::gpt(256, &[(ESP, 34, 255, "EFI system partition")]), Format::RawDisk),
+            ("imgformat-ext4.img", ext4, Format::RawFilesystem("ext4")),
+            ("imgformat-fixed.vhd", fixed_vhd(), Format::FixedVhd),
+        ];
+        for (name, data, format) in flashable {
+            assert_eq!(assess_bytes(name, &data), (format, Verdict::FlashableAsIs), "{}", name);
+        }
+
+        let compressed: [(&[u8], Format, &str); 7] = [
+            (&[0x1F, 0x8B, 0x08, 0x00], Format::Gzip, "gunzip"),
+            (b"\xFD7zXZ\x00\x00\x04", Format::Xz, "xz -dk"),
+            (&[0x28, 0xB5, 0x2F, 0xFD, 0x24], Format::Zstd, "zstd -d"),
+            (b"BZh91AY&SY", Format::Bzip2, "bunzip2"),
+            (&[0x04, 0x22, 0x4D, 0x18, 0x64], Format::Lz4, "lz4 -d"),
+            (b"PK\x03\x04\x14\x00", Format::Zip, "extract"),
+            (&[0x3A, 0xFF, 0x26, 0xED, 0x01, 0x00], Format::AndroidSparse, "simg2img"),
+        ];
+        for (head, format, how) in compressed {
+            let mut data = head.to_vec();
+            data.resize(4096, 0x55);
+            let (found, verdict) = assess_bytes("imgformat-packed", &data);
+            assert_eq!(found, format);
+            assert!(matches!(&verdict, Verdict::NeedsConversion(text) if text.contains(how)), "{:?}", verdict);
+            assert!(!verdict.is_flashable());
+        }
+
+        assert_eq!(assess_bytes("imgformat.txt", b"not an image\n"), (Format::Unknown, Verdict::NotDiskImage));
+        assert!(badge(&testimg::file("imgformat-badge.txt", b"text")).contains("not a disk image"));
+        assert!(badge(&testimg::file("imgformat-badge.iso", &Iso::default().build())).contains("flashable as-is"));
+    }
+
+    #[test]
+    fn judges_truncated_images_by_what_is_left() {
+        let unknown = (Format::Unknown, Verdict::NotDiskImage);
+        let iso = Iso::default().build();
+        assert_eq!(assess_bytes("imgformat-cut.iso", &iso[..16 * 2048 + 5]), unknown);
+        // A PVD cut short still carries the magic the filesystem probe looks for
+        let cut = assess_bytes("imgformat-cut.iso", &iso[..17 * 2048 - 1]);
+        assert_eq!(cut, (Format::RawFilesystem("iso9660"), Verdict::FlashableAsIs));
+        assert_eq!(assess_bytes("imgformat-cut.iso", &iso[..17 * 2048]).0, Format::Iso9660);
+
+        // The protective MBR is still a partition table
+        let gpt = testimg::gpt(256, &[(ESP, 34, 255, "EFI system partition")]);
+        assert_eq!(assess_bytes("imgformat-cut.img", &gpt[..600]).0, Format::RawDisk);
+        assert_eq!(assess_bytes("imgformat-cut.img", &gpt[..500]), unknown);
+
+        assert_eq!(assess_bytes("imgformat-cut-ext4.img", &testimg::ext4("rootfs", 16, 4)[..0x440]), unknown);
+        let vhd = fixed_vhd();
+        assert_eq!(assess_bytes("imgformat-cut.vhd", &vhd[..vhd.len() - 1]), unknown);
+        assert_eq!(assess_bytes("imgformat-cut.gz", &[0x1F]), unknown);
+        assert_eq!(assess_bytes("imgformat-empty", &[]), unknown);
+    }
+
+    #[test]
+    fn expands_virtual_disks_unless_they_are_broken() {
+        let fixture = |name: &str| Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/vdisk").join(name);
+        for (name, format) in [
+            ("disk.qcow2", Format::Qcow2),
+            ("disk.vhd", Format::Vhd),
+            ("disk.vhdx", Format::Vhdx),
+            ("disk.vmdk", Format::Vmdk),
+            ("disk.dmg", Format::Dmg),
+        ] {
+            assert_eq!(assess(&fixture(name)), (format, Verdict::Expanded(64 * 1024)), "{}", name);
+        }
+
+        // Truncated or corrupt: recognised, but it has to be converted by something that copes
+        for (name, len, format) in [
+            ("disk.qcow2", 100, Format::Qcow2),
+            ("disk.vhd", 512, Format::Vhd),
+            ("disk.vhdx", 128 * 1024, Format::Vhdx),
+            ("disk.vmdk", 64, Format::Vmdk),
+        ] {
+            let data = std::fs::read(fixture(name)).unwrap();
+            let (found, verdict) = assess_bytes(&format!("imgformat-cut-{}", name), &data[..len]);
+            assert_eq!(found, format, "{} cut to {} bytes", name, len);
+            assert!(matches!(&verdict, Verdict::NeedsConversion(text) if text.contains("qemu-img")), "{:?}", verdict);
+        }
+
+        // A DMG is found by its trailer, so corrupt the plist offset in there instead
+        let mut dmg = std::fs::read(fixture("disk.dmg")).unwrap();
+        let koly = dmg.len() - 512;
+        dmg[koly + 216..koly + 224].fill(0xFF);
+        let (found, verdict) = assess_bytes("imgformat-bad.dmg", &dmg);
+        assert_eq!(found, Format::Dmg);
+        assert!(matches!(&verdict, Verdict::NeedsConversion(text) if text.contains("hdiutil")), "{:?}", verdict);
+    }
+}
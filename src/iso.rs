@@ -10,15 +10,26 @@ use crossterm::{
 use std::fs;
 use std::io::{stdout, Write};
 
+use crate::{boot, checksum, imgformat, iso9660, parttable, signature};
+use crate::mode::Mode;
+
 /// To run this program, go to the README.md and follow the steps
 /// 
 /// This program will list all directories and files, letting you move around your computer's files like a file explorer
 /// When a file is chosen, it will ask if '/folder/file' is the correct path, and then proceed to targ.rs
 /// 
 /// Navigate using the arrow-keys to move up and down, and enter key to select
-pub fn main() -> std::io::Result<Option<std::path::PathBuf>> {
+/// In verify-only mode the chosen file is the image the drive is compared against
+pub fn main(mode: Mode) -> std::io::Result<Option<std::path::PathBuf>> {
+    let purpose = match mode {
+        Mode::Flash => "you wish to flash",
+        Mode::Verify => "you wish to verify the drive against",
+    };
+
     let mut selected = 0;
     let mut current_dir = std::env::current_dir()?; // Track current directory
+    // Partition preview of the highlighted file, kept until the highlight moves
+    let mut preview: Option<(std::path::PathBuf, Vec<String>)> = None;
 
     enable_raw_mode()?;
     let mut stdout = stdout();
@@ -54,7 +65,7 @@ pub fn main() -> std::io::Result<Option<std::path::PathBuf>> {
             cursor::MoveTo(0, 0),
             terminal::Clear(ClearType::FromCursorDown)
         )?;
-        println!("{}", "Please navigate to the file you wish to flash".with(Color::Blue));
+        println!("{}", format!("Please navigate to the file {}", purpose).with(Color::Blue));
 
         for (i, item) in menu_items.iter().enumerate() {
             execute!(stdout, cursor::MoveTo(0, (i + 1) as u16))?;
@@ -79,20 +90,34 @@ pub fn main() -> std::io::Result<Option<std::path::PathBuf>> {
             }
         }
 
+        // Preview pane on the right: the partition layout of the highlighted image
+        let highlighted = current_dir.join(&menu_items[selected]);
+        if preview.as_ref().map(|(path, _)| path) != Some(&highlighted) {
+            let lines = if highlighted.is_file() { parttable::preview(&highlighted) } else { Vec::new() };
+            preview = Some((highlighted, lines));
+        }
+        let (width, _) = terminal::size()?;
+        let column = width / 2;
+        if let Some((_, lines)) = &preview
+            && width >= 60
+        {
+            for (i, line) in lines.iter().enumerate() {
+                execute!(stdout, cursor::MoveTo(column, (i + 1) as u16))?;
+                let line: String = line.chars().take((width - column - 2) as usize).collect();
+                print!("{} {}", "│".with(Color::DarkGrey), line);
+            }
+        }
+
         stdout.flush()?;
 
         // Handle user input
         if let Event::Key(event) = event::read()? {
             match event.code {
                 KeyCode::Up => {
-                    if selected > 0 {
-                        selected -= 1;
-                    }
+                    selected = selected.saturating_sub(1);
                 }
-                KeyCode::Down => {
-                    if selected < menu_items.len().saturating_sub(1) {
-                        selected += 1;
-                    }
+                KeyCode::Down if selected < menu_items.len().saturating_sub(1) => {
+                    selected += 1;
                 }
                 KeyCode::Enter => {
                     let selected_item = &menu_items[selected];
@@ -115,9 +140,30 @@ pub fn main() -> std::io::Result<Option<std::path::PathBuf>> {
                         continue;
                     }
 
-                    // File selected: confirmation
+                    // File selected: check it against any published (and signed) checksum first,
+                    // so a corrupt or tampered download is caught before it costs a write cycle
+                    execute!(
+                        stdout,
+                        cursor::MoveTo(0, 0),
+                        terminal::Clear(ClearType::FromCursorDown)
+                    )?;
+                    let mut image_lines = Vec::new();
+                    match checksum::check(&path) {
+                        Some(result) => {
+                            image_lines.extend(result.signature.lines());
+                            image_lines.extend(result.lines());
+                        }
+                        None => {
+                            image_lines.extend(signature::unverified_lines("no signed checksum file found next to the image"));
+                            image_lines.push(checksum::missing_badge());
+                        }
+                    }
+                    image_lines.extend(image_details(&path));
+
+                    // Confirmation, defaulting to "No" for files that cannot be flashed as they are
                     let confirm_options = ["Yes", "No"];
-                    let mut confselected = 0;
+                    let flashable = imgformat::assess(&path).1.is_flashable();
+                    let mut confselected = if flashable { 0 } else { 1 };
 
                     loop {
                         execute!(
@@ -125,10 +171,16 @@ pub fn main() -> std::io::Result<Option<std::path::PathBuf>> {
                             cursor::MoveTo(0, 0),
                             terminal::Clear(ClearType::FromCursorDown)
                         )?;
-                        println!("Is '{}' the file you wish to flash?", path.display());
+                        println!("Is '{}' the file {}?", path.display(), purpose);
+
+                        for (i, line) in image_lines.iter().enumerate() {
+                            execute!(stdout, cursor::MoveTo(0, (i + 1) as u16))?;
+                            print!("{}", line);
+                        }
+                        let options_row = image_lines.len() + 2;
 
                         for (i, item) in confirm_options.iter().enumerate() {
-                            execute!(stdout, cursor::MoveTo(0, (i + 2) as u16))?;
+                            execute!(stdout, cursor::MoveTo(0, (i + options_row) as u16))?;
                             execute!(stdout, terminal::Clear(ClearType::CurrentLine))?;
                             if i == confselected {
                                 print!("  {}", item.on_white().black());
@@ -142,14 +194,10 @@ pub fn main() -> std::io::Result<Option<std::path::PathBuf>> {
                         if let Event::Key(ev) = event::read()? {
                             match ev.code {
                                 KeyCode::Up => {
-                                    if confselected > 0 {
-                                        confselected -= 1;
-                                    }
+                                    confselected = confselected.saturating_sub(1);
                                 }
-                                KeyCode::Down => {
-                                    if confselected < confirm_options.len() - 1 {
-                                        confselected += 1;
-                                    }
+                                KeyCode::Down if confselected < confirm_options.len() - 1 => {
+                                    confselected += 1;
                                 }
                                 KeyCode::Enter => {
                                     if confirm_options[confselected] == "Yes" {
@@ -171,3 +219,15 @@ pub fn main() -> std::io::Result<Option<std::path::PathBuf>> {
         }
     }
 }
+
+/// What the image says about itself, e.g. its format, the ISO9660 volume label, whether it
+/// will boot from a USB drive and the partitions it will create, shown before it is used
+pub fn image_details(path: &std::path::Path) -> Vec<String> {
+    let mut lines = vec![imgformat::badge(path)];
+    lines.extend(iso9660::read(path).map(|volume| volume.lines()).unwrap_or_default());
+    if let Some(boot) = boot::classify(path) {
+        lines.extend(boot.lines());
+    }
+    lines.extend(parttable::preview(path));
+    lines
+}
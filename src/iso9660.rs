@@ -0,0 +1,370 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::units::human;
+
+/// Logical sector size of ISO9660 and of UDF on optical media
+const SECTOR: u64 = 2048;
+/// The volume descriptor set starts at sector 16, after the system area
+const FIRST_DESCRIPTOR: u64 = 16;
+/// Give up looking for the set terminator after this many descriptors
+const MAX_DESCRIPTORS: u64 = 64;
+/// UDF Anchor Volume Descriptor Pointer location
+const UDF_ANCHOR: u64 = 256;
+
+/// What the volume descriptors of an ISO9660 / UDF image say about it
+#[derive(Debug, Clone, Default)]
+pub struct Volume {
+    /// Volume identifier from the Primary Volume Descriptor (d-characters, upper case)
+    pub label: Option<String>,
+    /// Volume identifier from the Joliet descriptor, which allows mixed case
+    pub joliet_label: Option<String>,
+    /// Logical volume identifier from the UDF descriptors
+    pub udf_label: Option<String>,
+    pub publisher: Option<String>,
+    pub application: Option<String>,
+    /// Volume creation date, e.g. "2024-04-24 11:29:08 UTC+00:00"
+    pub created: Option<String>,
+    /// Volume size in bytes as recorded by the PVD
+    pub size: Option<u64>,
+    pub udf: bool,
+    /// Platforms with a bootable El Torito entry, e.g. BIOS and EFI
+    pub el_torito: Vec<BootPlatform>,
+}
+
+/// Platform ID of an El Torito boot entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootPlatform {
+    Bios,
+    Efi,
+    Other(u8),
+}
+
+impl BootPlatform {
+    fn from_id(id: u8) -> BootPlatform {
+        match id {
+            0x00 => BootPlatform::Bios,
+            0xEF => BootPlatform::Efi,
+            other => BootPlatform::Other(other),
+        }
+    }
+}
+
+impl Volume {
+    /// Lines for the confirm screens
+    pub fn lines(&self) -> Vec<String> {
+        let unset = || "(not set)".to_string();
+        let mut label = self.label.clone().unwrap_or_else(unset);
+        let mut others = Vec::new();
+        if let Some(joliet) = self.joliet_label.as_ref().filter(|j| Some(*j) != self.label.as_ref()) {
+            others.push(format!("Joliet: {}", joliet));
+        }
+        if let Some(udf) = self.udf_label.as_ref().filter(|u| Some(*u) != self.label.as_ref()) {
+            others.push(format!("UDF: {}", udf));
+        }
+        if !others.is_empty() {
+            label = format!("{} ({})", label, others.join(", "));
+        }
+
+        let format = match (self.label.is_some() || self.size.is_some(), self.udf) {
+            (true, true) => "ISO9660 + UDF",
+            (true, false) if self.joliet_label.is_some() => "ISO9660 + Joliet",
+            (true, false) => "ISO9660",
+            (false, _) => "UDF",
+        };
+
+        vec![
+            format!("Volume label: {}", label),
+            format!("Publisher:    {}", self.publisher.clone().unwrap_or_else(unset)),
+            format!("Application:  {}", self.application.clone().unwrap_or_else(unset)),
+            format!("Created:      {}", self.created.clone().unwrap_or_else(unset)),
+            format!(
+                "Volume size:  {} [{}]",
+                self.size.map(|s| format!("{} ({} bytes)", human(s), s)).unwrap_or_else(unset),
+                format
+            ),
+        ]
+    }
+}
+
+fn read_sector(file: &mut File, lba: u64) -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; SECTOR as usize];
+    file.seek(SeekFrom::Start(lba * SECTOR)).ok()?;
+    file.read_exact(&mut buf).ok()?;
+    Some(buf)
+}
+
+/// Read the volume descriptors of `path`, or None if it is not an ISO9660 or UDF image
+pub fn read(path: &Path) -> Option<Volume> {
+    let mut file = File::open(path).ok()?;
+    let mut volume = Volume::default();
+    let mut found = false;
+
+    for lba in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + MAX_DESCRIPTORS {
+        let Some(desc) = read_sector(&mut file, lba) else { break };
+        match &desc[1..6] {
+            b"CD001" => {
+                found = true;
+                match desc[0] {
+                    1 => primary(&desc, &mut volume),
+                    0 if &desc[7..30] == b"EL TORITO SPECIFICATION" => {
+                        let catalog = u32::from_le_bytes(desc[0x47..0x4B].try_into().unwrap()) as u64;
+                        volume.el_torito = read_sector(&mut file, catalog)
+                            .map(|sector| boot_catalog(&sector))
+                            .unwrap_or_default();
+                    }
+                    2 if is_joliet(&desc) => joliet(&desc, &mut volume),
+                    // Volume descriptor set terminator; the UDF recognition sequence may follow
+                    _ => {}
+                }
+            }
+            b"NSR02" | b"NSR03" => volume.udf = true,
+            b"BEA01" | b"TEA01" | b"BOOT2" | b"CDW02" => {}
+            _ => break,
+        }
+    }
+
+    if volume.udf {
+        found = true;
+        volume.udf_label = udf_label(&mut file);
+    }
+
+    found.then_some(volume)
+}
+
+/// Fill in the fields of the Primary Volume Descriptor
+fn primary(desc: &[u8], volume: &mut Volume) {
+    volume.label = a_string(&desc[40..72]);
+    volume.publisher = a_string(&desc[318..446]).map(file_reference);
+    volume.application = a_string(&desc[574..702]).map(file_reference);
+    volume.created = date(&desc[813..830]);
+
+    // Both-endian fields: the little-endian half comes first
+    let blocks = u32::from_le_bytes(desc[80..84].try_into().unwrap()) as u64;
+    let block_size = u16::from_le_bytes(desc[128..130].try_into().unwrap()) as u64;
+    if blocks > 0 && block_size > 0 {
+        volume.size = Some(blocks * block_size);
+    }
+}
+
+/// A Supplementary Volume Descriptor is Joliet if its escape sequences select UCS-2 level 1, 2 or 3
+fn is_joliet(desc: &[u8]) -> bool {
+    matches!(&desc[88..91], b"%/@" | b"%/C" | b"%/E")
+}
+
+/// Joliet repeats the PVD fields in UCS-2, which keeps case and non-ASCII characters
+fn joliet(desc: &[u8], volume: &mut Volume) {
+    volume.joliet_label = ucs2_string(&desc[40..72]);
+    if let Some(publisher) = ucs2_string(&desc[318..446]) {
+        volume.publisher = Some(file_reference(publisher));
+    }
+    if let Some(application) = ucs2_string(&desc[574..702]) {
+        volume.application = Some(file_reference(application));
+    }
+}
+
+/// Platforms of the bootable entries in an El Torito boot catalog
+fn boot_catalog(catalog: &[u8]) -> Vec<BootPlatform> {
+    // Validation entry: header ID 1, platform of the default entry, key bytes 0x55 0xAA
+    if catalog[0] != 1 || catalog[30..32] != [0x55, 0xAA] {
+        return Vec::new();
+    }
+    let mut platforms = Vec::new();
+    let mut add = |platform: BootPlatform| {
+        if !platforms.contains(&platform) {
+            platforms.push(platform);
+        }
+    };
+    if catalog[32] == 0x88 {
+        add(BootPlatform::from_id(catalog[1]));
+    }
+
+    // Section headers (0x90, or 0x91 for the last one) each followed by their entries
+    let mut platform = catalog[1];
+    for entry in catalog[64..].chunks_exact(32) {
+        match entry[0] {
+            0x90 | 0x91 => platform = entry[1],
+            0x88 => add(BootPlatform::from_id(platform)),
+            // Non-bootable entries and selection criteria extensions
+            0x00 | 0x44 => {}
+            _ => break,
+        }
+    }
+    platforms
+}
+
+/// Logical volume identifier from the UDF Main Volume Descriptor Sequence
+fn udf_label(file: &mut File) -> Option<String> {
+    let anchor = read_sector(file, UDF_ANCHOR)?;
+    if tag_id(&anchor) != 2 {
+        return None;
+    }
+    let length = u32::from_le_bytes(anchor[16..20].try_into().unwrap()) as u64;
+    let location = u32::from_le_bytes(anchor[20..24].try_into().unwrap()) as u64;
+
+    let mut label = None;
+    for lba in location..location + (length / SECTOR).min(MAX_DESCRIPTORS) {
+        let desc = read_sector(file, lba)?;
+        match tag_id(&desc) {
+            // Primary Volume Descriptor: volume identifier, used if there is no logical one
+            1 if label.is_none() => label = d_string(&desc[24..56]),
+            // Logical Volume Descriptor: the name operating systems show
+            6 => return d_string(&desc[84..212]).or(label),
+            // Terminating Descriptor
+            8 => break,
+            _ => {}
+        }
+    }
+    label
+}
+
+fn tag_id(desc: &[u8]) -> u16 {
+    u16::from_le_bytes([desc[0], desc[1]])
+}
+
+/// Space-padded a-/d-characters
+fn a_string(field: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(field);
+    let text = text.trim_end_matches([' ', '\0']).trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Big-endian UCS-2, padded with spaces or NULs
+fn ucs2_string(field: &[u8]) -> Option<String> {
+    let units: Vec<u16> = field.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+    let text = String::from_utf16_lossy(&units);
+    let text = text.trim_end_matches([' ', '\0']).trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// UDF dstring: compression ID, characters, and the used length in the last byte
+fn d_string(field: &[u8]) -> Option<String> {
+    let used = (*field.last()? as usize).min(field.len() - 1);
+    if used < 2 {
+        return None;
+    }
+    let chars = &field[1..used];
+    let text = match field[0] {
+        8 => chars.iter().map(|&b| b as char).collect(),
+        16 => ucs2_string(chars)?,
+        _ => return None,
+    };
+    let text: &str = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// A leading underscore means "the text is in this file in the root directory"
+fn file_reference(text: String) -> String {
+    match text.strip_prefix('_') {
+        Some(name) if !name.is_empty() => format!("(see file {} on the image)", name),
+        _ => text,
+    }
+}
+
+/// 17-byte dec-datetime: "YYYYMMDDHHMMSScc" plus the UTC offset in 15 minute steps
+fn date(field: &[u8]) -> Option<String> {
+    let digits = std::str::from_utf8(&field[..16]).ok()?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) || digits.bytes().all(|b| b == b'0') {
+        return None;
+    }
+    let offset = field[16] as i8 as i32 * 15;
+    Some(format!(
+        "{}-{}-{} {}:{}:{} UTC{}{:02}:{:02}",
+        &digits[0..4],
+        &digits[4..6],
+        &digits[6..8],
+        &digits[8..10],
+        &digits[10..12],
+        &digits[12..14],
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testimg::{self, Iso};
+
+    #[test]
+    fn reads_primary_joliet_and_udf_descriptors() {
+        let path = testimg::file("iso9660-full.iso", &Iso { joliet: true, udf: true, ..Default::default() }.build());
+        let volume = read(&path).unwrap();
+        assert_eq!(volume.label.as_deref(), Some("UBUNTU 24.04 LTS"));
+        assert_eq!(volume.joliet_label.as_deref(), Some("Ubuntu 24.04 LTS"));
+        assert_eq!(volume.udf_label.as_deref(), Some("Ubuntu 24.04 LTS amd64"));
+        // Joliet's mixed-case fields win over the PVD's
+        assert_eq!(volume.publisher.as_deref(), Some("Canonical"));
+        assert_eq!(volume.application.as_deref(), Some("(see file APPINFO.TXT on the image)"));
+        assert_eq!(volume.created.as_deref(), Some("2024-04-24 11:29:08 UTC+02:00"));
+        assert_eq!(volume.size, Some(260 * 2048));
+        assert!(volume.udf);
+        let lines = volume.lines();
+        assert!(lines[0].contains("Joliet: Ubuntu 24.04 LTS, UDF: Ubuntu 24.04 LTS amd64"), "{}", lines[0]);
+        assert!(lines[4].ends_with("[ISO9660 + UDF]"), "{}", lines[4]);
+
+        let path = testimg::file("iso9660-plain.iso", &Iso::default().build());
+        let volume = read(&path).unwrap();
+        assert_eq!(volume.joliet_label, None);
+        assert_eq!(volume.udf_label, None);
+        assert_eq!(volume.publisher.as_deref(), Some("CANONICAL"));
+        assert!(!volume.udf);
+        assert!(volume.lines()[4].ends_with("[ISO9660]"));
+
+        let mut other = Iso::default().build();
+        other[16 * 2048 + 1..16 * 2048 + 6].copy_from_slice(b"CD002");
+        assert!(read(&testimg::file("iso9660-other.iso", &other)).is_none());
+    }
+
+    #[test]
+    fn reads_what_is_there_of_truncated_images() {
+        let image = Iso { joliet: true, udf: true, ..Default::default() }.build();
+        let cut = |len: usize| read(&testimg::file("iso9660-cut.iso", &image[..len]));
+
+        for len in [0, 2048, 16 * 2048, 17 * 2048 - 1] {
+            assert!(cut(len).is_none(), "{} bytes", len);
+        }
+        let volume = cut(17 * 2048).unwrap();
+        assert_eq!(volume.label.as_deref(), Some("UBUNTU 24.04 LTS"));
+        assert_eq!(volume.joliet_label, None);
+
+        // Cut inside the recognition sequence or the UDF descriptors: no UDF label, no panic
+        for len in [20 * 2048 + 7, 256 * 2048, 257 * 2048 + 5, 259 * 2048] {
+            let volume = cut(len).unwrap();
+            assert_eq!(volume.joliet_label.as_deref(), Some("Ubuntu 24.04 LTS"));
+            assert!(volume.udf_label.is_none() || len == 259 * 2048, "{} bytes", len);
+        }
+        assert_eq!(cut(259 * 2048).unwrap().udf_label.as_deref(), Some("Ubuntu 24.04 LTS amd64"));
+
+        // An anchor pointing far past the end
+        let mut far = image.clone();
+        far[256 * 2048 + 16..256 * 2048 + 24].fill(0xFF);
+        let volume = read(&testimg::file("iso9660-far.iso", &far)).unwrap();
+        assert!(volume.udf && volume.udf_label.is_none());
+    }
+
+    #[test]
+    fn lists_el_torito_boot_platforms() {
+        let platforms = |el_torito: Vec<u8>| {
+            let image = Iso { el_torito, ..Default::default() }.build();
+            read(&testimg::file("iso9660-boot.iso", &image)).unwrap().el_torito
+        };
+        assert_eq!(platforms(vec![0x00]), [BootPlatform::Bios]);
+        assert_eq!(platforms(vec![0x00, 0xEF, 0xEF]), [BootPlatform::Bios, BootPlatform::Efi]);
+        assert_eq!(platforms(vec![0xEF, 0x02]), [BootPlatform::Efi, BootPlatform::Other(0x02)]);
+        assert_eq!(platforms(Vec::new()), []);
+
+        let image = Iso { el_torito: vec![0x00, 0xEF], ..Default::default() }.build();
+        // The boot record comes right after the PVD, the catalog at sector 23
+        let mut broken = image.clone();
+        broken[23 * 2048 + 31] = 0;
+        assert_eq!(read(&testimg::file("iso9660-badkey.iso", &broken)).unwrap().el_torito, []);
+        let mut far = image.clone();
+        far[17 * 2048 + 0x47..17 * 2048 + 0x4B].fill(0xFF);
+        assert_eq!(read(&testimg::file("iso9660-farcat.iso", &far)).unwrap().el_torito, []);
+        let cut = read(&testimg::file("iso9660-nocat.iso", &image[..23 * 2048])).unwrap();
+        assert_eq!(cut.el_torito, []);
+    }
+}
@@ -0,0 +1,476 @@
+//! LZFSE and LZVN decompression, as used by Apple's compressed disk images
+//!
+//! Follows the block format of Apple's reference implementation: a stream is a
+//! sequence of blocks, each starting with a "bvx" magic, ended by "bvx$".
+
+use std::io;
+
+const END_OF_STREAM: u32 = u32::from_le_bytes(*b"bvx$");
+const UNCOMPRESSED: u32 = u32::from_le_bytes(*b"bvx-");
+const COMPRESSED_V1: u32 = u32::from_le_bytes(*b"bvx1");
+const COMPRESSED_V2: u32 = u32::from_le_bytes(*b"bvx2");
+const COMPRESSED_LZVN: u32 = u32::from_le_bytes(*b"bvxn");
+
+const L_SYMBOLS: usize = 20;
+const M_SYMBOLS: usize = 20;
+const D_SYMBOLS: usize = 64;
+const LITERAL_SYMBOLS: usize = 256;
+const L_STATES: usize = 64;
+const M_STATES: usize = 64;
+const D_STATES: usize = 256;
+const LITERAL_STATES: usize = 1024;
+
+const L_EXTRA_BITS: [u8; L_SYMBOLS] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 3, 5, 8];
+const L_BASE: [u32; L_SYMBOLS] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 20, 28, 60];
+const M_EXTRA_BITS: [u8; M_SYMBOLS] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 5, 8, 11];
+const M_BASE: [u32; M_SYMBOLS] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 24, 56, 312];
+
+fn invalid(why: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("LZFSE: {}", why))
+}
+
+fn le32(src: &[u8], offset: usize) -> io::Result<u32> {
+    src.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| invalid("truncated block header"))
+}
+
+/// Decompress a whole LZFSE stream, failing once `out` would grow past `limit` bytes
+///
+/// Block headers declare their own sizes, so the caller's expected output length
+/// is the only thing standing between a corrupt stream and an exhausted memory.
+pub fn decompress(src: &[u8], out: &mut Vec<u8>, limit: usize) -> io::Result<()> {
+    let mut pos = 0;
+    loop {
+        let magic = le32(src, pos)?;
+        let sized = matches!(magic, UNCOMPRESSED | COMPRESSED_LZVN | COMPRESSED_V2);
+        if sized && le32(src, pos + 4)? as usize > limit.saturating_sub(out.len()) {
+            return Err(invalid("block decodes past the expected size"));
+        }
+        match magic {
+            END_OF_STREAM => return Ok(()),
+            UNCOMPRESSED => {
+                let n = le32(src, pos + 4)? as usize;
+                let data = src.get(pos + 8..pos + 8 + n).ok_or_else(|| invalid("truncated raw block"))?;
+                out.extend_from_slice(data);
+                pos += 8 + n;
+            }
+            COMPRESSED_LZVN => {
+                let n_raw = le32(src, pos + 4)? as usize;
+                let n_payload = le32(src, pos + 8)? as usize;
+                let payload = src.get(pos + 12..pos + 12 + n_payload).ok_or_else(|| invalid("truncated LZVN block"))?;
+                let start = out.len();
+                lzvn_decode(payload, out, start + n_raw)?;
+                pos += 12 + n_payload;
+            }
+            COMPRESSED_V2 => pos += decode_v2(&src[pos..], out)?,
+            COMPRESSED_V1 => return Err(invalid("version 1 blocks are not supported")),
+            _ => return Err(invalid("bad block magic")),
+        }
+    }
+}
+
+/// One entry of a literal decoding table
+#[derive(Clone, Copy, Default)]
+struct Entry {
+    bits: u8,
+    symbol: u8,
+    delta: i32,
+}
+
+/// One entry of an L, M or D decoding table: state bits plus extra value bits
+#[derive(Clone, Copy, Default)]
+struct ValueEntry {
+    total_bits: u8,
+    value_bits: u8,
+    delta: i32,
+    base: u32,
+}
+
+/// Spread each symbol over `freq[symbol]` states, calling `f(state, symbol, bits, delta)`
+fn build_table(nstates: usize, freq: &[u16], mut f: impl FnMut(usize, usize, u8, i32)) -> io::Result<()> {
+    let n_clz = (nstates as u32).leading_zeros() as i32;
+    let mut state = 0;
+    for (symbol, &freq) in freq.iter().enumerate() {
+        let freq = freq as i32;
+        if freq == 0 {
+            continue;
+        }
+        if state + freq as usize > nstates {
+            return Err(invalid("frequency table overflows the state count"));
+        }
+        // Shift so that nstates <= (freq << k) < 2 * nstates
+        let k = (freq as u32).leading_zeros() as i32 - n_clz;
+        let j0 = ((2 * nstates as i32) >> k) - freq;
+        for j in 0..freq {
+            if j < j0 {
+                f(state, symbol, k as u8, ((freq + j) << k) - nstates as i32);
+            } else {
+                f(state, symbol, (k - 1) as u8, (j - j0) << (k - 1));
+            }
+            state += 1;
+        }
+    }
+    Ok(())
+}
+
+fn literal_table(freq: &[u16]) -> io::Result<Vec<Entry>> {
+    let mut table = vec![Entry::default(); LITERAL_STATES];
+    build_table(LITERAL_STATES, freq, |state, symbol, bits, delta| {
+        table[state] = Entry { bits, symbol: symbol as u8, delta };
+    })?;
+    Ok(table)
+}
+
+fn value_table(nstates: usize, freq: &[u16], extra_bits: &[u8], base: &[u32]) -> io::Result<Vec<ValueEntry>> {
+    let mut table = vec![ValueEntry::default(); nstates];
+    build_table(nstates, freq, |state, symbol, bits, delta| {
+        table[state] = ValueEntry {
+            total_bits: bits + extra_bits[symbol],
+            value_bits: extra_bits[symbol],
+            delta,
+            base: base[symbol],
+        };
+    })?;
+    Ok(table)
+}
+
+/// Bits read backwards from the end of a payload, as the encoder wrote them forwards
+struct BitStream<'a> {
+    buf: &'a [u8],
+    /// Bytes of `buf` not yet loaded into the accumulator
+    pos: usize,
+    accum: u64,
+    accum_bits: u32,
+}
+
+impl<'a> BitStream<'a> {
+    /// `extra_bits` (-7..=0) is how many bits of the last byte are padding
+    fn new(buf: &'a [u8], extra_bits: i32) -> io::Result<BitStream<'a>> {
+        let take = if extra_bits != 0 { 8 } else { 7 };
+        if buf.len() < take {
+            return Err(invalid("payload too short"));
+        }
+        let pos = buf.len() - take;
+        let mut bytes = [0u8; 8];
+        bytes[..take].copy_from_slice(&buf[pos..]);
+        let accum = u64::from_le_bytes(bytes);
+        let accum_bits = (take as i32 * 8 + extra_bits) as u32;
+        if !(56..64).contains(&accum_bits) || accum >> accum_bits != 0 {
+            return Err(invalid("bad bit stream start"));
+        }
+        Ok(BitStream { buf, pos, accum, accum_bits })
+    }
+
+    /// Top up the accumulator to at least 56 bits
+    fn refill(&mut self) -> io::Result<()> {
+        let bits = (63 - self.accum_bits) & !7;
+        let bytes = (bits / 8) as usize;
+        if bytes > self.pos {
+            return Err(invalid("bit stream overrun"));
+        }
+        self.pos -= bytes;
+        let mut incoming = [0u8; 8];
+        incoming[..bytes].copy_from_slice(&self.buf[self.pos..self.pos + bytes]);
+        let incoming = u64::from_le_bytes(incoming);
+        if bits > 0 {
+            self.accum = (self.accum << bits) | incoming;
+            self.accum_bits += bits;
+        }
+        Ok(())
+    }
+
+    fn pull(&mut self, n: u32) -> u64 {
+        self.accum_bits -= n;
+        let result = self.accum >> self.accum_bits;
+        self.accum &= (1u64 << self.accum_bits) - 1;
+        result
+    }
+}
+
+fn decode_literal(state: &mut usize, table: &[Entry], bits: &mut BitStream) -> u8 {
+    let entry = table[*state];
+    *state = (entry.delta + bits.pull(entry.bits as u32) as i32) as usize;
+    entry.symbol
+}
+
+fn decode_value(state: &mut usize, table: &[ValueEntry], bits: &mut BitStream) -> u32 {
+    let entry = table[*state];
+    let raw = bits.pull(entry.total_bits as u32);
+    *state = (entry.delta + (raw >> entry.value_bits) as i32) as usize;
+    entry.base + (raw & ((1u64 << entry.value_bits) - 1)) as u32
+}
+
+/// Fields of the v2 header, packed into three 64-bit words
+fn field(word: u64, offset: u32, bits: u32) -> u64 {
+    (word >> offset) & ((1u64 << bits) - 1)
+}
+
+/// Decode a v2 (FSE) block and return its length in `src`
+fn decode_v2(src: &[u8], out: &mut Vec<u8>) -> io::Result<usize> {
+    let word = |i: usize| -> io::Result<u64> {
+        src.get(8 + i * 8..16 + i * 8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+            .ok_or_else(|| invalid("truncated v2 header"))
+    };
+    let n_raw = le32(src, 4)? as usize;
+    let (v0, v1, v2) = (word(0)?, word(1)?, word(2)?);
+
+    let n_literals = field(v0, 0, 20) as usize;
+    let n_literal_payload = field(v0, 20, 20) as usize;
+    let n_matches = field(v0, 40, 20) as usize;
+    let literal_bits = field(v0, 60, 3) as i32 - 7;
+    let literal_state = [field(v1, 0, 10), field(v1, 10, 10), field(v1, 20, 10), field(v1, 30, 10)];
+    let n_lmd_payload = field(v1, 40, 20) as usize;
+    let lmd_bits = field(v1, 60, 3) as i32 - 7;
+    let header_size = field(v2, 0, 32) as usize;
+    let mut l_state = field(v2, 32, 10) as usize;
+    let mut m_state = field(v2, 42, 10) as usize;
+    let mut d_state = field(v2, 52, 10) as usize;
+
+    // Frequency tables, in a variable-length code between the fixed fields and header_size
+    let header = src.get(32..header_size).ok_or_else(|| invalid("truncated frequency tables"))?;
+    let mut freq = [0u16; L_SYMBOLS + M_SYMBOLS + D_SYMBOLS + LITERAL_SYMBOLS];
+    let mut accum: u32 = 0;
+    let mut accum_bits = 0;
+    let mut pos = 0;
+    for f in freq.iter_mut() {
+        while pos < header.len() && accum_bits + 8 <= 32 {
+            accum |= (header[pos] as u32) << accum_bits;
+            accum_bits += 8;
+            pos += 1;
+        }
+        let (value, nbits) = freq_value(accum);
+        if nbits > accum_bits {
+            return Err(invalid("bad frequency table"));
+        }
+        accum >>= nbits;
+        accum_bits -= nbits;
+        *f = value;
+    }
+    if accum_bits >= 8 || pos != header.len() {
+        return Err(invalid("bad frequency table"));
+    }
+    let (l_freq, rest) = freq.split_at(L_SYMBOLS);
+    let (m_freq, rest) = rest.split_at(M_SYMBOLS);
+    let (d_freq, literal_freq) = rest.split_at(D_SYMBOLS);
+
+    let literal_payload = src
+        .get(header_size..header_size + n_literal_payload)
+        .ok_or_else(|| invalid("truncated literal payload"))?;
+    let lmd_start = header_size + n_literal_payload;
+    let lmd_payload = src.get(lmd_start..lmd_start + n_lmd_payload).ok_or_else(|| invalid("truncated LMD payload"))?;
+
+    // Literals: four interleaved FSE states
+    let table = literal_table(literal_freq)?;
+    let mut literals = vec![0u8; n_literals.div_ceil(4) * 4];
+    let mut bits = BitStream::new(literal_payload, literal_bits)?;
+    let mut states = literal_state.map(|s| s as usize);
+    if states.iter().any(|&s| s >= LITERAL_STATES) {
+        return Err(invalid("bad literal state"));
+    }
+    for chunk in literals.chunks_exact_mut(4) {
+        bits.refill()?;
+        for (byte, state) in chunk.iter_mut().zip(states.iter_mut()) {
+            *byte = decode_literal(state, &table, &mut bits);
+        }
+    }
+
+    // Matches: literal length, match length and distance triples
+    let l_table = value_table(L_STATES, l_freq, &L_EXTRA_BITS, &L_BASE)?;
+    let m_table = value_table(M_STATES, m_freq, &M_EXTRA_BITS, &M_BASE)?;
+    let (d_extra, d_base) = distance_tables();
+    let d_table = value_table(D_STATES, d_freq, &d_extra, &d_base)?;
+    if l_state >= L_STATES || m_state >= M_STATES || d_state >= D_STATES {
+        return Err(invalid("bad LMD state"));
+    }
+
+    let end = out.len() + n_raw;
+    let mut bits = BitStream::new(lmd_payload, lmd_bits)?;
+    let mut literal = 0;
+    let mut distance = 0usize;
+    for _ in 0..n_matches {
+        bits.refill()?;
+        let l = decode_value(&mut l_state, &l_table, &mut bits) as usize;
+        let m = decode_value(&mut m_state, &m_table, &mut bits) as usize;
+        let d = decode_value(&mut d_state, &d_table, &mut bits) as usize;
+        if d != 0 {
+            distance = d;
+        }
+
+        let lits = literals.get(literal..literal + l).ok_or_else(|| invalid("literal overrun"))?;
+        out.extend_from_slice(lits);
+        literal += l;
+        copy_match(out, distance, m)?;
+        if out.len() > end {
+            return Err(invalid("block decodes past its size"));
+        }
+    }
+    if out.len() != end {
+        return Err(invalid("block decodes short of its size"));
+    }
+
+    Ok(lmd_start + n_lmd_payload)
+}
+
+/// Variable-length frequency code: returns (value, bits used)
+fn freq_value(bits: u32) -> (u16, u32) {
+    const NBITS: [u8; 32] = [
+        2, 3, 2, 5, 2, 3, 2, 8, 2, 3, 2, 5, 2, 3, 2, 14, 2, 3, 2, 5, 2, 3, 2, 8, 2, 3, 2, 5, 2, 3, 2, 14,
+    ];
+    const VALUE: [u8; 32] = [
+        0, 2, 1, 4, 0, 3, 1, 0, 0, 2, 1, 5, 0, 3, 1, 0, 0, 2, 1, 6, 0, 3, 1, 0, 0, 2, 1, 7, 0, 3, 1, 0,
+    ];
+    let b = (bits & 31) as usize;
+    match NBITS[b] {
+        8 => (8 + ((bits >> 4) & 0xF) as u16, 8),
+        14 => (24 + ((bits >> 4) & 0x3FF) as u16, 14),
+        n => (VALUE[b] as u16, n as u32),
+    }
+}
+
+/// Distance symbols come in groups of four sharing a number of extra bits
+fn distance_tables() -> ([u8; D_SYMBOLS], [u32; D_SYMBOLS]) {
+    let mut extra = [0u8; D_SYMBOLS];
+    let mut base = [0u32; D_SYMBOLS];
+    let mut next = 0u32;
+    for symbol in 0..D_SYMBOLS {
+        let bits = (symbol / 4) as u8;
+        extra[symbol] = bits;
+        base[symbol] = next;
+        next += 1 << bits;
+    }
+    (extra, base)
+}
+
+/// Copy `len` bytes from `distance` bytes back; the ranges may overlap
+fn copy_match(out: &mut Vec<u8>, distance: usize, len: usize) -> io::Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    if distance == 0 || distance > out.len() {
+        return Err(invalid("match distance out of range"));
+    }
+    let start = out.len() - distance;
+    for i in 0..len {
+        out.push(out[start + i]);
+    }
+    Ok(())
+}
+
+/// Decode an LZVN payload until the end-of-stream opcode, stopping at `end` bytes of output
+/// and failing if an opcode runs past it
+fn lzvn_decode(src: &[u8], out: &mut Vec<u8>, end: usize) -> io::Result<()> {
+    let byte = |i: usize| src.get(i).copied().ok_or_else(|| invalid("truncated LZVN stream"));
+    let mut pos = 0;
+    let mut distance = 0usize;
+
+    while out.len() < end {
+        let opc = byte(pos)?;
+        // (opcode length, literals, match length, new distance)
+        let (len, literals, matched, new_distance) = match opc {
+            0x06 => return Ok(()),
+            0x0E | 0x16 => (1, 0, 0, None),
+            0xE0 => (2, byte(pos + 1)? as usize + 16, 0, None),
+            0xE1..=0xEF => (1, (opc & 0xF) as usize, 0, None),
+            0xF0 => (2, 0, byte(pos + 1)? as usize + 16, None),
+            0xF1..=0xFF => (1, 0, (opc & 0xF) as usize, None),
+            0xA0..=0xBF => {
+                let ext = u16::from_le_bytes([byte(pos + 1)?, byte(pos + 2)?]) as usize;
+                let l = ((opc >> 3) & 3) as usize;
+                let m = ((((opc & 7) as usize) << 2) | (ext & 3)) + 3;
+                (3, l, m, Some(ext >> 2))
+            }
+            0x70..=0x7F | 0xD0..=0xDF => return Err(invalid("undefined LZVN opcode")),
+            _ => {
+                let l = (opc >> 6) as usize;
+                let m = ((opc >> 3) & 7) as usize + 3;
+                match opc & 7 {
+                    // Large distance: 16-bit little-endian
+                    7 => (3, l, m, Some(u16::from_le_bytes([byte(pos + 1)?, byte(pos + 2)?]) as usize)),
+                    // Previous distance; below 0x40 these are undefined
+                    6 if opc >= 0x40 => (1, l, m, None),
+                    6 => return Err(invalid("undefined LZVN opcode")),
+                    // Small distance: 3 bits in the opcode and one byte
+                    _ => (2, l, m, Some((((opc & 7) as usize) << 8) | byte(pos + 1)? as usize)),
+                }
+            }
+        };
+        pos += len;
+
+        let lits = src.get(pos..pos + literals).ok_or_else(|| invalid("truncated LZVN literals"))?;
+        out.extend_from_slice(lits);
+        pos += literals;
+
+        if let Some(d) = new_distance {
+            distance = d;
+        }
+        copy_match(out, distance, matched)?;
+        if out.len() > end {
+            return Err(invalid("block decodes past its size"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Streams encoded by testdata/lzfse/make_vectors.py, each holding expected.txt
+    const EXPECTED: &[u8] = include_bytes!("../testdata/lzfse/expected.txt");
+    const STREAMS: [(&str, &[u8]); 3] = [
+        ("bvx-", include_bytes!("../testdata/lzfse/bvx-.lzfse")),
+        ("bvxn", include_bytes!("../testdata/lzfse/bvxn.lzfse")),
+        ("bvx2", include_bytes!("../testdata/lzfse/bvx2.lzfse")),
+    ];
+
+    #[test]
+    fn decodes_each_block_kind() {
+        for (name, stream) in STREAMS {
+            let mut out = Vec::new();
+            decompress(stream, &mut out, EXPECTED.len()).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert!(out == EXPECTED, "{} decodes to the wrong data", name);
+        }
+    }
+
+    #[test]
+    fn decodes_a_stream_of_several_blocks() {
+        let mut out = Vec::new();
+        decompress(include_bytes!("../testdata/lzfse/multi.lzfse"), &mut out, EXPECTED.len() * 3).unwrap();
+        assert!(out == EXPECTED.repeat(3));
+    }
+
+    #[test]
+    fn stops_at_the_expected_size() {
+        // Every block kind, one byte short of room
+        for (name, stream) in STREAMS {
+            assert!(decompress(stream, &mut Vec::new(), EXPECTED.len() - 1).is_err(), "{} overran its limit", name);
+        }
+        let multi = include_bytes!("../testdata/lzfse/multi.lzfse");
+        assert!(decompress(multi, &mut Vec::new(), EXPECTED.len() * 2).is_err());
+
+        // A block header claiming 4 GiB fails before anything is decoded
+        let mut huge = b"bvxn".to_vec();
+        huge.extend_from_slice(&u32::MAX.to_le_bytes());
+        huge.extend_from_slice(&1u32.to_le_bytes());
+        huge.extend_from_slice(b"\x06bvx$");
+        let mut out = Vec::new();
+        assert!(decompress(&huge, &mut out, 1 << 20).is_err());
+        assert!(out.capacity() == 0);
+
+        // An LZVN block that decodes to more than its header says
+        let mut lying = STREAMS[1].1.to_vec();
+        lying[4..8].copy_from_slice(&10u32.to_le_bytes());
+        assert!(decompress(&lying, &mut Vec::new(), EXPECTED.len()).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_streams() {
+        for (name, stream) in STREAMS {
+            for len in 0..stream.len() {
+                assert!(decompress(&stream[..len], &mut Vec::new(), EXPECTED.len()).is_err(), "{} cut to {} bytes", name, len);
+            }
+        }
+    }
+}
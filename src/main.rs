@@ -1,7 +1,7 @@
-/// # Main.rs
-/// 
-/// main.rs is the backbone for all other files' external functions
-/// It includes the FFI C functions, and calls all rust external functions
+//! # Main.rs
+//!
+//! main.rs is the backbone for all other files' external functions
+//! It includes the FFI C functions, and calls all rust external functions
 
 
 use std::ffi::CString;
@@ -10,7 +10,8 @@ use std::io::{
     Write,
     self
 };
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_int, c_void};
+use std::path::Path;
 use crossterm::{
     execute,
     cursor,
@@ -25,85 +26,312 @@ use std::{
     time::Duration
 };
 
+mod boot;
+mod busy;
+mod cache;
+mod checksum;
+mod cli;
+mod devread;
+mod digest;
+mod dmg;
+mod hotplug;
+mod identity;
+mod imgformat;
 mod iso;
+mod iso9660;
+mod lzfse;
+mod mismatch;
+mod mode;
+mod parttable;
+mod report;
+mod signature;
+mod source;
+mod sysdisk;
 mod targ;
+#[cfg(test)]
+mod testimg;
+mod units;
+mod vdisk;
 mod flash_confirm;
+mod fsprobe;
+mod verify;
 mod verify_confirm;
 
 //Extern to initialize all C functions
 unsafe extern "C" {
-    fn flash(iso_path: *const c_char, dev_name: *const c_char);
-    fn verify(iso_path: *const c_char, dev_name: *const c_char) -> bool;
+    fn flash(
+        iso_path: *const c_char,
+        dev_name: *const c_char,
+        on_chunk: Option<extern "C" fn(*mut c_void, *const u8, usize)>,
+        ctx: *mut c_void,
+    ) -> c_int;
+
+    fn flash_stream(
+        read_chunk: extern "C" fn(*mut c_void, *mut u8, usize) -> isize,
+        read_ctx: *mut c_void,
+        total_size: i64,
+        dev_name: *const c_char,
+        on_chunk: Option<extern "C" fn(*mut c_void, *const u8, usize)>,
+        ctx: *mut c_void,
+    ) -> c_int;
+}
+
+/// Called by flash.c with every chunk it reads from the image, so the image
+/// digest is ready for verification without reading the image again
+extern "C" fn hash_chunk(ctx: *mut c_void, buf: *const u8, len: usize) {
+    let digest = unsafe { &mut *(ctx as *mut digest::Digest) };
+    digest.update(unsafe { std::slice::from_raw_parts(buf, len) });
+}
+
+/// Called by flash.c to fetch the next chunk of an image that is expanded on the Rust side
+extern "C" fn read_source(ctx: *mut c_void, buf: *mut u8, len: usize) -> isize {
+    let source = unsafe { &mut *(ctx as *mut source::Source) };
+    let buf = unsafe { std::slice::from_raw_parts_mut(buf, len) };
+
+    // Fill the whole buffer so the device still gets large writes
+    let mut filled = 0;
+    while filled < len {
+        match io::Read::read(source, &mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("\nFailed to expand the image: {e}");
+                return -1;
+            }
+        }
+    }
+    filled as isize
 }
 
+use mode::Mode;
+
 fn main() -> Result<()> {
-    let Some(iso_path) = iso::main()? else {
+    let args = match cli::parse() {
+        Ok(args) => args,
+        Err(why) => {
+            eprintln!("{why}\n\n{}", cli::USAGE);
+            exit(2);
+        }
+    };
+
+    // Cache maintenance runs without the menus
+    if let Some(command) = args.cache {
+        let result = match command {
+            cli::CacheCommand::Show => cache::show(),
+            cli::CacheCommand::Clear => cache::clear(),
+        };
+        if let Err(why) = result {
+            eprintln!("Digest cache error: {why}");
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    // Every run ends with a machine-readable report, however far it got
+    let mut report = report::RunReport::new();
+    let result = session(&args, &mut report);
+    if let Err(why) = &result {
+        report.error(why.to_string());
+    }
+    match report.write(args.report.as_deref()) {
+        Ok(path) => println!("\nReport written to {}", path.display()),
+        Err(why) => eprintln!("\nFailed to write the run report: {why}"),
+    }
+
+    disable_raw_mode()?;
+    execute!(std::io::stdout(), cursor::Show)?;
+    result
+}
+
+/// Ask for the mode, image and drive, then run, recording each choice in `report`
+fn session(args: &cli::Args, report: &mut report::RunReport) -> Result<()> {
+    // Ask what to do unless it was given on the command line
+    let Some(mode) = args.mode.or_else(mode::menu) else {
+        report.cancelled();
+        return Ok(());
+    };
+    report.chose_mode(mode);
+
+    let Some(iso_path) = iso::main(mode)? else {
         eprintln!("\nFailed to get ISO file");
+        report.cancelled();
         return Ok(());
     };
+    report.chose_image(&iso_path);
 
-    let dev_name = match targ::menu() {
-        Ok(Some(dev)) => dev,
+    let drive = match targ::menu(args.allow_system_disk) {
+        Ok(Some(drive)) => drive,
         Ok(None) => {
             eprintln!("NULL value found at dev_path: could not unwrap");
+            report.cancelled();
             return Ok(());
         }
         Err(why) => {
             eprintln!("Error getting device target: {why}");
+            report.error(format!("could not list the drives: {why}"));
             return Ok(());
         }
     };
+    report.chose_device(&drive.path);
 
     // Clear terminal
     println!("\x1B[H\x1B[2J");
 
-    let mut stdout = std::io::stdout();
+    execute!(std::io::stdout(), cursor::Hide)?;
 
-    execute!(stdout, cursor::Hide)?;
+    // The identity was pinned when the drive was listed, so a different one showing up
+    // under the same name, even while the menu was open, is caught
+    run(mode, &iso_path, &drive.path, &drive.identity, report)
+}
 
-    let confirms_flash = flash_confirm::menu(&iso_path.display().to_string(), &dev_name);
-    if !confirms_flash {
-        disable_raw_mode()?;
-        execute!(stdout, cursor::Show)?;
-        exit(0);
-    }
+/// Flash (in flash mode) and verify, recording each phase in `report`
+fn run(
+    mode: Mode,
+    iso_path: &Path,
+    dev_name: &str,
+    identity: &identity::Identity,
+    report: &mut report::RunReport,
+) -> Result<()> {
+    //Convert iso_path and dev_name into a C string, to give the arguments for the C functions
+    let iso_c = CString::new(iso_path.to_string_lossy().into_owned()).unwrap();
+    let dev_c = CString::new(dev_name).unwrap();
 
-    let flash_time = Instant::now();
+    // Image digest computed during the flash pass, if any
+    let mut image_digest = None;
 
-    //Convert iso_path and dev_name into a C string, to give the arguments for flash.c function
-    let iso_c = CString::new(iso_path.to_string_lossy().into_owned()).unwrap();
-    let dev_c = CString::new(dev_name.as_str()).unwrap();
+    // Verify-only mode never writes: go straight to the comparison
+    if mode == Mode::Flash {
+        let confirms_flash = flash_confirm::menu(
+            &iso_path.display().to_string(),
+            dev_name,
+            &iso::image_details(iso_path),
+            &[
+                targ::link_lines(Path::new("/"), dev_name, report.image_size()),
+                parttable::contents(Path::new(dev_name)),
+                busy::lines(Path::new("/"), dev_name),
+            ]
+            .concat(),
+        );
+        if !confirms_flash {
+            report.cancelled();
+            return Ok(());
+        }
 
-    unsafe {
-        //Call the flash function
-        flash(iso_c.as_ptr(), dev_c.as_ptr());
-    }
+        if let Err(why) = identity.check(Path::new("/"), dev_name) {
+            eprintln!("{why}\nNothing was written.");
+            report.error(format!("aborted before writing: {why}"));
+            return Ok(());
+        }
 
-    let flash_time_taken = flash_time.elapsed();
-    io::stdout().flush().unwrap();
-    println!("\nFinished flashing in {:.2} seconds", flash_time_taken.as_secs_f64());
+        let mut image = match source::open(iso_path) {
+            Ok(image) => image,
+            Err(why) => {
+                eprintln!("Cannot read the image: {why}");
+                report.error(format!("cannot read the image: {why}"));
+                return Ok(());
+            }
+        };
 
-    thread::sleep(Duration::from_secs(3));
+        let flash_time = Instant::now();
 
-    let confirms_verify: bool = verify_confirm::menu(&iso_path.display().to_string(), &dev_name);
-    if !confirms_verify {
-        disable_raw_mode()?;
-        execute!(stdout, cursor::Show)?;
-        exit(0);
-    }
+        let mut flash_digest = digest::Digest::new(verify::ALGO).ok();
+        let (on_chunk, ctx): (Option<extern "C" fn(*mut c_void, *const u8, usize)>, *mut c_void) =
+            match flash_digest.as_mut() {
+                Some(d) => (Some(hash_chunk), d as *mut digest::Digest as *mut c_void),
+                None => (None, std::ptr::null_mut()),
+            };
+
+        let status = if image.expanded {
+            // Virtual disk: Rust expands it to raw data, flash.c writes it
+            let total = image.size as i64;
+            let read_ctx = &mut image as *mut source::Source as *mut c_void;
+            unsafe { flash_stream(read_source, read_ctx, total, dev_c.as_ptr(), on_chunk, ctx) }
+        } else {
+            unsafe {
+                //Call the flash function
+                flash(iso_c.as_ptr(), dev_c.as_ptr(), on_chunk, ctx)
+            }
+        };
+
+        let flash_time_taken = flash_time.elapsed();
+        // How much reached the drive before a failure is not known
+        report.phase("flash", flash_time_taken, (status == 0).then(|| report.image_size()));
+        io::stdout().flush().unwrap();
+
+        if status != 0 {
+            eprintln!("\nFlashing failed after {:.2} seconds", flash_time_taken.as_secs_f64());
+            report.error("flash failed: could not write the image to the device");
+            return Ok(());
+        }
+
+        image_digest = flash_digest.and_then(|d| d.finish().ok());
+        if let Some(digest) = &image_digest {
+            // The cache holds digests of image files, not of the raw data inside a virtual disk
+            if !image.expanded {
+                cache::store(iso_path, verify::ALGO, digest);
+            }
+            report.image_digest(digest.clone());
+        }
+
+        println!("\nFinished flashing in {:.2} seconds", flash_time_taken.as_secs_f64());
 
-    let is_verified: bool;
+        thread::sleep(Duration::from_secs(3));
 
-    unsafe {  
-        is_verified = verify(iso_c.as_ptr(), dev_c.as_ptr());
+        let confirms_verify: bool = verify_confirm::menu(&iso_path.display().to_string(), dev_name);
+        if !confirms_verify {
+            return Ok(());
+        }
     }
+
+    if let Err(why) = identity.check(Path::new("/"), dev_name) {
+        eprintln!("\n{why}\nNot verifying a different drive.");
+        report.error(format!("aborted before verifying: {why}"));
+        return Ok(());
+    }
+
+    let verify_time = Instant::now();
+    let verify_result = verify::run(iso_path, dev_name, image_digest);
+    report.phase("verify", verify_time.elapsed(), verify_result.is_ok().then(|| report.image_size()));
+    let is_verified = match verify_result {
+        Ok(outcome) => {
+            println!("Image  {}: {}", verify::ALGO, digest::to_hex(&outcome.image_digest));
+            println!("Device {}: {}", verify::ALGO, digest::to_hex(&outcome.device_digest));
+            println!("Device read: {}", outcome.device_read);
+            let matched = outcome.matched;
+            report.verified(outcome);
+            matched
+        }
+        Err(why) => {
+            eprintln!("\nError during verification: {why}");
+            report.error(format!("verification failed: {why}"));
+            false
+        }
+    };
+
     if is_verified {
         println!("\nVerification success");
     } else {
         println!("\nVerification failed");
+
+        // Find out where the drive differs from the image
+        println!("Locating the mismatching regions...");
+        let compare_time = Instant::now();
+        match mismatch::compare(iso_path, dev_name) {
+            Ok(mismatches) => {
+                report.phase("compare", compare_time.elapsed(), Some(report.image_size()));
+                println!("\n{}", mismatches.summary());
+                if let Err(why) = mismatch::offer_save(&mismatches) {
+                    eprintln!("Failed to save the verification report: {why}");
+                }
+                report.mismatch(mismatches);
+            }
+            Err(why) => {
+                eprintln!("Could not compare the image and the device block by block: {why}");
+                report.error(format!("block comparison failed: {why}"));
+            }
+        }
     }
 
-    disable_raw_mode()?;
-    execute!(stdout, cursor::Show)?;
     Ok(())
 }
@@ -0,0 +1,585 @@
+use crossterm::{
+    cursor,
+    event::{
+        self,
+        Event,
+        KeyCode
+    },
+    execute,
+    style::Stylize,
+    terminal::{
+        self,
+        ClearType,
+        disable_raw_mode,
+        enable_raw_mode
+    },
+};
+use std::fs;
+use std::io::{self, Read, Write, stdout};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::devread::{self, DeviceReader, ReadMethod};
+use crate::source;
+use crate::units::human;
+
+/// Size of the blocks the image and device are compared in
+const BLOCK_SIZE: usize = 1024 * 1024;
+/// How many blocks are read per call, to keep the number of syscalls down
+const BLOCKS_PER_READ: usize = 16;
+/// Ranges listed on screen; the saved report always lists all of them
+const MAX_RANGES_SHOWN: usize = 10;
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+/// A run of consecutive differing blocks, as byte offsets [start, end)
+#[derive(Debug, Clone, Copy)]
+pub struct Range {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// How the differing blocks are laid out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    /// No block differs (the digests disagreed for another reason, e.g. a read error)
+    Identical,
+    /// Everything from `start` to the end of the image differs
+    Tail { start: u64 },
+    /// A handful of separate regions
+    Clustered(usize),
+    /// Many small regions spread over the image
+    Scattered(usize),
+}
+
+/// Result of a block-by-block comparison of an image and a device
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub image: PathBuf,
+    pub device: String,
+    /// How the device was read back
+    pub device_read: ReadMethod,
+    pub image_size: u64,
+    pub block_size: u64,
+    pub blocks_total: u64,
+    pub blocks_differing: u64,
+    /// Differing blocks whose device contents were all zeros
+    pub blocks_zero: u64,
+    /// Blocks the device returned a read error for; they count as differing
+    pub blocks_unreadable: u64,
+    /// Exact byte offset of the first differing byte
+    pub first_mismatch: Option<u64>,
+    /// Offset where the device stopped returning data, if before the end of the image
+    pub device_end: Option<u64>,
+    pub ranges: Vec<Range>,
+    pub pattern: Pattern,
+}
+
+/// What the comparison needs from the device being read back
+trait Device: Read {
+    fn method(&self) -> ReadMethod;
+    /// Read up to `out.len()` bytes at `offset`, failing only for that area
+    fn read_at(&mut self, offset: u64, out: &mut [u8]) -> io::Result<usize>;
+    /// Continue sequential reads from `offset`
+    fn seek(&mut self, offset: u64) -> io::Result<()>;
+}
+
+impl Device for DeviceReader {
+    fn method(&self) -> ReadMethod {
+        DeviceReader::method(self)
+    }
+
+    fn read_at(&mut self, offset: u64, out: &mut [u8]) -> io::Result<usize> {
+        DeviceReader::read_at(self, offset, out)
+    }
+
+    fn seek(&mut self, offset: u64) -> io::Result<()> {
+        DeviceReader::seek(self, offset)
+    }
+}
+
+/// Read until `buf` is full or EOF, returning how many bytes were read
+fn read_full(file: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        match file.read(&mut buf[total..]) {
+            Ok(0) => break,
+            Ok(n) => total += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(total)
+}
+
+/// Read the device's part of a chunk at `offset` into `buf`, returning how many bytes it
+/// gave and which blocks of the chunk could not be read
+///
+/// After a read error the chunk is read again block by block, so a failing drive is
+/// reported on rather than ending the comparison.
+fn read_device(device: &mut impl Device, offset: u64, buf: &mut [u8]) -> io::Result<(usize, Vec<usize>)> {
+    if let Ok(n) = read_full(device, buf) {
+        return Ok((n, Vec::new()));
+    }
+
+    let mut unreadable = Vec::new();
+    let mut read = 0;
+    for (i, block) in buf.chunks_mut(BLOCK_SIZE).enumerate() {
+        let at = offset + (i * BLOCK_SIZE) as u64;
+        match device.read_at(at, block) {
+            Ok(n) => {
+                read += n;
+                if n < block.len() {
+                    break;
+                }
+            }
+            Err(_) => {
+                unreadable.push(i);
+                block.fill(0);
+                read += block.len();
+            }
+        }
+    }
+    device.seek(offset + read as u64)?;
+    Ok((read, unreadable))
+}
+
+/// Compare `image` and `device` block by block over the length of the image
+pub fn compare(image: &Path, device: &str) -> io::Result<Report> {
+    let mut image_file = source::open(image)?;
+    let image_size = image_file.size;
+    let mut device_file = devread::open(Path::new(device))?;
+    let mut report = compare_with(&mut image_file, image_size, &mut device_file)?;
+    report.image = image.to_path_buf();
+    report.device = device.to_string();
+    Ok(report)
+}
+
+/// Compare the first `image_size` bytes of `image_file` and `device_file`, leaving the
+/// report's names empty
+fn compare_with(image_file: &mut impl Read, image_size: u64, device_file: &mut impl Device) -> io::Result<Report> {
+    let chunk = BLOCK_SIZE * BLOCKS_PER_READ;
+    let mut image_buf = vec![0u8; chunk];
+    let mut device_buf = vec![0u8; chunk];
+
+    let mut offset: u64 = 0;
+    let mut ranges: Vec<Range> = Vec::new();
+    let mut blocks_total = 0;
+    let mut blocks_differing = 0;
+    let mut blocks_zero = 0;
+    let mut blocks_unreadable = 0;
+    let mut first_mismatch = None;
+    let mut device_end = None;
+    let mut last_percent = -1;
+
+    while offset < image_size {
+        let want = chunk.min((image_size - offset) as usize);
+        let image_read = read_full(image_file, &mut image_buf[..want])?;
+        if image_read == 0 {
+            break;
+        }
+
+        let (device_read, unreadable) = if device_end.is_some() {
+            (0, Vec::new())
+        } else {
+            read_device(device_file, offset, &mut device_buf[..image_read])?
+        };
+        if device_read < image_read && device_end.is_none() {
+            device_end = Some(offset + device_read as u64);
+        }
+
+        for block_start in (0..image_read).step_by(BLOCK_SIZE) {
+            let block_end = (block_start + BLOCK_SIZE).min(image_read);
+            let image_block = &image_buf[block_start..block_end];
+            let unreadable_block = unreadable.contains(&(block_start / BLOCK_SIZE));
+            blocks_total += 1;
+
+            // Bytes past the end of the device, or in a block it failed to read, count as differing
+            let first_diff = if unreadable_block {
+                blocks_unreadable += 1;
+                Some(0)
+            } else if block_end > device_read {
+                let readable = device_read.saturating_sub(block_start);
+                image_block[..readable]
+                    .iter()
+                    .zip(&device_buf[block_start..block_start + readable])
+                    .position(|(a, b)| a != b)
+                    .or(Some(readable))
+            } else {
+                image_block
+                    .iter()
+                    .zip(&device_buf[block_start..block_end])
+                    .position(|(a, b)| a != b)
+            };
+
+            let Some(diff) = first_diff else { continue };
+
+            let abs_start = offset + block_start as u64;
+            let abs_end = offset + block_end as u64;
+            blocks_differing += 1;
+            first_mismatch.get_or_insert(abs_start + diff as u64);
+
+            let device_block = &device_buf[block_start..block_end.min(device_read).max(block_start)];
+            if !unreadable_block && device_block.len() == image_block.len() && device_block.iter().all(|&b| b == 0) {
+                blocks_zero += 1;
+            }
+
+            match ranges.last_mut() {
+                Some(last) if last.end == abs_start => last.end = abs_end,
+                _ => ranges.push(Range { start: abs_start, end: abs_end }),
+            }
+        }
+
+        offset += image_read as u64;
+
+        let percent = (offset * 100 / image_size.max(1)) as i64;
+        if percent != last_percent {
+            print!("\rComparing blocks: {:3}%", percent);
+            io::stdout().flush()?;
+            last_percent = percent;
+        }
+    }
+    println!();
+
+    let pattern = classify(&ranges, image_size);
+    let device_read = device_file.method();
+
+    Ok(Report {
+        image: PathBuf::new(),
+        device: String::new(),
+        device_read,
+        image_size,
+        block_size: BLOCK_SIZE as u64,
+        blocks_total,
+        blocks_differing,
+        blocks_zero,
+        blocks_unreadable,
+        first_mismatch,
+        device_end,
+        ranges,
+        pattern,
+    })
+}
+
+/// Work out whether the differing blocks cluster, and where
+fn classify(ranges: &[Range], image_size: u64) -> Pattern {
+    match ranges {
+        [] => Pattern::Identical,
+        [only] if only.end >= image_size && only.start > 0 => Pattern::Tail { start: only.start },
+        _ if ranges.len() <= 4 => Pattern::Clustered(ranges.len()),
+        _ => Pattern::Scattered(ranges.len()),
+    }
+}
+
+impl Report {
+    /// One-line explanation of the mismatch pattern
+    pub fn pattern_description(&self) -> String {
+        match &self.pattern {
+            Pattern::Identical => {
+                "no block differs - the mismatch was caused by a read error, not by the data".to_string()
+            }
+            Pattern::Tail { start } => {
+                let mut text = format!(
+                    "every block from {} ({}) to the end of the image differs",
+                    human(*start),
+                    start
+                );
+                // Fake-capacity drives report more space than they have, so data past
+                // their real size is lost; the real size is usually a power-of-two GiB
+                if *start >= GIB && start % GIB == 0 && (start / GIB).is_power_of_two() {
+                    text.push_str(&format!(
+                        ". This is the {} GiB boundary: the drive is probably a fake-capacity drive",
+                        start / GIB
+                    ));
+                }
+                text
+            }
+            Pattern::Clustered(n) => format!("differences are clustered in {} region(s)", n),
+            Pattern::Scattered(n) => format!(
+                "differences are scattered over {} regions - suggests failing flash cells or an unreliable connection",
+                n
+            ),
+        }
+    }
+
+    /// The full report, listing at most `max_ranges` ranges
+    fn render(&self, max_ranges: usize) -> String {
+        let mut out = String::new();
+        out.push_str("tEtcher verification report\n");
+        out.push_str(&format!("Image:  {} ({} bytes)\n", self.image.display(), self.image_size));
+        out.push_str(&format!("Device: {}\n", self.device));
+        out.push_str(&format!("Device read: {}\n", self.device_read));
+        out.push_str(&format!("Block size: {}\n", human(self.block_size)));
+        out.push_str(&format!(
+            "Differing blocks: {} of {} ({})\n",
+            self.blocks_differing,
+            self.blocks_total,
+            human(self.ranges.iter().map(|r| r.end - r.start).sum())
+        ));
+        if self.blocks_unreadable > 0 {
+            out.push_str(&format!("Differing blocks the device could not read: {}\n", self.blocks_unreadable));
+        }
+        if self.blocks_zero > 0 {
+            out.push_str(&format!("Differing blocks that read back as all zeros: {}\n", self.blocks_zero));
+        }
+        if let Some(first) = self.first_mismatch {
+            out.push_str(&format!("First mismatch at byte offset {} (0x{:x})\n", first, first));
+        }
+        if let Some(end) = self.device_end {
+            out.push_str(&format!("The device returned no data past offset {} ({})\n", end, human(end)));
+        }
+        out.push_str(&format!("Pattern: {}\n", self.pattern_description()));
+
+        if !self.ranges.is_empty() {
+            out.push_str("Differing ranges:\n");
+            for range in self.ranges.iter().take(max_ranges) {
+                out.push_str(&format!(
+                    "  0x{:012x} - 0x{:012x} ({})\n",
+                    range.start,
+                    range.end,
+                    human(range.end - range.start)
+                ));
+            }
+            if self.ranges.len() > max_ranges {
+                out.push_str(&format!("  ... and {} more\n", self.ranges.len() - max_ranges));
+            }
+        }
+        out
+    }
+
+    /// Summary for the terminal
+    pub fn summary(&self) -> String {
+        self.render(MAX_RANGES_SHOWN)
+    }
+
+    /// Write the complete report to `path`
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.render(usize::MAX))
+    }
+}
+
+/// Ask whether to save the report, and save it in the current directory if so
+pub fn offer_save(report: &Report) -> io::Result<()> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let path = std::env::current_dir()?.join(format!("tetcher-verify-report-{}.txt", secs));
+
+    enable_raw_mode()?;
+    let mut stdout = stdout();
+    let (_, top) = cursor::position()?;
+
+    let options = ["Yes", "No"];
+    let mut selected = 0;
+
+    let save = loop {
+        execute!(stdout, cursor::MoveTo(0, top), terminal::Clear(ClearType::FromCursorDown))?;
+        print!("Save the full report to {}?", path.display());
+
+        for (i, item) in options.iter().enumerate() {
+            execute!(stdout, cursor::MoveTo(0, top + 1 + i as u16))?;
+            if i == selected {
+                print!("{}", item.on_white().black());
+            } else {
+                print!("{}", item);
+            }
+        }
+
+        stdout.flush()?;
+
+        if let Event::Key(key) = event::read()? {
+            match key.code {
+                KeyCode::Up => selected = selected.saturating_sub(1),
+                KeyCode::Down if selected < options.len() - 1 => selected += 1,
+                KeyCode::Enter => break selected == 0,
+                KeyCode::Esc => break false,
+                _ => {}
+            }
+        }
+    };
+
+    disable_raw_mode()?;
+    println!();
+    if save {
+        report.save(&path)?;
+        println!("Report saved to {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: usize = BLOCK_SIZE;
+
+    /// A device holding `data` that fails every read touching one of the `bad` blocks
+    struct Fake {
+        data: Vec<u8>,
+        bad: Vec<usize>,
+        pos: u64,
+    }
+
+    impl Fake {
+        fn new(data: Vec<u8>, bad: &[usize]) -> Fake {
+            Fake { data, bad: bad.to_vec(), pos: 0 }
+        }
+    }
+
+    impl Read for Fake {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            let n = self.read_at(self.pos, out)?;
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Device for Fake {
+        fn method(&self) -> ReadMethod {
+            ReadMethod::Direct
+        }
+
+        fn read_at(&mut self, offset: u64, out: &mut [u8]) -> io::Result<usize> {
+            let start = (offset as usize).min(self.data.len());
+            let end = (start + out.len()).min(self.data.len());
+            if self.bad.iter().any(|&block| block * MIB < end.max(start + 1) && start < (block + 1) * MIB) {
+                return Err(io::Error::other("I/O error"));
+            }
+            out[..end - start].copy_from_slice(&self.data[start..end]);
+            Ok(end - start)
+        }
+
+        fn seek(&mut self, offset: u64) -> io::Result<()> {
+            self.pos = offset;
+            Ok(())
+        }
+    }
+
+    /// Image of `len` bytes that differs from block to block and from zeros
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8 | 1).collect()
+    }
+
+    fn compare_to(image: &[u8], device: &mut Fake) -> Report {
+        compare_with(&mut io::Cursor::new(image), image.len() as u64, device).unwrap()
+    }
+
+    fn ranges(report: &Report) -> Vec<(u64, u64)> {
+        report.ranges.iter().map(|r| (r.start, r.end)).collect()
+    }
+
+    #[test]
+    fn finds_and_merges_differing_blocks() {
+        let image = image(6 * MIB + 1000);
+        let mut data = image.clone();
+        data[MIB + 5] ^= 0xFF;
+        data[2 * MIB..4 * MIB].fill(0);
+        // A zeroed partial last block is a zero block too
+        data[6 * MIB..].fill(0);
+
+        let report = compare_to(&image, &mut Fake::new(data, &[]));
+        assert_eq!(report.blocks_total, 7);
+        assert_eq!(report.blocks_differing, 4);
+        assert_eq!(report.blocks_zero, 3);
+        assert_eq!(report.blocks_unreadable, 0);
+        assert_eq!(report.first_mismatch, Some((MIB + 5) as u64));
+        assert_eq!(report.device_end, None);
+        assert_eq!(ranges(&report), [(MIB as u64, 4 * MIB as u64), (6 * MIB as u64, 6 * MIB as u64 + 1000)]);
+        assert_eq!(report.pattern, Pattern::Clustered(2));
+        assert_eq!(report.device_read, ReadMethod::Direct);
+
+        let same = compare_to(&image, &mut Fake::new(image.clone(), &[]));
+        assert_eq!((same.blocks_differing, same.first_mismatch), (0, None));
+        assert_eq!(same.pattern, Pattern::Identical);
+    }
+
+    #[test]
+    fn counts_unreadable_blocks_as_differing() {
+        let image = image(20 * MIB);
+        let mut data = image.clone();
+        // Zeros in an unreadable block are not what the device holds, so not a zero block
+        data[3 * MIB..4 * MIB].fill(0);
+
+        let report = compare_to(&image, &mut Fake::new(data, &[3, 17]));
+        assert_eq!(report.blocks_total, 20);
+        assert_eq!(report.blocks_differing, 2);
+        assert_eq!(report.blocks_unreadable, 2);
+        assert_eq!(report.blocks_zero, 0);
+        assert_eq!(report.first_mismatch, Some(3 * MIB as u64));
+        assert_eq!(ranges(&report), [(3 * MIB as u64, 4 * MIB as u64), (17 * MIB as u64, 18 * MIB as u64)]);
+        assert_eq!(report.device_end, None);
+    }
+
+    #[test]
+    fn stops_comparing_where_a_short_device_ends() {
+        let image = image(6 * MIB + 1000);
+        let data = image[..4 * MIB + MIB / 2].to_vec();
+
+        let report = compare_to(&image, &mut Fake::new(data, &[]));
+        let end = (4 * MIB + MIB / 2) as u64;
+        assert_eq!(report.device_end, Some(end));
+        assert_eq!(report.first_mismatch, Some(end));
+        assert_eq!(report.blocks_differing, 3);
+        // Blocks the device has no data for are not zero blocks
+        assert_eq!(report.blocks_zero, 0);
+        assert_eq!(ranges(&report), [(4 * MIB as u64, image.len() as u64)]);
+        assert_eq!(report.pattern, Pattern::Tail { start: 4 * MIB as u64 });
+
+        let empty = compare_to(&image, &mut Fake::new(Vec::new(), &[]));
+        assert_eq!(empty.device_end, Some(0));
+        assert_eq!(empty.blocks_differing, 7);
+        // Everything differing from the very start is not a tail
+        assert_eq!(empty.pattern, Pattern::Clustered(1));
+    }
+
+    #[test]
+    fn rereads_a_failed_chunk_block_by_block() {
+        let data = image(5 * MIB);
+        let mut device = Fake::new(data.clone(), &[3]);
+        device.seek(2 * MIB as u64).unwrap();
+        let mut buf = vec![0xAA; 3 * MIB];
+        assert_eq!(read_device(&mut device, 2 * MIB as u64, &mut buf).unwrap(), (3 * MIB, vec![1]));
+        assert_eq!(buf[..MIB], data[2 * MIB..3 * MIB]);
+        assert!(buf[MIB..2 * MIB].iter().all(|&b| b == 0));
+        assert_eq!(buf[2 * MIB..], data[4 * MIB..]);
+        assert_eq!(device.pos, 5 * MIB as u64, "sequential reads carry on after the chunk");
+
+        // A failed chunk that runs past the end of the device stops where the device does
+        device.seek(3 * MIB as u64).unwrap();
+        let mut buf = vec![0; 3 * MIB];
+        let (read, unreadable) = read_device(&mut device, 3 * MIB as u64, &mut buf).unwrap();
+        assert_eq!((read, unreadable), (2 * MIB, vec![0]));
+        assert_eq!(buf[MIB..2 * MIB], data[4 * MIB..]);
+        assert_eq!(device.pos, 5 * MIB as u64);
+
+        let mut device = Fake::new(data.clone(), &[]);
+        let mut buf = vec![0; 2 * MIB];
+        assert_eq!(read_device(&mut device, 0, &mut buf).unwrap(), (2 * MIB, Vec::new()));
+        assert_eq!(buf, data[..2 * MIB]);
+    }
+
+    #[test]
+    fn classifies_and_describes_patterns() {
+        let range = |start: u64, end: u64| Range { start, end };
+        let gib = GIB;
+        assert_eq!(classify(&[], 10 * gib), Pattern::Identical);
+        assert_eq!(classify(&[range(8 * gib, 10 * gib)], 10 * gib), Pattern::Tail { start: 8 * gib });
+        assert_eq!(classify(&[range(0, 10 * gib)], 10 * gib), Pattern::Clustered(1));
+        assert_eq!(classify(&[range(gib, 2 * gib)], 10 * gib), Pattern::Clustered(1));
+        let four: Vec<Range> = (0..4).map(|i| range(i * gib, i * gib + 1)).collect();
+        assert_eq!(classify(&four, 10 * gib), Pattern::Clustered(4));
+        let five: Vec<Range> = (0..5).map(|i| range(i * gib, i * gib + 1)).collect();
+        assert_eq!(classify(&five, 10 * gib), Pattern::Scattered(5));
+
+        let describe = |pattern| {
+            let mut report = compare_to(&[], &mut Fake::new(Vec::new(), &[]));
+            report.pattern = pattern;
+            report.pattern_description()
+        };
+        assert!(describe(Pattern::Identical).contains("read error"));
+        assert!(describe(Pattern::Tail { start: 8 * gib }).contains("8 GiB boundary"));
+        for start in [gib / 2, 3 * gib, 6 * gib, 4 * gib + 4096] {
+            assert!(!describe(Pattern::Tail { start }).contains("fake-capacity"), "{}", start);
+        }
+        assert!(describe(Pattern::Clustered(2)).contains("2 region(s)"));
+        assert!(describe(Pattern::Scattered(40)).contains("failing flash cells"));
+    }
+}
@@ -0,0 +1,80 @@
+use crossterm::{
+    cursor,
+    event::{
+        self,
+        Event,
+        KeyCode
+    },
+    execute,
+    style::{
+        Color,
+        Stylize
+    },
+    terminal::{
+        self,
+        ClearType,
+        disable_raw_mode,
+        enable_raw_mode
+    },
+};
+use std::io::{
+    stdout,
+    Write
+};
+
+/// The top-level operations tEtcher can perform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Write an image to a drive, then optionally verify it
+    Flash,
+    /// Compare an already flashed drive against an image, without writing
+    Verify,
+}
+
+/// Menu UI for choosing what to do, shown when no operation is given on the command line
+pub fn menu() -> Option<Mode> {
+    enable_raw_mode().unwrap();
+    let mut stdout = stdout();
+    execute!(stdout, cursor::Hide).unwrap();
+
+    let options = ["Flash an image to a drive", "Verify a drive against an image", "[Exit]"];
+    let mut selected = 0;
+
+    loop {
+        execute!(stdout, cursor::MoveTo(0, 0), terminal::Clear(ClearType::FromCursorDown)).unwrap();
+        println!("{}", "What would you like to do?".with(Color::Blue));
+
+        for (i, item) in options.iter().enumerate() {
+            execute!(stdout, cursor::MoveTo(0, (i + 1) as u16)).unwrap();
+            execute!(stdout, terminal::Clear(ClearType::CurrentLine)).unwrap();
+
+            if i == selected {
+                print!("  {}", item.on_white().black());
+            } else {
+                print!("  {}", item);
+            }
+        }
+
+        stdout.flush().unwrap();
+
+        if let Event::Key(key) = event::read().unwrap() {
+            match key.code {
+                KeyCode::Up => selected = selected.saturating_sub(1),
+                KeyCode::Down if selected < options.len() - 1 => selected += 1,
+                KeyCode::Enter => {
+                    disable_raw_mode().unwrap();
+                    return match selected {
+                        0 => Some(Mode::Flash),
+                        1 => Some(Mode::Verify),
+                        _ => None,
+                    };
+                }
+                KeyCode::Esc => {
+                    disable_raw_mode().unwrap();
+                    return None;
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crossterm::style::Stylize;
+
+use crate::fsprobe;
+use crate::units::human;
+
+/// EFI System Partition type GUID
+const GPT_ESP: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+/// MBR partition type of an EFI System Partition
+const MBR_ESP: u8 = 0xEF;
+/// MBR partition type of the protective entry in front of a GPT
+const MBR_GPT_PROTECTIVE: u8 = 0xEE;
+/// Sector sizes a GPT header may be laid out for
+const SECTOR_SIZES: [u64; 2] = [512, 4096];
+/// Upper bound on GPT entries read, the usual table holds 128
+const MAX_GPT_ENTRIES: u32 = 256;
+/// Upper bound on the bytes of GPT entries read, whatever the header claims
+const MAX_GPT_TABLE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Mbr,
+    Gpt,
+}
+
+impl std::fmt::Display for Scheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self { Scheme::Mbr => "MBR", Scheme::Gpt => "GPT" })
+    }
+}
+
+/// Partition type as stored in the table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartType {
+    Mbr(u8),
+    /// Type GUID in its canonical upper-case text form
+    Gpt(String),
+}
+
+impl PartType {
+    pub fn is_esp(&self) -> bool {
+        match self {
+            PartType::Mbr(id) => *id == MBR_ESP,
+            PartType::Gpt(guid) => guid == GPT_ESP,
+        }
+    }
+
+    /// Human readable name of the partition type
+    pub fn description(&self) -> String {
+        match self {
+            PartType::Mbr(id) => {
+                let name = match *id {
+                    0x00 => "Empty",
+                    0x01 => "FAT12",
+                    0x04 | 0x06 | 0x0E => "FAT16",
+                    0x05 | 0x0F => "Extended",
+                    0x07 => "NTFS/exFAT",
+                    0x0B | 0x0C => "FAT32",
+                    0x17 => "Hidden NTFS/ISO",
+                    0x82 => "Linux swap",
+                    0x83 => "Linux",
+                    0x8E => "Linux LVM",
+                    0xA5 => "FreeBSD",
+                    0xAF => "Apple HFS+",
+                    MBR_ESP => "EFI System",
+                    MBR_GPT_PROTECTIVE => "GPT protective",
+                    0xFD => "Linux RAID",
+                    _ => return format!("type 0x{:02X}", id),
+                };
+                format!("{} (0x{:02X})", name, id)
+            }
+            PartType::Gpt(guid) => match guid.as_str() {
+                GPT_ESP => "EFI System".to_string(),
+                "21686148-6449-6E6F-744E-656564454649" => "BIOS boot".to_string(),
+                "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7" => "Microsoft basic data".to_string(),
+                "E3C9E316-0B5C-4DB8-817D-F92DF00215AE" => "Microsoft reserved".to_string(),
+                "DE94BBA4-06D1-4D40-A16A-BFD50179D6AC" => "Windows recovery".to_string(),
+                "0FC63DAF-8483-4772-8E79-3D69D8477DE4" => "Linux filesystem".to_string(),
+                "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709" => "Linux root (x86-64)".to_string(),
+                "B921B045-1DF0-41C3-AF44-4C6F280D3FAE" => "Linux root (ARM64)".to_string(),
+                "BC13C2FF-59E6-4262-A352-B275FD6F7172" => "Linux extended boot".to_string(),
+                "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F" => "Linux swap".to_string(),
+                "E6D6D379-F507-44C2-A23C-238F2A3DF928" => "Linux LVM".to_string(),
+                "A19D880F-05FC-4D3B-A006-743F0F84911E" => "Linux RAID".to_string(),
+                "48465300-0000-11AA-AA11-00306543ECAC" => "Apple HFS+".to_string(),
+                "7C3457EF-0000-11AA-AA11-00306543ECAC" => "Apple APFS".to_string(),
+                "FE3A2A5D-4F32-41A7-B725-ACCC3285A309" => "ChromeOS kernel".to_string(),
+                "3CB8E202-3B7E-47DD-8A3C-7FF2A13CFCEC" => "ChromeOS root".to_string(),
+                other => other.to_string(),
+            },
+        }
+    }
+}
+
+/// One entry of an MBR or GPT partition table
+#[derive(Debug, Clone)]
+pub struct Partition {
+    pub number: u32,
+    pub kind: PartType,
+    /// Offset of the partition from the start of the image, in bytes
+    pub start: u64,
+    pub size: u64,
+    /// GPT partition name
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub scheme: Scheme,
+    pub partitions: Vec<Partition>,
+}
+
+impl Table {
+    pub fn has_esp(&self) -> bool {
+        self.partitions.iter().any(|p| p.kind.is_esp())
+    }
+}
+
+/// Partition layout the image will create, with the filesystem found in each partition
+///
+/// Empty for images without a partition table, such as plain ISO9660 images
+pub fn preview(path: &Path) -> Vec<String> {
+    let Some(table) = read(path) else { return Vec::new() };
+    let Ok(mut file) = File::open(path) else { return Vec::new() };
+    let mut lines = vec![format!("Partition table: {}", summary(&table))];
+    lines.extend(rows(&table, &mut file));
+    lines
+}
+
+/// What is on a drive right now: its partitions, or the filesystem it holds without one
+pub fn contents(dev: &Path) -> Vec<String> {
+    let heading = "Currently on the drive:".bold();
+    let mut file = match File::open(dev) {
+        Ok(file) => file,
+        Err(why) => return vec![format!("{} could not be read ({})", heading, why)],
+    };
+    if let Some(table) = read(dev) {
+        let mut lines = vec![format!("{} {}", heading, summary(&table))];
+        lines.extend(rows(&table, &mut file));
+        return lines;
+    }
+    match fsprobe::probe(&mut file, 0) {
+        Some(fs) => vec![format!("{} {} without a partition table{}", heading, fs, used(&fs))],
+        None => vec![format!("{} no partition table or filesystem found", heading)],
+    }
+}
+
+/// "GPT (3 partitions)"
+fn summary(table: &Table) -> String {
+    let count = table.partitions.len();
+    format!("{} ({} partition{})", table.scheme, count, if count == 1 { "" } else { "s" })
+}
+
+/// One line per partition with its type, size and filesystem
+fn rows(table: &Table, file: &mut File) -> Vec<String> {
+    let mut lines = Vec::new();
+    for part in &table.partitions {
+        let mut line = format!("  {:>2}  {:<22} {:>11}", part.number, part.kind.description(), human(part.size));
+        match fsprobe::probe(file, part.start) {
+            Some(fs) => line.push_str(&format!("  {}{}", fs, used(&fs))),
+            None => line.push_str("  -"),
+        }
+        if let Some(name) = &part.name {
+            line.push_str(&format!("  [{}]", name));
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn used(fs: &fsprobe::Filesystem) -> String {
+    fs.used.map(|used| format!(", {} used", human(used))).unwrap_or_default()
+}
+
+/// The first sector of an image, if it carries the 0x55AA boot signature
+pub fn mbr(path: &Path) -> Option<[u8; 512]> {
+    let mut sector = [0u8; 512];
+    File::open(path).ok()?.read_exact(&mut sector).ok()?;
+    (sector[510..512] == [0x55, 0xAA]).then_some(sector)
+}
+
+/// Read the partition table of `path`, preferring a GPT over the MBR in front of it
+pub fn read(path: &Path) -> Option<Table> {
+    let mut file = File::open(path).ok()?;
+    if let Some(table) = read_gpt(&mut file) {
+        return Some(table);
+    }
+    let sector = mbr(path)?;
+    read_mbr(&sector)
+}
+
+fn read_mbr(sector: &[u8; 512]) -> Option<Table> {
+    let mut partitions = Vec::new();
+    for (i, entry) in sector[446..510].chunks_exact(16).enumerate() {
+        // Boot indicator must be 0x00 or 0x80, otherwise this is not a partition table
+        // (e.g. a FAT boot sector, which has the same signature)
+        if entry[0] & 0x7F != 0 {
+            return None;
+        }
+        let kind = entry[4];
+        let start = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64;
+        let sectors = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64;
+        if sectors == 0 {
+            continue;
+        }
+        partitions.push(Partition {
+            number: i as u32 + 1,
+            kind: PartType::Mbr(kind),
+            start: start * 512,
+            size: sectors * 512,
+            name: None,
+        });
+    }
+    (!partitions.is_empty()).then_some(Table { scheme: Scheme::Mbr, partitions })
+}
+
+fn read_gpt(file: &mut File) -> Option<Table> {
+    for sector in SECTOR_SIZES {
+        let mut header = [0u8; 92];
+        if file.seek(SeekFrom::Start(sector)).is_err() || file.read_exact(&mut header).is_err() {
+            return None;
+        }
+        if &header[0..8] != b"EFI PART" {
+            continue;
+        }
+
+        let entries_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+        let count = u32::from_le_bytes(header[80..84].try_into().unwrap()).min(MAX_GPT_ENTRIES);
+        let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()) as usize;
+        // The spec requires 128 * 2^n; anything else is a corrupt or hostile header
+        if !entry_size.is_power_of_two() || !(128..=4096).contains(&entry_size) {
+            return None;
+        }
+        let count = (count as usize).min(MAX_GPT_TABLE / entry_size);
+
+        let mut entries = vec![0u8; count * entry_size];
+        file.seek(SeekFrom::Start(entries_lba.checked_mul(sector)?)).ok()?;
+        file.read_exact(&mut entries).ok()?;
+
+        let mut partitions = Vec::new();
+        for (i, entry) in entries.chunks_exact(entry_size).enumerate() {
+            if entry[0..16].iter().all(|&b| b == 0) {
+                continue;
+            }
+            let first = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+            let last = u64::from_le_bytes(entry[40..48].try_into().unwrap());
+            // An entry whose byte range does not fit in a u64 cannot be real
+            let Some(start) = first.checked_mul(sector) else { continue };
+            let Some(size) = last.checked_add(1).and_then(|end| end.saturating_sub(first).checked_mul(sector)) else {
+                continue;
+            };
+            let name: Vec<u16> =
+                entry[56..128].chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+            let name = String::from_utf16_lossy(&name).trim_end_matches('\0').trim().to_string();
+
+            partitions.push(Partition {
+                number: i as u32 + 1,
+                kind: PartType::Gpt(guid(&entry[0..16])),
+                start,
+                size,
+                name: (!name.is_empty()).then_some(name),
+            });
+        }
+        return Some(Table { scheme: Scheme::Gpt, partitions });
+    }
+    None
+}
+
+/// GUIDs are stored with their first three fields little-endian
+pub fn guid(bytes: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{}",
+        u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+        u16::from_le_bytes(bytes[4..6].try_into().unwrap()),
+        u16::from_le_bytes(bytes[6..8].try_into().unwrap()),
+        bytes[8],
+        bytes[9],
+        bytes[10..16].iter().map(|b| format!("{:02X}", b)).collect::<String>()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testimg::{self, ESP, Iso, LINUX};
+
+    fn table(name: &str, disk: &[u8]) -> Option<Table> {
+        read(&testimg::file(name, disk))
+    }
+
+    /// A GPT disk with the header's entry size and count replaced
+    fn gpt_with(entry_size: u32, count: u32) -> Vec<u8> {
+        let mut disk = testimg::gpt(4096, &[(ESP, 34, 97, "EFI system partition"), (LINUX, 98, 4095, "root")]);
+        disk[512 + 80..512 + 84].copy_from_slice(&count.to_le_bytes());
+        disk[512 + 84..512 + 88].copy_from_slice(&entry_size.to_le_bytes());
+        disk
+    }
+
+    #[test]
+    fn reads_gpt_and_mbr_tables() {
+        let gpt = table("parttable-gpt.img", &gpt_with(128, 128)).unwrap();
+        assert_eq!(gpt.scheme, Scheme::Gpt);
+        assert_eq!(gpt.partitions.len(), 2);
+        assert!(gpt.has_esp());
+        assert_eq!(gpt.partitions[1].kind, PartType::Gpt("0FC63DAF-8483-4772-8E79-3D69D8477DE4".to_string()));
+
+        let mut disk = vec![0u8; 64 * 512];
+        disk[..512].copy_from_slice(&testimg::mbr(false, &[(0x0C, 1, 15), (0xEF, 16, 48)]));
+        let mbr = table("parttable-mbr.img", &disk).unwrap();
+        assert_eq!(mbr.scheme, Scheme::Mbr);
+        assert_eq!(mbr.partitions.len(), 2);
+        assert!(mbr.has_esp());
+
+        // A FAT boot sector has the boot signature but no valid boot indicators
+        disk[446] = 0xEB;
+        assert!(table("parttable-fat.img", &disk).is_none());
+        assert!(table("parttable-empty.img", &[]).is_none());
+    }
+
+    #[test]
+    fn rejects_gpt_entry_sizes_outside_the_spec() {
+        // Only 128 * 2^n up to 4096 is accepted; the protective MBR is all that is left
+        for entry_size in [0, 1, 96, 127, 129, 200, 384, 8192, 1 << 31, u32::MAX] {
+            let fallback = table("parttable-entry-size.img", &gpt_with(entry_size, 128)).unwrap();
+            assert_eq!(fallback.scheme, Scheme::Mbr, "entry size {}", entry_size);
+            assert_eq!(fallback.partitions[0].kind, PartType::Mbr(MBR_GPT_PROTECTIVE));
+        }
+        for entry_size in [128, 256, 512, 4096] {
+            let gpt = table("parttable-entry-size.img", &gpt_with(entry_size, 128)).unwrap();
+            assert_eq!(gpt.scheme, Scheme::Gpt, "entry size {}", entry_size);
+        }
+
+        // A huge entry count is capped instead of allocated
+        let gpt = table("parttable-count.img", &gpt_with(128, u32::MAX)).unwrap();
+        assert_eq!((gpt.scheme, gpt.partitions.len()), (Scheme::Gpt, 2));
+        let gpt = table("parttable-count.img", &gpt_with(4096, u32::MAX)).unwrap();
+        assert_eq!(gpt.scheme, Scheme::Gpt);
+    }
+
+    #[test]
+    fn falls_back_to_the_mbr_of_truncated_gpt_disks() {
+        let disk = gpt_with(128, 128);
+        for len in [512, 600, 1024, 1024 + 128 * 128 - 1] {
+            let fallback = table("parttable-cut.img", &disk[..len]).unwrap();
+            assert_eq!(fallback.scheme, Scheme::Mbr, "{} bytes", len);
+        }
+        assert!(table("parttable-cut.img", &disk[..511]).is_none());
+        assert_eq!(table("parttable-cut.img", &disk[..1024 + 128 * 128]).unwrap().partitions.len(), 2);
+    }
+
+    #[test]
+    fn skips_gpt_entries_whose_range_overflows() {
+        let far = u64::MAX / 512 + 1;
+        let disk = testimg::gpt(
+            256,
+            &[
+                (ESP, 34, 97, "EFI system partition"),
+                (LINUX, far, far + 10, "too far"),
+                (LINUX, 98, u64::MAX, "too long"),
+                (LINUX, 98, far + 97, "too big"),
+                (LINUX, 200, 100, "backwards"),
+                (LINUX, 98, 255, "root"),
+            ],
+        );
+        let gpt = table("parttable-overflow.img", &disk).unwrap();
+        let found: Vec<_> = gpt.partitions.iter().map(|p| (p.number, p.start, p.size, p.name.as_deref())).collect();
+        assert_eq!(
+            found,
+            [
+                (1, 34 * 512, 64 * 512, Some("EFI system partition")),
+                (5, 200 * 512, 0, Some("backwards")),
+                (6, 98 * 512, 158 * 512, Some("root")),
+            ]
+        );
+    }
+
+    #[test]
+    fn previews_partitions_with_their_filesystems() {
+        let mut disk = testimg::gpt(256, &[(ESP, 34, 97, "EFI system partition"), (LINUX, 98, 255, "root")]);
+        disk[34 * 512..35 * 512 + 512].copy_from_slice(&testimg::fat32("EFI", 64, 1));
+        disk[98 * 512..102 * 512].copy_from_slice(&testimg::ext4("rootfs", 19, 5));
+
+        let lines = preview(&testimg::file("parttable-preview.img", &disk));
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "Partition table: GPT (2 partitions)");
+        assert!(lines[1].starts_with("   1  EFI System") && lines[1].contains("vfat \"EFI\""), "{}", lines[1]);
+        assert!(lines[1].ends_with("  [EFI system partition]"), "{}", lines[1]);
+        assert!(lines[2].contains("ext4 \"rootfs\"") && lines[2].ends_with("  [root]"), "{}", lines[2]);
+
+        // A filesystem cut off with the image is shown as unknown
+        let lines = preview(&testimg::file("parttable-preview-cut.img", &disk[..98 * 512 + 100]));
+        assert!(lines[2].ends_with("  -  [root]"), "{}", lines[2]);
+
+        assert_eq!(preview(&testimg::file("parttable-preview.iso", &Iso::default().build())), Vec::<String>::new());
+    }
+}
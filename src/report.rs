@@ -0,0 +1,221 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{Value, json};
+
+use crate::digest;
+use crate::mismatch;
+use crate::mode::Mode;
+use crate::source;
+use crate::targ;
+use crate::verify;
+
+/// One timed step of a run, e.g. flashing or verifying
+#[derive(Debug, Clone)]
+struct Phase {
+    name: &'static str,
+    duration: Duration,
+    /// None when the phase failed part way and the amount is unknown
+    bytes: Option<u64>,
+}
+
+/// Machine-readable record of one flash or verify run, written as JSON at the end
+///
+/// It is started before the menus, so the mode, image and device stay None when the
+/// run was cancelled before they were chosen.
+#[derive(Debug, Clone)]
+pub struct RunReport {
+    mode: Option<Mode>,
+    started_at: u64,
+    image: Option<PathBuf>,
+    image_size: Option<u64>,
+    image_digest: Option<Vec<u8>>,
+    device: Option<String>,
+    device_details: targ::DeviceDetails,
+    phases: Vec<Phase>,
+    verify: Option<verify::Outcome>,
+    mismatch: Option<mismatch::Report>,
+    /// "completed", "cancelled" or "failed"
+    status: &'static str,
+    errors: Vec<String>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl RunReport {
+    pub fn new() -> RunReport {
+        RunReport {
+            mode: None,
+            started_at: unix_now(),
+            image: None,
+            image_size: None,
+            image_digest: None,
+            device: None,
+            device_details: targ::DeviceDetails::default(),
+            phases: Vec::new(),
+            verify: None,
+            mismatch: None,
+            status: "completed",
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn chose_mode(&mut self, mode: Mode) {
+        self.mode = Some(mode);
+    }
+
+    pub fn chose_image(&mut self, image: &Path) {
+        self.image = Some(image.to_path_buf());
+        // Size of the data written, i.e. the raw content of a virtual disk
+        self.image_size = source::open(image).ok().map(|s| s.size);
+    }
+
+    pub fn chose_device(&mut self, device: &str) {
+        self.device = Some(device.to_string());
+        self.device_details = targ::device_details(Path::new("/"), device);
+    }
+
+    /// Record how long a phase took and how much data it moved, if known
+    pub fn phase(&mut self, name: &'static str, duration: Duration, bytes: Option<u64>) {
+        self.phases.push(Phase { name, duration, bytes });
+    }
+
+    pub fn image_digest(&mut self, digest: Vec<u8>) {
+        self.image_digest = Some(digest);
+    }
+
+    /// Record the verification; a device that does not match the image fails the run
+    pub fn verified(&mut self, outcome: verify::Outcome) {
+        self.image_digest = Some(outcome.image_digest.clone());
+        if !outcome.matched {
+            self.error("verification failed: the device does not match the image");
+        }
+        self.verify = Some(outcome);
+    }
+
+    pub fn mismatch(&mut self, report: mismatch::Report) {
+        self.mismatch = Some(report);
+    }
+
+    pub fn cancelled(&mut self) {
+        self.status = "cancelled";
+    }
+
+    pub fn error(&mut self, why: impl Into<String>) {
+        self.status = "failed";
+        self.errors.push(why.into());
+    }
+
+    pub fn image_size(&self) -> u64 {
+        self.image_size.unwrap_or(0)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let phases: Vec<Value> = self
+            .phases
+            .iter()
+            .map(|p| {
+                let secs = p.duration.as_secs_f64();
+                json!({
+                    "name": p.name,
+                    "seconds": secs,
+                    "bytes": p.bytes,
+                    "throughput_bytes_per_sec": p.bytes.filter(|_| secs > 0.0).map(|b| b as f64 / secs),
+                })
+            })
+            .collect();
+
+        let verify = self.verify.as_ref().map(|v| {
+            json!({
+                "result": if v.matched { "pass" } else { "fail" },
+                "algorithm": verify::ALGO.to_string(),
+                "image_digest": digest::to_hex(&v.image_digest),
+                "device_digest": digest::to_hex(&v.device_digest),
+                "device_read": v.device_read.to_string(),
+            })
+        });
+
+        let mismatch = self.mismatch.as_ref().map(|m| {
+            json!({
+                "block_size": m.block_size,
+                "blocks_total": m.blocks_total,
+                "blocks_differing": m.blocks_differing,
+                "blocks_zero": m.blocks_zero,
+                "first_mismatch": m.first_mismatch,
+                "device_end": m.device_end,
+                "pattern": m.pattern_description(),
+                "ranges": m.ranges.iter().map(|r| json!({ "start": r.start, "end": r.end })).collect::<Vec<_>>(),
+            })
+        });
+
+        json!({
+            "tool": "tEtcher",
+            "version": env!("CARGO_PKG_VERSION"),
+            "operation": self.mode.map(|mode| match mode { Mode::Flash => "flash", Mode::Verify => "verify" }),
+            "status": self.status,
+            "started_at": self.started_at,
+            "finished_at": unix_now(),
+            "image": self.image.as_ref().map(|image| json!({
+                "path": image.display().to_string(),
+                "size": self.image_size,
+                "digest_algorithm": verify::ALGO.to_string(),
+                "digest": self.image_digest.as_deref().map(digest::to_hex),
+            })),
+            "device": self.device.as_ref().map(|device| json!({
+                "path": device,
+                "model": self.device_details.model,
+                "serial": self.device_details.serial,
+                "size": self.device_details.size,
+            })),
+            "phases": phases,
+            "verify": verify,
+            "mismatch": mismatch,
+            "errors": self.errors,
+        })
+    }
+
+    /// Write the report to `path`, or to tetcher-report-<time>.json in the current directory
+    pub fn write(&self, path: Option<&Path>) -> io::Result<PathBuf> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => std::env::current_dir()?.join(format!("tetcher-report-{}.json", self.started_at)),
+        };
+        let text = serde_json::to_string_pretty(&self.to_json()).map_err(io::Error::other)?;
+        fs::write(&path, text + "\n")?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devread::ReadMethod;
+
+    fn outcome(matched: bool) -> verify::Outcome {
+        verify::Outcome {
+            matched,
+            image_digest: vec![0xAB; 4],
+            device_digest: vec![if matched { 0xAB } else { 0xCD }; 4],
+            device_read: ReadMethod::Direct,
+        }
+    }
+
+    #[test]
+    fn a_device_that_does_not_match_fails_the_run() {
+        let mut report = RunReport::new();
+        report.verified(outcome(true));
+        assert_eq!(report.to_json()["status"], "completed");
+        assert_eq!(report.to_json()["verify"]["result"], "pass");
+
+        let mut report = RunReport::new();
+        report.verified(outcome(false));
+        let json = report.to_json();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["verify"]["result"], "fail");
+        assert_eq!(json["errors"][0], "verification failed: the device does not match the image");
+    }
+}
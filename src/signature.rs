@@ -0,0 +1,345 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use base64::Engine;
+use crossterm::style::{Color, Stylize};
+
+/// Default location of the trusted distro keys, overridable with TETCHER_KEYRING
+const DEFAULT_KEYRING: &str = "/etc/tetcher/keyring";
+
+/// Result of trying to authenticate a checksum file
+#[derive(Debug, Clone)]
+pub enum Verdict {
+    /// A signature checked out against a key in the trusted keyring
+    Verified { signer: String, method: &'static str, signature: PathBuf },
+    /// A signature was found but is cryptographically invalid: the file was tampered with
+    Bad { signature: PathBuf, reason: String },
+    /// Nothing could be verified (no signature, unknown key, missing gpgv...)
+    Unverified(String),
+}
+
+impl Verdict {
+    /// Lines for the image confirm screen
+    pub fn lines(&self) -> Vec<String> {
+        match self {
+            Verdict::Verified { signer, method, signature } => vec![format!(
+                "{} {} signature {} by {}",
+                "[ SIGNED ]".with(Color::Green).bold(),
+                method,
+                file_name(signature),
+                signer
+            )],
+            Verdict::Bad { signature, reason } => vec![
+                format!(
+                    "{} {} IS NOT A VALID SIGNATURE: {}",
+                    "[ BAD SIGNATURE ]".with(Color::Red).bold(),
+                    file_name(signature),
+                    reason
+                ),
+                "  The checksum file has been modified. DO NOT TRUST THIS IMAGE."
+                    .with(Color::Red)
+                    .bold()
+                    .to_string(),
+            ],
+            Verdict::Unverified(reason) => unverified_lines(reason),
+        }
+    }
+}
+
+/// Loud warning shown when the image's authenticity could not be established
+pub fn unverified_lines(reason: &str) -> Vec<String> {
+    vec![
+        format!(
+            "{} {}",
+            "[ UNVERIFIED ]".with(Color::Red).bold(),
+            "WARNING: the image could not be authenticated".with(Color::Red).bold()
+        ),
+        format!("  {}", reason),
+    ]
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+/// Directory holding the trusted OpenPGP (`.gpg`, `.kbx`, `.asc`) and minisign (`.pub`) keys
+pub fn keyring_dir() -> PathBuf {
+    std::env::var_os("TETCHER_KEYRING")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_KEYRING))
+}
+
+/// Keyring files with the given extensions
+fn keyring_files(dir: &Path, exts: &[&str]) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.is_file()
+                && p.extension()
+                    .map(|e| exts.iter().any(|x| e.eq_ignore_ascii_case(x)))
+                    .unwrap_or(false)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Look for a detached or inline signature on `checksum_file` and verify it
+pub fn check(checksum_file: &Path) -> Verdict {
+    let keyring = keyring_dir();
+    let mut reasons = Vec::new();
+    let mut bad = None;
+
+    // Detached signatures next to the checksum file
+    for ext in ["gpg", "sig", "asc", "minisig"] {
+        let mut sig = checksum_file.as_os_str().to_owned();
+        sig.push(".");
+        sig.push(ext);
+        let sig = PathBuf::from(sig);
+        if !sig.is_file() {
+            continue;
+        }
+
+        let verdict = if ext == "minisig" {
+            check_minisign(checksum_file, &sig, &keyring)
+        } else {
+            check_openpgp(&sig, Some(checksum_file), &keyring)
+        };
+
+        match verdict {
+            verdict @ Verdict::Verified { .. } => return verdict,
+            verdict @ Verdict::Bad { .. } => bad = bad.or(Some(verdict)),
+            Verdict::Unverified(reason) => reasons.push(format!("{}: {}", file_name(&sig), reason)),
+        }
+    }
+
+    // Inline clearsigned checksum files (e.g. Fedora CHECKSUM)
+    let clearsigned = fs::read_to_string(checksum_file)
+        .map(|t| t.starts_with("-----BEGIN PGP SIGNED MESSAGE-----"))
+        .unwrap_or(false);
+    if clearsigned {
+        match check_openpgp(checksum_file, None, &keyring) {
+            verdict @ Verdict::Verified { .. } => return verdict,
+            verdict @ Verdict::Bad { .. } => bad = bad.or(Some(verdict)),
+            Verdict::Unverified(reason) => reasons.push(format!("{}: {}", file_name(checksum_file), reason)),
+        }
+    }
+
+    if let Some(verdict) = bad {
+        return verdict;
+    }
+    if reasons.is_empty() {
+        return Verdict::Unverified(format!("{} is not signed", file_name(checksum_file)));
+    }
+    Verdict::Unverified(reasons.join("; "))
+}
+
+/// Verify a minisign signature against every `.pub` key in the keyring
+fn check_minisign(data_path: &Path, sig_path: &Path, keyring: &Path) -> Verdict {
+    use minisign_verify::{Error, PublicKey, Signature};
+
+    let signature = match Signature::from_file(sig_path) {
+        Ok(sig) => sig,
+        Err(why) => return Verdict::Unverified(format!("unreadable signature ({})", why)),
+    };
+    let data = match fs::read(data_path) {
+        Ok(data) => data,
+        Err(why) => return Verdict::Unverified(why.to_string()),
+    };
+
+    let keys = keyring_files(keyring, &["pub"]);
+    if keys.is_empty() {
+        return Verdict::Unverified(format!("no minisign keys in {}", keyring.display()));
+    }
+
+    for key_path in keys {
+        let Ok(key) = PublicKey::from_file(&key_path) else { continue };
+        match key.verify(&data, &signature, true) {
+            Ok(()) => {
+                let comment = key
+                    .untrusted_comment()
+                    .map(|c| c.trim_start_matches("untrusted comment:").trim().to_string())
+                    .unwrap_or_default();
+                return Verdict::Verified {
+                    signer: format!("{} [{}] \"{}\"", file_name(&key_path), comment, signature.trusted_comment()),
+                    method: "minisign",
+                    signature: sig_path.to_path_buf(),
+                };
+            }
+            // Signed by some other key: keep looking
+            Err(Error::UnexpectedKeyId) => continue,
+            Err(why) => {
+                return Verdict::Bad { signature: sig_path.to_path_buf(), reason: why.to_string() };
+            }
+        }
+    }
+
+    Verdict::Unverified("signed by a key that is not in the trusted keyring".to_string())
+}
+
+/// Verify an OpenPGP signature with gpgv, using only the keys in the trusted keyring
+///
+/// `data_path` is None for clearsigned files, which carry their own data
+fn check_openpgp(sig_path: &Path, data_path: Option<&Path>, keyring: &Path) -> Verdict {
+    let binary_keys = keyring_files(keyring, &["gpg", "kbx"]);
+    let armored_keys = keyring_files(keyring, &["asc", "key"]);
+    if binary_keys.is_empty() && armored_keys.is_empty() {
+        return Verdict::Unverified(format!("no OpenPGP keys in {}", keyring.display()));
+    }
+
+    // gpgv only reads binary keyrings, so dearmor .asc keys into a private temporary directory
+    let key_dir = if armored_keys.is_empty() { None } else { KeyDir::new().ok() };
+    let mut temp_keys = Vec::new();
+    for (i, key) in armored_keys.iter().enumerate() {
+        let Some(dir) = &key_dir else { break };
+        let Some(bytes) = fs::read_to_string(key).ok().and_then(|t| dearmor(&t)) else { continue };
+        let temp = dir.0.join(format!("{}.gpg", i));
+        let written = fs::OpenOptions::new().write(true).create_new(true).open(&temp).and_then(|mut f| f.write_all(&bytes));
+        if written.is_ok() {
+            temp_keys.push(temp);
+        }
+    }
+
+    let mut cmd = Command::new("gpgv");
+    cmd.args(["--status-fd", "1"]);
+    for key in binary_keys.iter().chain(temp_keys.iter()) {
+        cmd.arg("--keyring").arg(key);
+    }
+    cmd.arg(sig_path);
+    if let Some(data) = data_path {
+        cmd.arg(data);
+    }
+
+    let output = cmd.output();
+    drop(key_dir);
+
+    let output = match output {
+        Ok(output) => output,
+        Err(why) => return Verdict::Unverified(format!("could not run gpgv ({})", why)),
+    };
+
+    let status = String::from_utf8_lossy(&output.stdout);
+    parse_gpgv_status(&status, sig_path)
+}
+
+/// Directory only this process can use, removed with everything in it when dropped
+///
+/// tEtcher runs as root, so a predictable name in a shared /tmp would let another user
+/// plant a symlink there or swap in a key of their own.
+struct KeyDir(PathBuf);
+
+impl KeyDir {
+    #[cfg(unix)]
+    fn new() -> io::Result<KeyDir> {
+        use std::ffi::{CString, OsString};
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+        let template = std::env::temp_dir().join("tetcher-keys-XXXXXX");
+        let mut path = CString::new(template.as_os_str().as_bytes())?.into_bytes_with_nul();
+        // mkdtemp picks an unused name and creates the directory with mode 0700
+        if unsafe { libc::mkdtemp(path.as_mut_ptr() as *mut libc::c_char) }.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        path.pop();
+        Ok(KeyDir(PathBuf::from(OsString::from_vec(path))))
+    }
+
+    #[cfg(not(unix))]
+    fn new() -> io::Result<KeyDir> {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let dir = std::env::temp_dir().join(format!("tetcher-keys-{}-{}", std::process::id(), nanos));
+        // Fails instead of reusing a directory that is already there
+        fs::create_dir(&dir)?;
+        Ok(KeyDir(dir))
+    }
+}
+
+impl Drop for KeyDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Interpret gpgv's `--status-fd` output
+fn parse_gpgv_status(status: &str, sig_path: &Path) -> Verdict {
+    let mut good_uid = None;
+    let mut fingerprint = None;
+
+    for line in status.lines() {
+        let Some(rest) = line.strip_prefix("[GNUPG:] ") else { continue };
+        let mut words = rest.splitn(3, ' ');
+        let keyword = words.next().unwrap_or("");
+        let first = words.next().unwrap_or("");
+        let tail = words.next().unwrap_or("");
+
+        match keyword {
+            "GOODSIG" => good_uid = Some(tail.to_string()),
+            "VALIDSIG" => fingerprint = Some(first.to_string()),
+            "BADSIG" => {
+                return Verdict::Bad {
+                    signature: sig_path.to_path_buf(),
+                    reason: format!("bad signature from {}", tail),
+                };
+            }
+            "NO_PUBKEY" => {
+                return Verdict::Unverified(format!("signed by key {} which is not in the trusted keyring", first));
+            }
+            "EXPKEYSIG" | "REVKEYSIG" => {
+                return Verdict::Unverified(format!("signing key {} is expired or revoked", first));
+            }
+            _ => {}
+        }
+    }
+
+    match (good_uid, fingerprint) {
+        (Some(uid), Some(fpr)) => Verdict::Verified {
+            signer: format!("{} ({})", uid, fpr),
+            method: "OpenPGP",
+            signature: sig_path.to_path_buf(),
+        },
+        _ => Verdict::Unverified("gpgv could not verify the signature".to_string()),
+    }
+}
+
+/// Convert an ASCII-armored OpenPGP key block to binary
+fn dearmor(text: &str) -> Option<Vec<u8>> {
+    let mut body = String::new();
+    let mut in_block = false;
+    let mut in_headers = false;
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with("-----BEGIN PGP PUBLIC KEY BLOCK") {
+            in_block = true;
+            in_headers = true;
+            continue;
+        }
+        if line.starts_with("-----END PGP PUBLIC KEY BLOCK") {
+            break;
+        }
+        if !in_block {
+            continue;
+        }
+        if in_headers {
+            // Armor headers ("Comment: ...") end at the first blank line
+            if line.is_empty() {
+                in_headers = false;
+            } else if !line.contains(": ") {
+                in_headers = false;
+                body.push_str(line);
+            }
+            continue;
+        }
+        // "=XXXX" is the CRC24 checksum line
+        if line.starts_with('=') {
+            break;
+        }
+        body.push_str(line);
+    }
+
+    base64::engine::general_purpose::STANDARD.decode(body).ok()
+}
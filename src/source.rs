@@ -0,0 +1,36 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::imgformat::{self, Format};
+use crate::vdisk;
+
+/// The bytes that end up on the drive: the file itself, or the raw disk inside a virtual disk image
+pub struct Source {
+    reader: Box<dyn Read + Send>,
+    /// Length of the raw content in bytes
+    pub size: u64,
+    /// The content is decoded from a container rather than being the file's own bytes,
+    /// so digests of the file (checksums, the digest cache) do not apply to it
+    pub expanded: bool,
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+/// Open `image` for reading its raw content from the start
+pub fn open(image: &Path) -> io::Result<Source> {
+    match imgformat::detect(image) {
+        format @ (Format::Qcow2 | Format::Vhd | Format::Vhdx | Format::Vmdk | Format::Dmg) => {
+            let reader = vdisk::Reader::new(vdisk::open(image, format)?);
+            Ok(Source { size: reader.size(), reader: Box::new(reader), expanded: true })
+        }
+        _ => {
+            let file = File::open(image)?;
+            Ok(Source { size: file.metadata()?.len(), reader: Box::new(file), expanded: false })
+        }
+    }
+}
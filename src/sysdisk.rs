@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Mount points whose drive the running system needs
+#[cfg(target_os = "linux")]
+const SYSTEM_MOUNTS: [&str; 5] = ["/", "/boot", "/boot/efi", "/efi", "/usr"];
+/// Where live systems mount the USB stick or DVD they booted from
+#[cfg(target_os = "linux")]
+const LIVE_MEDIA: [&str; 5] =
+    ["/run/live/medium", "/lib/live/mount/medium", "/cdrom", "/run/initramfs/live", "/run/archiso/bootmnt"];
+
+/// Drives the running system depends on, by kernel name (e.g. "sda"), with the reason
+///
+/// Covers the drives behind the root, boot and EFI filesystems, live boot media, swap,
+/// and anything in an active device-mapper (LVM, LUKS) or md RAID stack. /proc, /sys
+/// and /dev are looked up under `root`, which is "/" except when testing.
+#[cfg(target_os = "linux")]
+pub fn protected(root: &Path) -> BTreeMap<String, String> {
+    use std::fs;
+
+    let mut disks = BTreeMap::new();
+    let mut protect = |name: &str, reason: String| {
+        for disk in whole_disks(root, name) {
+            disks.entry(disk).or_insert_with(|| reason.clone());
+        }
+    };
+
+    if let Ok(mountinfo) = fs::read_to_string(root.join("proc/self/mountinfo")) {
+        for line in mountinfo.lines() {
+            let Some((mount, rest)) = line.split_once(" - ") else { continue };
+            let fields: Vec<&str> = mount.split(' ').collect();
+            let (Some(&majmin), Some(&point)) = (fields.get(2), fields.get(4)) else { continue };
+            let system = SYSTEM_MOUNTS.contains(&point);
+            let live = LIVE_MEDIA.contains(&point);
+            if !system && !live {
+                continue;
+            }
+            // btrfs and friends report an anonymous device number; fall back to the mount source
+            let source = rest.split(' ').nth(1).unwrap_or("");
+            let Some(name) = by_majmin(root, majmin).or_else(|| by_path(root, source)) else { continue };
+            let reason = if live {
+                format!("the running live system booted from it ({})", point)
+            } else {
+                format!("{} is on {}", point, describe(root, &name))
+            };
+            protect(&name, reason);
+        }
+    }
+
+    if let Ok(swaps) = fs::read_to_string(root.join("proc/swaps")) {
+        for line in swaps.lines().skip(1) {
+            let Some(path) = line.split_whitespace().next() else { continue };
+            // A swap file lives on whatever filesystem holds it
+            let name = by_path(root, path).or_else(|| by_majmin(root, &file_majmin(root, path)?));
+            if let Some(name) = name {
+                protect(&name, format!("swap is on {}", describe(root, &name)));
+            }
+        }
+    }
+
+    if let Ok(entries) = fs::read_dir(root.join("sys/block")) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with("dm-") || name.starts_with("md") {
+                let reason = format!("part of the active {}", describe(root, &name));
+                for slave in slaves(root, &name) {
+                    protect(&slave, reason.clone());
+                }
+            }
+        }
+    }
+
+    disks
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn protected(_root: &Path) -> BTreeMap<String, String> {
+    BTreeMap::new()
+}
+
+/// Kernel name of the block device with this "major:minor" number
+#[cfg(target_os = "linux")]
+fn by_majmin(root: &Path, majmin: &str) -> Option<String> {
+    let dir = std::fs::canonicalize(root.join(format!("sys/dev/block/{}", majmin))).ok()?;
+    Some(dir.file_name()?.to_string_lossy().to_string())
+}
+
+/// Kernel name of a /dev node, following symlinks such as /dev/mapper/* and /dev/disk/by-uuid/*
+#[cfg(target_os = "linux")]
+fn by_path(root: &Path, path: &str) -> Option<String> {
+    let relative = path.strip_prefix("/dev/")?;
+    let node = std::fs::canonicalize(root.join("dev").join(relative)).ok()?;
+    let name = node.file_name()?.to_string_lossy().to_string();
+    std::fs::metadata(root.join(format!("sys/class/block/{}", name))).is_ok().then_some(name)
+}
+
+/// "major:minor" of the filesystem holding `path`
+#[cfg(target_os = "linux")]
+fn file_majmin(root: &Path, path: &str) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    let dev = std::fs::metadata(root.join(path.trim_start_matches('/'))).ok()?.dev();
+    Some(format!("{}:{}", libc::major(dev), libc::minor(dev)))
+}
+
+/// Devices directly under a dm or md device
+#[cfg(target_os = "linux")]
+fn slaves(root: &Path, name: &str) -> Vec<String> {
+    std::fs::read_dir(root.join(format!("sys/class/block/{}/slaves", name)))
+        .map(|entries| entries.flatten().map(|e| e.file_name().to_string_lossy().to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// The whole disks a block device lives on: the parent of a partition, the drives under
+/// a dm or md stack, or the drive holding a loop device's backing file
+#[cfg(target_os = "linux")]
+fn whole_disks(root: &Path, name: &str) -> Vec<String> {
+    let mut disks = Vec::new();
+    let mut pending = vec![(name.to_string(), 0)];
+    while let Some((name, depth)) = pending.pop() {
+        // Stacks are shallow; the limit only guards against a malformed sysfs
+        if depth > 16 {
+            continue;
+        }
+        let dir = root.join(format!("sys/class/block/{}", name));
+        let below = slaves(root, &name);
+        if !below.is_empty() {
+            pending.extend(below.into_iter().map(|s| (s, depth + 1)));
+        } else if std::fs::metadata(dir.join("partition")).is_ok() {
+            if let Ok(path) = std::fs::canonicalize(&dir)
+                && let Some(parent) = path.parent().and_then(|p| p.file_name())
+            {
+                disks.push(parent.to_string_lossy().to_string());
+            }
+        } else if let Ok(file) = std::fs::read_to_string(dir.join("loop/backing_file")) {
+            if let Some(below) = file_majmin(root, file.trim()).and_then(|m| by_majmin(root, &m)) {
+                pending.push((below, depth + 1));
+            }
+        } else {
+            disks.push(name);
+        }
+    }
+    disks.sort();
+    disks.dedup();
+    disks
+}
+
+/// "sda2", or "dm-0 (vg-root)" for device-mapper devices
+#[cfg(target_os = "linux")]
+pub fn describe(root: &Path, name: &str) -> String {
+    match std::fs::read_to_string(root.join(format!("sys/class/block/{}/dm/name", name))) {
+        Ok(dm) => format!("{} ({})", name, dm.trim()),
+        Err(_) => name.to_string(),
+    }
+}
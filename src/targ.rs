@@ -3,8 +3,9 @@
 
 use std::fs;
 use std::io::{self, Result, Write, stdout};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::{Duration, Instant};
 
 use crossterm::{
     execute,
@@ -14,104 +15,642 @@ use crossterm::{
     event::{self, KeyCode, Event},
 };
 
+use crate::identity::{self, Identity};
+use crate::units::{duration, human};
+use crate::{fsprobe, hotplug, parttable, sysdisk};
+
+/// Kernel events arrive in bursts (the disk, then each partition); wait for the last one
+const HOTPLUG_SETTLE: Duration = Duration::from_millis(500);
+/// Share of a USB link's signalling rate left for data after encoding and protocol overhead
+const USB_EFFICIENCY: f64 = 0.7;
+
 /// Unified structure for displaying drives
-#[derive(Debug, Clone)]
-struct DriveInfo {
-    path: String,
+#[derive(Debug, Clone, Default)]
+pub struct DriveInfo {
+    pub path: String,
     model: Option<String>,
+    vendor: Option<String>,
+    serial: Option<String>,
+    /// Capacity in bytes
+    size: Option<u64>,
+    /// How the drive is attached: "USB", "SD/MMC", "NVMe", "SATA"...
+    transport: Option<&'static str>,
+    /// Speed the USB connection was negotiated at, for USB drives on Linux
+    usb_link: Option<UsbLink>,
+    read_only: bool,
+    /// Reported as removable; other drives are only listed in "show all drives" mode
+    removable: bool,
+    /// Number of partitions the kernel sees on the drive
+    partitions: usize,
+    /// Filesystem labels found on the drive
+    labels: Vec<String>,
+    /// Why the running system needs this drive, if it does
+    protected: Option<String>,
+    /// What makes the drive this drive, read along with the rest of the listing so the
+    /// drive that gets written is the one that was on screen
+    pub identity: Identity,
 }
 
-/// Windows: list removable drives with model names
-#[cfg(target_os = "windows")]
-fn list_flashable_drives_windows() -> Vec<DriveInfo> {
-    let mut drives = Vec::new();
+impl DriveInfo {
+    /// First row of the menu entry: what the drive is
+    fn title(&self) -> String {
+        let mut title = self.path.clone();
+        let name: Vec<&str> = [&self.vendor, &self.model].into_iter().flatten().map(String::as_str).collect();
+        if !name.is_empty() {
+            title.push_str(&format!(" — {}", name.join(" ")));
+        }
+        if let Some(size) = self.size {
+            title.push_str(&format!("  {}", human(size)));
+        }
+        if let Some(transport) = self.transport {
+            title.push_str(&format!("  {}", transport));
+        }
+        if let Some(link) = self.usb_link {
+            title.push_str(&format!(" {}", link.rate()));
+        }
+        if let Some(serial) = &self.serial {
+            title.push_str(&format!("  S/N {}", serial));
+        }
+        title
+    }
 
-    let output = Command::new("powershell")
-        .args([
-            "-Command",
-            "Get-CimInstance Win32_DiskDrive | Where-Object { $_.MediaType -eq 'Removable Media' -or $_.InterfaceType -eq 'USB' } | Select-Object DeviceID, Model",
-        ])
-        .output()
-        .expect("failed to run PowerShell command");
+    /// Second row: what is on it
+    fn contents(&self) -> String {
+        let labels = self.labels.iter().map(|l| format!("\"{}\"", l)).collect::<Vec<_>>().join(", ");
+        match (self.partitions, labels.is_empty()) {
+            (0, true) => "no partitions".to_string(),
+            (0, false) => format!("no partitions, labelled {}", labels),
+            (1, true) => "1 partition".to_string(),
+            (1, false) => format!("1 partition: {}", labels),
+            (n, true) => format!("{} partitions", n),
+            (n, false) => format!("{} partitions: {}", n, labels),
+        }
+    }
+}
 
-    let text = String::from_utf8_lossy(&output.stdout);
+/// A negotiated USB connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct UsbLink {
+    /// Signalling rate in Mbit/s: 1.5, 12, 480, 5000, 10000 or 20000
+    mbps: f64,
+    /// The device says it can do SuperSpeed (USB 3), whatever it got this time
+    superspeed_capable: bool,
+}
 
-    for line in text.lines() {
-        if line.trim().is_empty() || line.contains("DeviceID") {
-            continue;
+impl UsbLink {
+    /// "480 Mbit/s", "5 Gbit/s"
+    fn rate(&self) -> String {
+        if self.mbps >= 1000.0 {
+            format!("{} Gbit/s", self.mbps / 1000.0)
+        } else {
+            format!("{} Mbit/s", self.mbps)
+        }
+    }
+
+    /// "USB 2.0 High Speed (480 Mbit/s)"
+    fn name(&self) -> String {
+        let generation = match self.mbps {
+            m if m < 12.0 => "USB 1.x Low Speed",
+            m if m < 480.0 => "USB 1.x Full Speed",
+            m if m < 5000.0 => "USB 2.0 High Speed",
+            m if m < 10000.0 => "USB 3 SuperSpeed",
+            _ => "USB 3 SuperSpeed+",
+        };
+        format!("{} ({})", generation, self.rate())
+    }
+
+    /// A USB 3 drive held back to USB 2 or slower, usually by the port or cable
+    fn slowed(&self) -> bool {
+        self.superspeed_capable && self.mbps < 5000.0
+    }
+
+    /// Best case write speed through this link, in bytes per second
+    fn bytes_per_sec(&self) -> f64 {
+        self.mbps * 1e6 / 8.0 * USB_EFFICIENCY
+    }
+
+    /// Confirm screen lines: the link, the shortest time writing `bytes` can take over it,
+    /// and a warning when the drive could go faster
+    fn lines(&self, bytes: u64) -> Vec<String> {
+        let mut lines = vec![if bytes == 0 {
+            format!("Connected over {}", self.name())
+        } else {
+            format!(
+                "Connected over {}: writing {} takes at least {}",
+                self.name(),
+                human(bytes),
+                duration(bytes as f64 / self.bytes_per_sec())
+            )
+        }];
+        if self.slowed() {
+            lines.push(format!(
+                "{} This USB 3 drive is running at {} speed. A USB 3 port (often blue) allows up to {:.0}x faster writes",
+                "[ SLOW USB LINK ]".yellow().bold(),
+                if self.mbps < 480.0 { "USB 1" } else { "USB 2" },
+                5000.0 / self.mbps
+            ));
         }
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() >= 2 {
-            let device_id = parts[0].trim().to_string();
-            let model = parts[1..].join(" ");
-            drives.push(DriveInfo { path: device_id, model: Some(model) });
-        } else if parts.len() == 1 && parts[0].starts_with("\\\\.\\") {
-            drives.push(DriveInfo { path: parts[0].trim().to_string(), model: None });
+        lines
+    }
+}
+
+/// What the USB link of `dev` means for writing `bytes` to it, for the confirm screen
+///
+/// sysfs is looked up under `root`, which is "/" except when testing.
+pub fn link_lines(root: &Path, dev: &str, bytes: u64) -> Vec<String> {
+    #[cfg(target_os = "linux")]
+    {
+        let name = dev.trim_start_matches("/dev/");
+        let sys = root.join("sys/block").join(name);
+        if linux_transport(name, &sys) == Some("USB")
+            && let Some(link) = linux_usb_link(root, &sys)
+        {
+            return link.lines(bytes);
         }
     }
+    Vec::new()
+}
+
+/// Where the drive list comes from
+trait DriveSource {
+    /// Removable drives, or every physical drive with `show_all` where that can be told apart
+    fn drives(&self, show_all: bool) -> Result<Vec<DriveInfo>>;
 
-    drives
+    /// Whether `show_all` lists anything more, so the menu only offers the toggle where it works
+    fn can_show_all(&self) -> bool {
+        false
+    }
 }
 
-/// macOS: list external drives with model names
+/// Windows: Win32_DiskDrive through PowerShell
+#[cfg(target_os = "windows")]
+struct PowerShell;
+
+#[cfg(target_os = "windows")]
+impl DriveSource for PowerShell {
+    fn drives(&self, _show_all: bool) -> Result<Vec<DriveInfo>> {
+        let mut drives = list_flashable_drives_windows()?;
+        for drive in &mut drives {
+            drive.identity = identity::pin(Path::new("/"), &drive.path);
+        }
+        Ok(drives)
+    }
+}
+
+/// macOS: diskutil
 #[cfg(target_os = "macos")]
-fn list_flashable_drives_macos() -> Vec<DriveInfo> {
-    let mut drives = Vec::new();
+struct DiskUtil;
 
-    let output = Command::new("diskutil").arg("list").output().expect("failed to run diskutil");
-    let text = String::from_utf8_lossy(&output.stdout);
-
-    for line in text.lines() {
-        if line.contains("external, physical") {
-            if let Some(disk_name) = line.split_whitespace().next() {
-                let path = format!("/dev/{}", disk_name);
-                let info_output = Command::new("diskutil").args(["info", &path]).output().unwrap();
-                let info_text = String::from_utf8_lossy(&info_output.stdout);
-
-                let mut model = None;
-                for infoline in info_text.lines() {
-                    if infoline.contains("Device / Media Name:") {
-                        model = Some(infoline.split(':').nth(1).unwrap_or("").trim().to_string());
-                        break;
-                    }
-                }
+#[cfg(target_os = "macos")]
+impl DriveSource for DiskUtil {
+    fn drives(&self, _show_all: bool) -> Result<Vec<DriveInfo>> {
+        let mut drives = list_flashable_drives_macos()?;
+        for drive in &mut drives {
+            drive.identity = identity::pin(Path::new("/"), &drive.path);
+        }
+        Ok(drives)
+    }
+}
 
-                drives.push(DriveInfo { path, model });
-            }
+/// Linux: sysfs, /dev, /proc and the udev database, all found under `root`
+///
+/// The root is "/" for the running system; tests point it at a fake tree.
+#[cfg(target_os = "linux")]
+struct Sysfs {
+    root: PathBuf,
+}
+
+#[cfg(target_os = "linux")]
+impl DriveSource for Sysfs {
+    fn drives(&self, show_all: bool) -> Result<Vec<DriveInfo>> {
+        list_flashable_drives_linux(&self.root, show_all)
+    }
+
+    fn can_show_all(&self) -> bool {
+        true
+    }
+}
+
+/// The drive source for the platform tEtcher is running on
+fn system_drives() -> impl DriveSource {
+    #[cfg(target_os = "windows")] { PowerShell }
+    #[cfg(target_os = "macos")] { DiskUtil }
+    #[cfg(target_os = "linux")] { Sysfs { root: PathBuf::from("/") } }
+}
+
+/// Windows: list removable and USB drives with model names
+#[cfg(target_os = "windows")]
+fn list_flashable_drives_windows() -> Result<Vec<DriveInfo>> {
+    // @(...) keeps the output a JSON array even when there is only one drive
+    let json = run(Command::new("powershell").args([
+        "-NoProfile",
+        "-Command",
+        "ConvertTo-Json -InputObject @(Get-CimInstance Win32_DiskDrive | Select-Object DeviceID, Model, SerialNumber, Size, InterfaceType, MediaType, Partitions)",
+    ]))?;
+    parse_win32_disk_drives(&String::from_utf8_lossy(&json))
+}
+
+/// Drives worth offering from `Get-CimInstance Win32_DiskDrive | ConvertTo-Json` output
+fn parse_win32_disk_drives(json: &str) -> Result<Vec<DriveInfo>> {
+    use serde_json::Value;
+
+    if json.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let value: Value = serde_json::from_str(json).map_err(|e| unreadable("PowerShell", e))?;
+    // Older PowerShells unwrap a one-element array into a bare object
+    let disks = match value {
+        Value::Array(disks) => disks,
+        disk => vec![disk],
+    };
+
+    let text = |disk: &Value, key: &str| {
+        disk.get(key).and_then(Value::as_str).map(str::trim).filter(|s| !s.is_empty()).map(str::to_string)
+    };
+    let mut drives = Vec::new();
+    for disk in &disks {
+        let Some(path) = text(disk, "DeviceID") else { continue };
+        let interface = text(disk, "InterfaceType");
+        let media = text(disk, "MediaType");
+        let removable = media.as_deref() == Some("Removable Media");
+        if !removable && interface.as_deref() != Some("USB") {
+            continue;
         }
+        // Card readers with no card in them report no size
+        let Some(size) = disk.get("Size").and_then(Value::as_u64).filter(|&s| s > 0) else { continue };
+        drives.push(DriveInfo {
+            path,
+            // Windows tacks the bus onto USB models; the transport column already says it
+            model: text(disk, "Model").map(|m| m.trim_end_matches(" USB Device").to_string()),
+            serial: text(disk, "SerialNumber"),
+            size: Some(size),
+            transport: match interface.as_deref() {
+                Some("USB") => Some("USB"),
+                Some("SCSI") => Some("SCSI"),
+                Some("IDE") => Some("IDE"),
+                Some("1394") => Some("FireWire"),
+                _ => None,
+            },
+            // External hard disks are not removable media, but they are not the system disk either
+            removable: true,
+            partitions: disk.get("Partitions").and_then(Value::as_u64).unwrap_or(0) as usize,
+            ..DriveInfo::default()
+        });
     }
+    Ok(drives)
+}
 
-    drives
+/// macOS: list external physical drives with model names
+#[cfg(target_os = "macos")]
+fn list_flashable_drives_macos() -> Result<Vec<DriveInfo>> {
+    let list = run(Command::new("diskutil").args(["list", "-plist", "external", "physical"]))?;
+    let mut drives = parse_diskutil_list(&list)?;
+    for drive in &mut drives {
+        let info = run(Command::new("diskutil").args(["info", "-plist", &drive.path]))?;
+        parse_diskutil_info(&info, drive)?;
+    }
+    Ok(drives)
+}
+
+/// Whole disks, their partition counts and volume names from `diskutil list -plist`
+fn parse_diskutil_list(plist: &[u8]) -> Result<Vec<DriveInfo>> {
+    let value = plist::Value::from_reader_xml(plist).map_err(|e| unreadable("diskutil", e))?;
+    let disks = value
+        .as_dictionary()
+        .and_then(|d| d.get("AllDisksAndPartitions"))
+        .and_then(plist::Value::as_array)
+        .ok_or_else(|| unreadable("diskutil", "no AllDisksAndPartitions list"))?;
+
+    let mut drives = Vec::new();
+    for disk in disks.iter().filter_map(plist::Value::as_dictionary) {
+        let Some(id) = disk.get("DeviceIdentifier").and_then(plist::Value::as_string) else { continue };
+        let partitions: Vec<&plist::Dictionary> = disk
+            .get("Partitions")
+            .and_then(plist::Value::as_array)
+            .map(|parts| parts.iter().filter_map(plist::Value::as_dictionary).collect())
+            .unwrap_or_default();
+        // A disk formatted without a partition table carries its volume name itself
+        let volumes = if partitions.is_empty() { vec![disk] } else { partitions.clone() };
+        let labels = volumes
+            .iter()
+            .filter_map(|v| v.get("VolumeName").and_then(plist::Value::as_string))
+            .filter(|name| !name.is_empty() && *name != "EFI")
+            .map(str::to_string)
+            .collect();
+        drives.push(DriveInfo {
+            path: format!("/dev/{}", id),
+            size: disk.get("Size").and_then(plist::Value::as_unsigned_integer),
+            removable: true,
+            partitions: partitions.len(),
+            labels,
+            ..DriveInfo::default()
+        });
+    }
+    Ok(drives)
+}
+
+/// Fill in model, bus, size and write protection from `diskutil info -plist`
+fn parse_diskutil_info(plist: &[u8], drive: &mut DriveInfo) -> Result<()> {
+    let value = plist::Value::from_reader_xml(plist).map_err(|e| unreadable("diskutil", e))?;
+    let info = value.as_dictionary().ok_or_else(|| unreadable("diskutil", "not a dictionary"))?;
+    let text = |key: &str| {
+        info.get(key).and_then(plist::Value::as_string).map(str::trim).filter(|s| !s.is_empty())
+    };
+    let flag = |key: &str| info.get(key).and_then(plist::Value::as_boolean);
+
+    drive.model = text("MediaName").map(str::to_string).or(drive.model.take());
+    drive.transport = match text("BusProtocol") {
+        Some("USB") => Some("USB"),
+        Some("Secure Digital") => Some("SD/MMC"),
+        Some("SATA") => Some("SATA"),
+        Some("PCI-Express") => Some("NVMe"),
+        Some("Thunderbolt") => Some("Thunderbolt"),
+        Some("FireWire") => Some("FireWire"),
+        _ => drive.transport,
+    };
+    if let Some(size) = info.get("TotalSize").or_else(|| info.get("Size")).and_then(plist::Value::as_unsigned_integer) {
+        drive.size = Some(size);
+    }
+    drive.read_only = flag("WritableMedia") == Some(false);
+    drive.removable = flag("Internal") != Some(true);
+    Ok(())
+}
+
+/// Output of a listing command, or why it failed
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+fn run(command: &mut Command) -> Result<Vec<u8>> {
+    let output = command.output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("{} failed: {}", command.get_program().to_string_lossy(), stderr.trim())));
+    }
+    Ok(output.stdout)
+}
+
+fn unreadable(tool: &str, why: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("could not read the drive list from {}: {}", tool, why))
 }
 
-/// Linux: list removable drives with model names
+/// Linux: list removable drives with model names, or every physical drive with `show_all`
 #[cfg(target_os = "linux")]
-fn list_flashable_drives_linux() -> Result<Vec<DriveInfo>> {
+fn list_flashable_drives_linux(root: &Path, show_all: bool) -> Result<Vec<DriveInfo>> {
     let mut drives = Vec::new();
+    let protected = sysdisk::protected(root);
 
-    for entry in fs::read_dir("/sys/block")? {
+    for entry in fs::read_dir(root.join("sys/block"))? {
         let entry = entry?;
         let dev_str_os = entry.file_name();
         let dev_str = dev_str_os.to_string_lossy().to_string(); // convert to owned String
-        let removable_path = format!("/sys/block/{}/removable", dev_str);
-
-        if let Ok(contents) = fs::read_to_string(&removable_path) {
-            if contents.trim() == "1" {
-                let model_path = format!("/sys/block/{}/device/model", dev_str);
-                let model = fs::read_to_string(&model_path).ok().map(|s| s.trim().to_string());
-                let dev_path = format!("/dev/{}", dev_str);
-                if fs::metadata(&dev_path).is_ok() {
-                    drives.push(DriveInfo { path: dev_path, model });
-                }
-            }
+        let removable_path = entry.path().join("removable");
+
+        let removable = fs::read_to_string(&removable_path).is_ok_and(|contents| contents.trim() == "1");
+        // Loop, RAM, dm and md devices have no "device" link, and show_all adds no fixed optical drives
+        let physical = fs::metadata(entry.path().join("device")).is_ok() && !dev_str.starts_with("sr");
+        if (removable || (show_all && physical)) && fs::metadata(root.join("dev").join(&dev_str)).is_ok() {
+            let mut drive = linux_drive(root, &dev_str);
+            drive.protected = protected.get(&dev_str).cloned();
+            drive.identity = identity::pin(root, &drive.path);
+            drives.push(drive);
         }
     }
 
+    drives.sort_by(|a, b| a.path.cmp(&b.path));
     Ok(drives)
 }
 
+/// Everything sysfs and the drive's own headers say about /dev/`name`
+#[cfg(target_os = "linux")]
+fn linux_drive(root: &Path, name: &str) -> DriveInfo {
+    let sys = root.join("sys/block").join(name);
+    let read = |file: &str| {
+        fs::read_to_string(sys.join(file))
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+    let path = format!("/dev/{}", name);
+
+    // Partitions are the sysfs children that have a "partition" file
+    let parts: Vec<String> = fs::read_dir(&sys)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .filter(|child| fs::metadata(sys.join(child).join("partition")).is_ok())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let transport = linux_transport(name, &sys);
+    DriveInfo {
+        // SD cards name themselves in device/name rather than device/model
+        model: read("device/model").or_else(|| read("device/name")),
+        // virtio and some other buses give a numeric PCI vendor ID here, which says nothing useful
+        vendor: read("device/vendor").filter(|v| !v.starts_with("0x")),
+        serial: linux_serial(root, &sys),
+        size: read("size").and_then(|s| s.parse::<u64>().ok()).map(|s| s * 512),
+        transport,
+        usb_link: if transport == Some("USB") { linux_usb_link(root, &sys) } else { None },
+        read_only: read("ro").as_deref() == Some("1"),
+        removable: read("removable").as_deref() == Some("1"),
+        partitions: parts.len(),
+        labels: linux_labels(root, name, &sys, &parts),
+        protected: None,
+        identity: Identity::default(),
+        path,
+    }
+}
+
+/// Bus the drive hangs off, judged by its place in the sysfs device tree
+#[cfg(target_os = "linux")]
+fn linux_transport(name: &str, sys: &Path) -> Option<&'static str> {
+    let real = fs::canonicalize(sys).ok()?;
+    let real = real.to_string_lossy();
+    if real.contains("/usb") {
+        Some("USB")
+    } else if name.starts_with("mmcblk") {
+        Some("SD/MMC")
+    } else if name.starts_with("nvme") {
+        Some("NVMe")
+    } else if real.contains("/ata") {
+        Some("SATA")
+    } else if real.contains("/virtio") {
+        Some("VirtIO")
+    } else if real.contains("/host") {
+        Some("SCSI")
+    } else {
+        None
+    }
+}
+
+/// Link speed of the USB device a drive sits behind, the nearest ancestor with a "speed" file
+#[cfg(target_os = "linux")]
+fn linux_usb_link(root: &Path, sys: &Path) -> Option<UsbLink> {
+    let top = fs::canonicalize(root.join("sys/devices")).ok()?;
+    let mut dir = fs::canonicalize(sys.join("device")).ok()?;
+    while dir.pop() && dir.starts_with(&top) && dir != top {
+        let Ok(speed) = fs::read_to_string(dir.join("speed")) else { continue };
+        let mbps = speed.trim().parse().ok()?;
+        // A USB 3 device that only got a USB 2 connection reports version 2.10, so its
+        // SuperSpeed capability has to come from the BOS descriptor where the kernel has it
+        let version = fs::read_to_string(dir.join("version")).unwrap_or_default();
+        let major: u32 = version.trim().split('.').next().and_then(|v| v.parse().ok()).unwrap_or(0);
+        let bos = fs::read(dir.join("bos_descriptors")).unwrap_or_default();
+        return Some(UsbLink { mbps, superspeed_capable: major >= 3 || bos_superspeed(&bos) });
+    }
+    None
+}
+
+/// Whether a USB BOS descriptor lists a SuperSpeed or SuperSpeedPlus device capability
+fn bos_superspeed(bos: &[u8]) -> bool {
+    // 5-byte BOS header, then device capability descriptors: length, type 0x10, capability
+    let mut at = match bos.get(..2) {
+        Some([len, 0x0f]) => *len as usize,
+        _ => return false,
+    };
+    while let Some(&[len, kind, capability]) = bos.get(at..at + 3) {
+        if kind == 0x10 && (capability == 0x03 || capability == 0x0a) {
+            return true;
+        }
+        if len == 0 {
+            break;
+        }
+        at += len as usize;
+    }
+    false
+}
+
+/// Filesystem labels from the drive's headers, or from the udev database when the
+/// drive cannot be opened (tEtcher not yet running as root)
+#[cfg(target_os = "linux")]
+fn linux_labels(root: &Path, name: &str, sys: &Path, parts: &[String]) -> Vec<String> {
+    let path = root.join("dev").join(name);
+    if let Ok(mut file) = fs::File::open(&path) {
+        let starts = match parttable::read(&path) {
+            Some(table) => table.partitions.iter().map(|p| p.start).collect(),
+            None => vec![0],
+        };
+        return starts
+            .into_iter()
+            .filter_map(|start| fsprobe::probe(&mut file, start)?.label)
+            .collect();
+    }
+
+    let mut parts = parts.to_vec();
+    parts.sort();
+    parts
+        .iter()
+        .filter_map(|part| {
+            let dev = fs::read_to_string(sys.join(part).join("dev")).ok()?;
+            let db = fs::read_to_string(root.join(format!("run/udev/data/b{}", dev.trim()))).ok()?;
+            db.lines().find_map(|l| l.strip_prefix("E:ID_FS_LABEL=")).map(str::to_string)
+        })
+        .collect()
+}
+
+/// Model, serial number and size of a device, for reports
+#[derive(Debug, Clone, Default)]
+pub struct DeviceDetails {
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub size: Option<u64>,
+}
+
+/// Look up what we can about the device at `path`, under `root` ("/" except when testing)
+pub fn device_details(root: &Path, path: &str) -> DeviceDetails {
+    #[cfg(target_os = "linux")]
+    {
+        let name = path.trim_start_matches("/dev/");
+        let sys = root.join("sys/block").join(name);
+        if fs::metadata(&sys).is_ok() {
+            let read = |file: &str| {
+                fs::read_to_string(sys.join(file))
+                    .ok()
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+            };
+            return DeviceDetails {
+                model: read("device/model"),
+                serial: linux_serial(root, &sys),
+                // sysfs sizes are always in 512-byte sectors
+                size: read("size").and_then(|s| s.parse::<u64>().ok()).map(|s| s * 512),
+            };
+        }
+    }
+
+    // Elsewhere (or for plain files), at least the size can be found by seeking to the end
+    let size = fs::File::open(root.join(path.trim_start_matches('/')))
+        .and_then(|mut f| std::io::Seek::seek(&mut f, std::io::SeekFrom::End(0)))
+        .ok();
+    DeviceDetails { size, ..DeviceDetails::default() }
+}
+
+/// Serial number from the udev database, or from the nearest sysfs ancestor that has one
+#[cfg(target_os = "linux")]
+fn linux_serial(root: &Path, sys: &Path) -> Option<String> {
+    if let Ok(dev) = fs::read_to_string(sys.join("dev"))
+        && let Ok(db) = fs::read_to_string(root.join(format!("run/udev/data/b{}", dev.trim())))
+        && let Some(serial) = db.lines().find_map(|l| l.strip_prefix("E:ID_SERIAL_SHORT="))
+    {
+        return Some(serial.to_string());
+    }
+
+    // USB sticks expose the serial on the USB device a few levels up
+    let mut dir = fs::canonicalize(sys.join("device")).ok()?;
+    let top = fs::canonicalize(root.join("sys/devices")).ok()?;
+    while dir.pop() {
+        if let Ok(serial) = fs::read_to_string(dir.join("serial")) {
+            let serial = serial.trim();
+            if !serial.is_empty() {
+                return Some(serial.to_string());
+            }
+        }
+        if dir == top {
+            break;
+        }
+    }
+    None
+}
+
+/// Note under the drive list about system drives left out of it
+fn hidden_lines(hidden: &[DriveInfo]) -> Vec<String> {
+    if hidden.is_empty() {
+        return Vec::new();
+    }
+    let mut lines = vec![format!(
+        "{} hidden because the running system uses {}:",
+        hidden.len(),
+        if hidden.len() == 1 { "it" } else { "them" }
+    )
+    .dark_grey()
+    .to_string()];
+    for drive in hidden {
+        let reason = drive.protected.as_deref().unwrap_or_default();
+        lines.push(format!("  {} ({})", drive.path, reason).dark_grey().to_string());
+    }
+    lines.push("Run with --allow-system-disk to list them anyway".dark_grey().to_string());
+    lines
+}
+
+/// Drives to offer, and the system drives left out because `allow_system_disk` is off
+///
+/// `show_all` adds non-removable drives on Linux. Elsewhere there is no way to tell the
+/// system disk yet, so only removable and external drives are listed either way.
+fn list_drives(
+    source: &impl DriveSource,
+    allow_system_disk: bool,
+    show_all: bool,
+) -> Result<(Vec<DriveInfo>, Vec<DriveInfo>)> {
+    let drives = source.drives(show_all)?;
+    Ok(drives.into_iter().partition(|drive| allow_system_disk || drive.protected.is_none()))
+}
+
 /// Menu UI for selecting which drive to flash to
-pub fn menu() -> Result<Option<String>> {
+///
+/// Drives the running system depends on are hidden unless `allow_system_disk` is set.
+/// The list follows drives being plugged in and removed while it is open. Returns the
+/// drive as it was listed when picked, identity included.
+pub fn menu(allow_system_disk: bool) -> Result<Option<DriveInfo>> {
     let mut stdout = stdout();
     print!("\x1B[H\x1B[2J");
     io::stdout().flush()?;
@@ -119,57 +658,603 @@ pub fn menu() -> Result<Option<String>> {
     enable_raw_mode()?;
     execute!(stdout, cursor::Hide)?;
 
-    let extdevs: Vec<DriveInfo> = {
-        #[cfg(target_os = "windows")] { list_flashable_drives_windows() }
-        #[cfg(target_os = "macos")] { list_flashable_drives_macos() }
-        #[cfg(target_os = "linux")] { list_flashable_drives_linux()? }
-    };
+    let source = system_drives();
+    let mut watcher = hotplug::Watcher::new();
+    let mut show_all = false;
+    let (mut extdevs, mut hidden) = list_drives(&source, allow_system_disk, show_all)?;
+    let mut extselected = 0;
+    // Set after a hotplug event: re-list once the burst of events has died down
+    let mut relist_at: Option<Instant> = None;
+    let mut redraw = true;
 
-    if extdevs.is_empty() {
-        println!("No removable drives detected.");
-        println!("Insert a USB drive and restart the program.");
-        disable_raw_mode()?;
-        return Ok(None);
+    loop {
+        if redraw {
+            let toggle = source.can_show_all().then_some(show_all);
+            draw(&extdevs, &hidden, extselected, toggle)?;
+            redraw = false;
+        }
+
+        if event::poll(Duration::from_millis(200))?
+            && let Event::Key(ev) = event::read()?
+        {
+            redraw = true;
+            match ev.code {
+                KeyCode::Up => { extselected = extselected.saturating_sub(1); }
+                KeyCode::Down if extselected + 1 < extdevs.len() => { extselected += 1; }
+                KeyCode::Char('a') | KeyCode::Char('A') if source.can_show_all() => {
+                    show_all = !show_all;
+                    relist_at = Some(Instant::now());
+                }
+                KeyCode::Enter if !extdevs.is_empty() => {
+                    let selected_device = extdevs[extselected].clone();
+                    // A drive that does not call itself removable could be anything: make sure
+                    if !selected_device.removable && !confirm_typed(&selected_device)? {
+                        continue;
+                    }
+                    disable_raw_mode()?;
+                    execute!(stdout, cursor::Show)?;
+                    return Ok(Some(selected_device));
+                }
+                KeyCode::Esc => { disable_raw_mode()?; execute!(stdout, cursor::Show)?; return Ok(None); }
+                _ => {}
+            }
+        }
+
+        if watcher.changed() {
+            relist_at = Some(Instant::now() + HOTPLUG_SETTLE);
+        }
+        if relist_at.is_some_and(|at| Instant::now() >= at) {
+            relist_at = None;
+            // Keep the highlight on the same drive, wherever it moved to
+            let current = extdevs.get(extselected).map(|d| d.path.clone());
+            (extdevs, hidden) = list_drives(&source, allow_system_disk, show_all)?;
+            extselected = current
+                .and_then(|path| extdevs.iter().position(|d| d.path == path))
+                .unwrap_or(extselected.min(extdevs.len().saturating_sub(1)));
+            redraw = true;
+        }
     }
+}
 
-    let mut extselected = 0;
+/// Draw the drive list, or the waiting screen when there is nothing to pick
+///
+/// `show_all` is whether every drive is listed, or None where the A toggle is not offered.
+fn draw(extdevs: &[DriveInfo], hidden: &[DriveInfo], extselected: usize, show_all: Option<bool>) -> Result<()> {
+    let mut stdout = stdout();
+    execute!(stdout, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+    let toggle = show_all.map(|all| if all { "A: removable drives only" } else { "A: show all drives" });
 
-    loop {
-        execute!(stdout, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
-        println!("External devices found:");
+    if extdevs.is_empty() {
+        match toggle {
+            Some(toggle) => print!("Waiting for a drive\u{2026} insert a USB drive or SD card (Esc to cancel, {})", toggle),
+            None => print!("Waiting for a drive\u{2026} insert a USB drive or SD card (Esc to cancel)"),
+        }
+        for (i, line) in hidden_lines(hidden).iter().enumerate() {
+            execute!(stdout, cursor::MoveTo(0, (i + 2) as u16))?;
+            print!("{}", line);
+        }
+        return stdout.flush();
+    }
 
-        for (i, item) in extdevs.iter().enumerate() {
-            execute!(stdout, cursor::MoveTo(0, (i + 1) as u16))?;
-            execute!(stdout, terminal::Clear(ClearType::CurrentLine))?;
+    let heading = if show_all == Some(true) { "All drives" } else { "External devices found" };
+    match toggle {
+        Some(toggle) => print!("{} ({}):", heading, toggle),
+        None => print!("{}:", heading),
+    }
 
-            let label = if let Some(model) = &item.model {
-                format!("{} — {}", item.path, model)
-            } else {
-                item.path.clone()
-            };
+    for (i, item) in extdevs.iter().enumerate() {
+        // Two rows per drive: what it is, then what is on it
+        execute!(stdout, cursor::MoveTo(0, (i * 2 + 1) as u16))?;
 
-            if i == extselected {
-                println!("{}", label.on_white().black());
-            } else {
-                println!("{}", label);
-            }
+        let label = item.title();
+
+        if i == extselected {
+            print!("{}", label.on_white().black());
+        } else {
+            print!("{}", label);
+        }
+        if item.read_only {
+            print!("  {}", "[ READ-ONLY ]".yellow().bold());
+        }
+        if !item.removable {
+            print!("  {}", "[ NON-REMOVABLE ]".magenta().bold());
         }
+        if item.usb_link.is_some_and(|link| link.slowed()) {
+            print!("  {} USB 3 drive on a slower port", "[ SLOW USB LINK ]".yellow().bold());
+        }
+        if let Some(reason) = &item.protected {
+            print!("  {} {}", "[ SYSTEM DISK ]".red().bold(), reason);
+        }
+
+        execute!(stdout, cursor::MoveTo(0, (i * 2 + 2) as u16))?;
+        print!("    {}", item.contents().dark_grey());
+    }
+
+    for (i, line) in hidden_lines(hidden).iter().enumerate() {
+        execute!(stdout, cursor::MoveTo(0, (extdevs.len() * 2 + 2 + i) as u16))?;
+        print!("{}", line);
+    }
+
+    stdout.flush()
+}
+
+/// Make the user type the model or serial number of a non-removable drive before using it
+fn confirm_typed(drive: &DriveInfo) -> Result<bool> {
+    let mut stdout = stdout();
+    // Drives that report neither are confirmed by their kernel name, e.g. "sdb"
+    let name = drive.path.trim_start_matches("/dev/").to_string();
+    let expected: Vec<&str> = match (&drive.model, &drive.serial) {
+        (None, None) => vec![name.as_str()],
+        (model, serial) => [model, serial].into_iter().flatten().map(|s| s.trim()).collect(),
+    };
+    let what = match (&drive.model, &drive.serial) {
+        (Some(_), Some(_)) => "its model or serial number",
+        (Some(_), None) => "its model",
+        (None, Some(_)) => "its serial number",
+        (None, None) => "its device name",
+    };
 
+    let mut typed = String::new();
+    let mut mismatch = false;
+    loop {
+        execute!(stdout, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+        print!("{} {} is not a removable drive", "[ NON-REMOVABLE ]".magenta().bold(), drive.path);
+        execute!(stdout, cursor::MoveTo(0, 1))?;
+        print!("{}", drive.title());
+        execute!(stdout, cursor::MoveTo(0, 3))?;
+        print!("Everything on it will be lost. To use it anyway, type {} (Esc to go back):", what);
+        execute!(stdout, cursor::MoveTo(0, 4))?;
+        print!("> {}", typed);
+        if mismatch {
+            execute!(stdout, cursor::MoveTo(0, 6))?;
+            print!("{}", "That does not match this drive".red());
+        }
         stdout.flush()?;
 
         if let Event::Key(ev) = event::read()? {
             match ev.code {
-                KeyCode::Up => { if extselected > 0 { extselected -= 1; } }
-                KeyCode::Down => { if extselected < extdevs.len() - 1 { extselected += 1; } }
+                KeyCode::Char(c) => typed.push(c),
+                KeyCode::Backspace => { typed.pop(); }
                 KeyCode::Enter => {
-                    let selected_device = extdevs[extselected].clone();
-                    disable_raw_mode()?;
-                    execute!(stdout, cursor::Show)?;
-                    return Ok(Some(selected_device.path));
-                }         
-                KeyCode::Esc => { disable_raw_mode()?; execute!(stdout, cursor::Show)?; return Ok(None); }
+                    if expected.iter().any(|e| !e.is_empty() && e.eq_ignore_ascii_case(typed.trim())) {
+                        return Ok(true);
+                    }
+                    mismatch = true;
+                    typed.clear();
+                }
+                KeyCode::Esc => return Ok(false),
                 _ => {}
             }
         }
     }
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::os::unix::fs::symlink;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A drive as the fixture should present it in sysfs
+    #[derive(Default)]
+    struct Fake {
+        name: &'static str,
+        /// "major:minor"; partitions get the following minors
+        majmin: &'static str,
+        /// "usb", "ata", "mmc", "virtio", or "" for a virtual device with no "device" link
+        bus: &'static str,
+        removable: bool,
+        /// device/model, padded the way SCSI pads it
+        model: Option<&'static str>,
+        /// device/name, which SD cards use instead of a model
+        card_name: Option<&'static str>,
+        vendor: Option<&'static str>,
+        /// On the USB device for USB drives, in the udev database otherwise
+        serial: Option<&'static str>,
+        /// USB device's negotiated speed in Mbit/s, its USB version, and its BOS descriptor
+        usb_speed: Option<&'static str>,
+        usb_version: Option<&'static str>,
+        bos: Option<&'static [u8]>,
+        sectors: u64,
+        partitions: usize,
+        /// Devices under a dm or md device, and the dm name
+        slaves: &'static [&'static str],
+        dm_name: Option<&'static str>,
+    }
+
+    /// Drive source over a fake /sys, /dev, /proc and /run/udev built from `Fake` drives
+    struct Fixture {
+        root: PathBuf,
+        majmin: BTreeMap<String, String>,
+        mounts: usize,
+    }
+
+    static FIXTURES: AtomicUsize = AtomicUsize::new(0);
+
+    impl Fixture {
+        fn new(drives: &[Fake]) -> Fixture {
+            let n = FIXTURES.fetch_add(1, Ordering::Relaxed);
+            let root = std::env::temp_dir().join(format!("tetcher-fixture-{}-{}", std::process::id(), n));
+            let _ = fs::remove_dir_all(&root);
+            for dir in ["sys/block", "sys/class/block", "sys/dev/block", "dev", "proc/self", "run/udev/data"] {
+                fs::create_dir_all(root.join(dir)).unwrap();
+            }
+            fs::write(root.join("proc/self/mountinfo"), "").unwrap();
+            fs::write(root.join("proc/swaps"), "Filename\tType\tSize\tUsed\tPriority\n").unwrap();
+            let mut fixture = Fixture { root, majmin: BTreeMap::new(), mounts: 0 };
+            for (i, drive) in drives.iter().enumerate() {
+                fixture.add(i, drive);
+            }
+            fixture
+        }
+
+        fn add(&mut self, i: usize, drive: &Fake) {
+            let write = |path: &Path, contents: &str| {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            };
+            let devices = self.root.join("sys/devices");
+            let device = match drive.bus {
+                "usb" => {
+                    let usb = devices.join(format!("pci0000:00/0000:00:14.0/usb1/1-{}", i));
+                    if let Some(serial) = drive.serial {
+                        write(&usb.join("serial"), &format!("{}\n", serial));
+                    }
+                    if let Some(speed) = drive.usb_speed {
+                        write(&usb.join("speed"), &format!("{}\n", speed));
+                    }
+                    if let Some(version) = drive.usb_version {
+                        write(&usb.join("version"), &format!(" {}\n", version));
+                    }
+                    if let Some(bos) = drive.bos {
+                        fs::write(usb.join("bos_descriptors"), bos).unwrap();
+                    }
+                    Some(usb.join(format!("1-{}:1.0/host{}/target{}:0:0/{}:0:0:0", i, i, i, i)))
+                }
+                "ata" => Some(devices.join(format!("pci0000:00/0000:00:17.0/ata{}/host{}/target{}:0:0/{}:0:0:0", i, i, i, i))),
+                "mmc" => Some(devices.join(format!("pci0000:00/0000:00:1e.6/mmc_host/mmc{}/mmc{}:0001", i, i))),
+                "virtio" => Some(devices.join(format!("pci0000:00/0000:00:0{}.0/virtio{}", i, i))),
+                _ => None,
+            };
+            let block = match &device {
+                Some(device) => device.join("block").join(drive.name),
+                None => devices.join("virtual/block").join(drive.name),
+            };
+            fs::create_dir_all(&block).unwrap();
+            if let Some(device) = &device {
+                fs::create_dir_all(device).unwrap();
+                symlink("../..", block.join("device")).unwrap();
+                if let Some(model) = drive.model {
+                    write(&device.join("model"), &format!("{:<16}\n", model));
+                }
+                if let Some(name) = drive.card_name {
+                    write(&device.join("name"), &format!("{}\n", name));
+                }
+                if let Some(vendor) = drive.vendor {
+                    write(&device.join("vendor"), &format!("{:<8}\n", vendor));
+                }
+            }
+            write(&block.join("removable"), if drive.removable { "1\n" } else { "0\n" });
+            write(&block.join("ro"), "0\n");
+            write(&block.join("size"), &format!("{}\n", drive.sectors));
+            for slave in drive.slaves {
+                fs::create_dir_all(block.join("slaves").join(slave)).unwrap();
+            }
+            if let Some(name) = drive.dm_name {
+                write(&block.join("dm/name"), &format!("{}\n", name));
+            }
+            if drive.bus != "usb"
+                && let Some(serial) = drive.serial
+            {
+                write(&self.root.join(format!("run/udev/data/b{}", drive.majmin)), &format!("E:ID_SERIAL_SHORT={}\n", serial));
+            }
+            symlink(&block, self.root.join("sys/block").join(drive.name)).unwrap();
+            self.node(drive.name, drive.majmin, &block);
+
+            let (major, minor) = drive.majmin.split_once(':').unwrap();
+            let minor: usize = minor.parse().unwrap();
+            for p in 1..=drive.partitions {
+                let sep = if drive.name.ends_with(|c: char| c.is_ascii_digit()) { "p" } else { "" };
+                let part = format!("{}{}{}", drive.name, sep, p);
+                let dir = block.join(&part);
+                write(&dir.join("partition"), &format!("{}\n", p));
+                self.node(&part, &format!("{}:{}", major, minor + p), &dir);
+            }
+        }
+
+        /// /dev node, /sys/class/block and /sys/dev/block entries for a disk or partition
+        fn node(&mut self, name: &str, majmin: &str, dir: &Path) {
+            fs::write(dir.join("dev"), format!("{}\n", majmin)).unwrap();
+            symlink(dir, self.root.join("sys/class/block").join(name)).unwrap();
+            symlink(dir, self.root.join("sys/dev/block").join(majmin)).unwrap();
+            fs::write(self.root.join("dev").join(name), "").unwrap();
+            self.majmin.insert(name.to_string(), majmin.to_string());
+        }
+
+        /// Record `dev` as mounted at `point` in /proc/self/mountinfo
+        fn mount(mut self, dev: &str, point: &str) -> Fixture {
+            self.mounts += 1;
+            let line = format!(
+                "{} 1 {} / {} rw,relatime shared:1 - ext4 /dev/{} rw\n",
+                20 + self.mounts,
+                self.majmin[dev],
+                point,
+                dev
+            );
+            let path = self.root.join("proc/self/mountinfo");
+            let mountinfo = fs::read_to_string(&path).unwrap() + &line;
+            fs::write(path, mountinfo).unwrap();
+            self
+        }
+    }
+
+    impl DriveSource for Fixture {
+        fn drives(&self, show_all: bool) -> Result<Vec<DriveInfo>> {
+            Sysfs { root: self.root.clone() }.drives(show_all)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn paths(drives: &[DriveInfo]) -> Vec<&str> {
+        drives.iter().map(|d| d.path.as_str()).collect()
+    }
+
+    fn stick(name: &'static str, majmin: &'static str) -> Fake {
+        Fake {
+            name,
+            majmin,
+            bus: "usb",
+            removable: true,
+            model: Some("Cruzer Blade"),
+            vendor: Some("SanDisk"),
+            sectors: 60_063_744,
+            partitions: 1,
+            ..Fake::default()
+        }
+    }
+
+    fn ssd(name: &'static str, majmin: &'static str) -> Fake {
+        Fake { name, majmin, bus: "ata", model: Some("Samsung SSD 870"), sectors: 976_773_168, partitions: 2, ..Fake::default() }
+    }
+
+    #[test]
+    fn lists_removable_drives_unless_showing_all() {
+        let fixture = Fixture::new(&[
+            ssd("sda", "8:0"),
+            stick("sdb", "8:16"),
+            // Optical drives report themselves removable
+            Fake { name: "sr0", majmin: "11:0", bus: "ata", removable: true, ..Fake::default() },
+            Fake { name: "loop0", majmin: "7:0", ..Fake::default() },
+            Fake { name: "zram0", majmin: "252:0", ..Fake::default() },
+        ]);
+
+        // The removable flag is all that counts by default, so sr0 is listed next to the stick
+        let (listed, hidden) = list_drives(&fixture, false, false).unwrap();
+        assert_eq!(paths(&listed), ["/dev/sdb", "/dev/sr0"]);
+        assert!(hidden.is_empty());
+        assert!(listed.iter().all(|d| d.removable));
+
+        // Showing all adds fixed physical drives; virtual devices stay out even then
+        let (listed, _) = list_drives(&fixture, false, true).unwrap();
+        assert_eq!(paths(&listed), ["/dev/sda", "/dev/sdb", "/dev/sr0"]);
+        assert!(!listed[0].removable);
+    }
+
+    #[test]
+    fn reads_model_vendor_serial_and_transport() {
+        let fixture = Fixture::new(&[
+            Fake { serial: Some("4C530001230614117443"), ..stick("sdb", "8:16") },
+            Fake {
+                name: "mmcblk0",
+                majmin: "179:0",
+                bus: "mmc",
+                removable: true,
+                card_name: Some("SC32G"),
+                serial: Some("0x1234abcd"),
+                sectors: 62_333_952,
+                partitions: 2,
+                ..Fake::default()
+            },
+            Fake {
+                name: "vdb",
+                majmin: "254:16",
+                bus: "virtio",
+                removable: true,
+                vendor: Some("0x1af4"),
+                sectors: 2048,
+                ..Fake::default()
+            },
+        ]);
+        let drives = fixture.drives(false).unwrap();
+        assert_eq!(paths(&drives), ["/dev/mmcblk0", "/dev/sdb", "/dev/vdb"]);
+
+        let card = &drives[0];
+        assert_eq!(card.model.as_deref(), Some("SC32G"));
+        assert_eq!(card.serial.as_deref(), Some("0x1234abcd"));
+        assert_eq!(card.transport, Some("SD/MMC"));
+        assert_eq!(card.partitions, 2);
+
+        // SCSI pads model and vendor with spaces
+        let stick = &drives[1];
+        assert_eq!(stick.model.as_deref(), Some("Cruzer Blade"));
+        assert_eq!(stick.vendor.as_deref(), Some("SanDisk"));
+        assert_eq!(stick.serial.as_deref(), Some("4C530001230614117443"));
+        assert_eq!(stick.transport, Some("USB"));
+        assert_eq!(stick.size, Some(60_063_744 * 512));
+        assert_eq!(stick.title(), "/dev/sdb — SanDisk Cruzer Blade  28.64 GiB  USB  S/N 4C530001230614117443");
+        // Pinned along with the listing, so the drive written is the one that was shown
+        assert_eq!(stick.identity.serial, stick.serial);
+        assert_eq!(stick.identity.size, stick.size);
+        assert_eq!(stick.identity.check(&fixture.root, "/dev/sdb"), Ok(()));
+
+        // A numeric PCI vendor ID is not shown
+        let virtual_disk = &drives[2];
+        assert_eq!(virtual_disk.vendor, None);
+        assert_eq!(virtual_disk.model, None);
+        assert_eq!(virtual_disk.transport, Some("VirtIO"));
+    }
+
+    #[test]
+    fn hides_drives_the_system_runs_from() {
+        let fixture = Fixture::new(&[
+            ssd("sda", "8:0"),
+            stick("sdb", "8:16"),
+            stick("sdc", "8:32"),
+            ssd("sdd", "8:48"),
+            Fake { name: "dm-0", majmin: "253:0", slaves: &["sdd2"], dm_name: Some("vg-root"), ..Fake::default() },
+        ])
+        .mount("sda2", "/")
+        .mount("sda1", "/boot/efi")
+        .mount("sdc1", "/run/live/medium")
+        .mount("sdb1", "/media/user/STICK");
+
+        let (listed, hidden) = list_drives(&fixture, false, true).unwrap();
+        assert_eq!(paths(&listed), ["/dev/sdb"]);
+        assert_eq!(paths(&hidden), ["/dev/sda", "/dev/sdc", "/dev/sdd"]);
+        assert_eq!(hidden[0].protected.as_deref(), Some("/ is on sda2"));
+        assert_eq!(
+            hidden[1].protected.as_deref(),
+            Some("the running live system booted from it (/run/live/medium)")
+        );
+        assert_eq!(hidden[2].protected.as_deref(), Some("part of the active dm-0 (vg-root)"));
+
+        // Listed anyway, still marked, with --allow-system-disk
+        let (listed, hidden) = list_drives(&fixture, true, true).unwrap();
+        assert_eq!(paths(&listed), ["/dev/sda", "/dev/sdb", "/dev/sdc", "/dev/sdd"]);
+        assert!(hidden.is_empty());
+        assert!(listed[0].protected.is_some() && listed[1].protected.is_none());
+    }
+
+    #[test]
+    fn parses_windows_disk_drives() {
+        let drives = parse_win32_disk_drives(include_str!("../testdata/win32-diskdrive.json")).unwrap();
+        // The internal SSD is left out, and so is the card reader with no card in it
+        assert_eq!(paths(&drives), [r"\\.\PHYSICALDRIVE1", r"\\.\PHYSICALDRIVE2"]);
+
+        let stick = &drives[0];
+        assert_eq!(stick.model.as_deref(), Some("SanDisk Cruzer Blade"));
+        assert_eq!(stick.serial.as_deref(), Some("4C530001230614117443"));
+        assert_eq!(stick.size, Some(30_752_636_928));
+        assert_eq!(stick.transport, Some("USB"));
+        assert_eq!(stick.partitions, 1);
+
+        // Multi-word models and padded serials come through whole
+        let disk = &drives[1];
+        assert_eq!(disk.model.as_deref(), Some("WD Elements 25A2"));
+        assert_eq!(disk.serial.as_deref(), Some("WX21A19ATFF3"));
+        assert_eq!(disk.partitions, 2);
+    }
+
+    #[test]
+    fn parses_a_single_windows_disk_drive() {
+        let drives = parse_win32_disk_drives(include_str!("../testdata/win32-diskdrive-single.json")).unwrap();
+        assert_eq!(paths(&drives), [r"\\.\PHYSICALDRIVE1"]);
+        assert_eq!(drives[0].model.as_deref(), Some("Kingston DataTraveler 3.0"));
+        assert_eq!(drives[0].serial, None);
+
+        assert!(parse_win32_disk_drives("").unwrap().is_empty());
+        assert!(parse_win32_disk_drives("Get-CimInstance : Access denied").is_err());
+    }
+
+    #[test]
+    fn parses_diskutil_list() {
+        let drives = parse_diskutil_list(include_bytes!("../testdata/diskutil-list.plist")).unwrap();
+        assert_eq!(paths(&drives), ["/dev/disk4", "/dev/disk5", "/dev/disk6"]);
+        assert_eq!(drives.iter().map(|d| d.partitions).collect::<Vec<_>>(), [2, 1, 0]);
+        // Volume names with spaces survive, and the EFI system partition is not worth naming
+        assert_eq!(drives[0].labels, ["Time Machine Backup"]);
+        assert_eq!(drives[1].labels, ["UBUNTU 24_0"]);
+        // A disk without a partition table names its one volume itself
+        assert_eq!(drives[2].labels, ["CAMERA"]);
+        assert_eq!(drives[1].size, Some(30_752_636_928));
+
+        assert!(parse_diskutil_list(b"disk5 (external, physical)").is_err());
+    }
+
+    #[test]
+    fn parses_diskutil_info() {
+        let mut drives = parse_diskutil_list(include_bytes!("../testdata/diskutil-list.plist")).unwrap();
+
+        parse_diskutil_info(include_bytes!("../testdata/diskutil-info.plist"), &mut drives[1]).unwrap();
+        let stick = &drives[1];
+        assert_eq!(stick.model.as_deref(), Some("SanDisk Cruzer Blade"));
+        assert_eq!(stick.transport, Some("USB"));
+        assert!(stick.removable && !stick.read_only);
+        assert_eq!(stick.title(), "/dev/disk5 — SanDisk Cruzer Blade  28.64 GiB  USB");
+
+        // An SD card with its lock switch on
+        parse_diskutil_info(include_bytes!("../testdata/diskutil-info-sd.plist"), &mut drives[2]).unwrap();
+        let card = &drives[2];
+        assert_eq!(card.model.as_deref(), Some("APPLE SD Card Reader"));
+        assert_eq!(card.transport, Some("SD/MMC"));
+        assert!(card.read_only);
+    }
+
+    /// BOS header, USB 2.0 extension (LPM), then the SuperSpeed USB device capability
+    const SUPERSPEED_BOS: &[u8] = &[
+        0x05, 0x0f, 0x16, 0x00, 0x02, //
+        0x07, 0x10, 0x02, 0x06, 0x00, 0x00, 0x00, //
+        0x0a, 0x10, 0x03, 0x00, 0x0e, 0x00, 0x01, 0x0a, 0xff, 0x07,
+    ];
+
+    #[test]
+    fn reads_the_negotiated_usb_link() {
+        let fixture = Fixture::new(&[
+            // USB 3 stick in a USB 3 port
+            Fake { usb_speed: Some("5000"), usb_version: Some("3.20"), ..stick("sdb", "8:16") },
+            // USB 3 stick in a USB 2 port: it reports 2.10, only the BOS descriptor says USB 3
+            Fake { usb_speed: Some("480"), usb_version: Some("2.10"), bos: Some(SUPERSPEED_BOS), ..stick("sdc", "8:32") },
+            // A plain USB 2 stick
+            Fake { usb_speed: Some("480"), usb_version: Some("2.00"), ..stick("sdd", "8:48") },
+            Fake { serial: Some("S4EVNF0M123456"), ..ssd("sda", "8:0") },
+        ]);
+        let drives = fixture.drives(true).unwrap();
+        assert_eq!(paths(&drives), ["/dev/sda", "/dev/sdb", "/dev/sdc", "/dev/sdd"]);
+        let links: Vec<_> = drives.iter().map(|d| d.usb_link).collect();
+        assert_eq!(
+            links,
+            [
+                None,
+                Some(UsbLink { mbps: 5000.0, superspeed_capable: true }),
+                Some(UsbLink { mbps: 480.0, superspeed_capable: true }),
+                Some(UsbLink { mbps: 480.0, superspeed_capable: false }),
+            ]
+        );
+        assert_eq!(drives[1].title(), "/dev/sdb — SanDisk Cruzer Blade  28.64 GiB  USB 5 Gbit/s");
+        assert!(!drives[1].usb_link.unwrap().slowed());
+        assert!(drives[2].usb_link.unwrap().slowed());
+        assert!(!drives[3].usb_link.unwrap().slowed());
+
+        // The confirm screen names the link of USB drives and warns about the one held back by its port
+        let lines = |dev| link_lines(&fixture.root, dev, 1 << 30);
+        assert_eq!(lines("/dev/sdb"), ["Connected over USB 3 SuperSpeed (5 Gbit/s): writing 1.00 GiB takes at least 2 s"]);
+        assert_eq!(lines("/dev/sdc").len(), 2);
+        assert!(lines("/dev/sdc")[1].contains("running at USB 2 speed"), "{:?}", lines("/dev/sdc"));
+        assert_eq!(lines("/dev/sdd").len(), 1);
+        assert_eq!(lines("/dev/sda"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn estimates_the_write_time_from_the_link() {
+        let slowed = UsbLink { mbps: 480.0, superspeed_capable: true };
+        let lines = slowed.lines(4 << 30);
+        assert_eq!(
+            lines[0],
+            "Connected over USB 2.0 High Speed (480 Mbit/s): writing 4.00 GiB takes at least 1 min 42 s"
+        );
+        assert!(lines[1].ends_with("A USB 3 port (often blue) allows up to 10x faster writes"));
+
+        let fast = UsbLink { mbps: 10000.0, superspeed_capable: true };
+        assert_eq!(fast.lines(4 << 30), ["Connected over USB 3 SuperSpeed+ (10 Gbit/s): writing 4.00 GiB takes at least 5 s"]);
+        assert_eq!(fast.lines(0), ["Connected over USB 3 SuperSpeed+ (10 Gbit/s)"]);
+
+        assert!(!bos_superspeed(&SUPERSPEED_BOS[..12]));
+        assert!(!bos_superspeed(&[]));
+    }
+}
@@ -0,0 +1,215 @@
+//! Small images built in memory for the parser tests
+
+use std::path::PathBuf;
+
+/// ISO9660 logical sector size
+pub const SECTOR: usize = 2048;
+
+/// A scratch file holding `data`, named after the test that uses it
+pub fn file(name: &str, data: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("tetcher-{}-{}", std::process::id(), name));
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+/// `text` padded with spaces to fill `field`
+fn put(field: &mut [u8], text: &[u8]) {
+    field.fill(b' ');
+    field[..text.len()].copy_from_slice(text);
+}
+
+/// `text` as big-endian UCS-2, padded with spaces to fill `field`
+fn put_ucs2(field: &mut [u8], text: &str) {
+    for pair in field.chunks_exact_mut(2) {
+        pair.copy_from_slice(&[0, b' ']);
+    }
+    for (pair, unit) in field.chunks_exact_mut(2).zip(text.encode_utf16()) {
+        pair.copy_from_slice(&unit.to_be_bytes());
+    }
+}
+
+/// UDF dstring with 8-bit characters, its used length in the last byte
+fn put_dstring(field: &mut [u8], text: &str) {
+    field[0] = 8;
+    field[1..1 + text.len()].copy_from_slice(text.as_bytes());
+    *field.last_mut().unwrap() = 1 + text.len() as u8;
+}
+
+/// Where `Iso` puts its El Torito boot catalog
+const CATALOG: usize = 23;
+
+/// An Ubuntu-like ISO9660 image, optionally with Joliet and UDF descriptors
+#[derive(Default)]
+pub struct Iso {
+    pub joliet: bool,
+    pub udf: bool,
+    /// Platform IDs of bootable El Torito entries; the first is the default entry
+    pub el_torito: Vec<u8>,
+}
+
+impl Iso {
+    /// Sectors 16..: PVD, El Torito boot record, Joliet SVD, terminator, UDF recognition
+    /// sequence, all back to back; boot catalog at 23; UDF anchor at 256 pointing at a PVD,
+    /// LVD and terminating descriptor
+    pub fn build(&self) -> Vec<u8> {
+        let sectors = if self.udf { 260 } else { 24 };
+        let mut image = vec![0u8; sectors * SECTOR];
+        let mut next = 16;
+        let mut descriptor = |kind: u8, id: &[u8]| {
+            let desc = &mut image[next * SECTOR..(next + 1) * SECTOR];
+            desc[0] = kind;
+            desc[1..6].copy_from_slice(id);
+            desc[6] = 1;
+            next += 1;
+            (next - 1) * SECTOR
+        };
+
+        let pvd = descriptor(1, b"CD001");
+        let boot_record = (!self.el_torito.is_empty()).then(|| descriptor(0, b"CD001"));
+        let joliet = self.joliet.then(|| descriptor(2, b"CD001"));
+        descriptor(255, b"CD001");
+        if self.udf {
+            descriptor(0, b"BEA01");
+            descriptor(0, b"NSR02");
+            descriptor(0, b"TEA01");
+        }
+
+        let desc = &mut image[pvd..pvd + SECTOR];
+        put(&mut desc[40..72], b"UBUNTU 24.04 LTS");
+        desc[80..84].copy_from_slice(&(sectors as u32).to_le_bytes());
+        desc[84..88].copy_from_slice(&(sectors as u32).to_be_bytes());
+        desc[128..130].copy_from_slice(&(SECTOR as u16).to_le_bytes());
+        put(&mut desc[318..446], b"CANONICAL");
+        put(&mut desc[574..702], b"_APPINFO.TXT");
+        // 11:29:08.00 at UTC+02:00, in 15 minute steps
+        desc[813..829].copy_from_slice(b"2024042411290800");
+        desc[829] = 8;
+
+        if let Some(at) = joliet {
+            let desc = &mut image[at..at + SECTOR];
+            desc[88..91].copy_from_slice(b"%/E");
+            put_ucs2(&mut desc[40..72], "Ubuntu 24.04 LTS");
+            put_ucs2(&mut desc[318..446], "Canonical");
+        }
+
+        if let Some(at) = boot_record {
+            let desc = &mut image[at..at + SECTOR];
+            desc[7..30].copy_from_slice(b"EL TORITO SPECIFICATION");
+            desc[0x47..0x4B].copy_from_slice(&(CATALOG as u32).to_le_bytes());
+
+            // Validation entry with the default entry's platform, then one section per other platform
+            let catalog = &mut image[CATALOG * SECTOR..(CATALOG + 1) * SECTOR];
+            catalog[0] = 1;
+            catalog[1] = self.el_torito[0];
+            catalog[30..32].copy_from_slice(&[0x55, 0xAA]);
+            catalog[32] = 0x88;
+            for (i, &platform) in self.el_torito[1..].iter().enumerate() {
+                let section = 64 + i * 64;
+                catalog[section] = if i + 2 == self.el_torito.len() { 0x91 } else { 0x90 };
+                catalog[section + 1] = platform;
+                catalog[section + 32] = 0x88;
+            }
+        }
+
+        if self.udf {
+            fn tag(image: &mut [u8], lba: usize, id: u16) -> &mut [u8] {
+                let desc = &mut image[lba * SECTOR..(lba + 1) * SECTOR];
+                desc[0..2].copy_from_slice(&id.to_le_bytes());
+                desc
+            }
+            let anchor = tag(&mut image, 256, 2);
+            anchor[16..20].copy_from_slice(&(3 * SECTOR as u32).to_le_bytes());
+            anchor[20..24].copy_from_slice(&257u32.to_le_bytes());
+            put_dstring(&mut tag(&mut image, 257, 1)[24..56], "Ubuntu 24.04");
+            put_dstring(&mut tag(&mut image, 258, 6)[84..212], "Ubuntu 24.04 LTS amd64");
+            tag(&mut image, 259, 8);
+        }
+        image
+    }
+}
+
+/// Type GUID of an EFI System Partition, as stored
+pub const ESP: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+/// Type GUID of a Linux filesystem partition, as stored
+pub const LINUX: [u8; 16] = [
+    0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4,
+];
+
+/// A 512-byte MBR listing (type, first sector, sectors) entries, with boot code or without
+pub fn mbr(boot_code: bool, entries: &[(u8, u32, u32)]) -> Vec<u8> {
+    let mut sector = vec![0u8; 512];
+    if boot_code {
+        // cli; hlt
+        sector[..2].copy_from_slice(&[0xFA, 0xF4]);
+    }
+    for (i, &(kind, first, sectors)) in entries.iter().enumerate() {
+        let entry = &mut sector[446 + i * 16..462 + i * 16];
+        entry[4] = kind;
+        entry[8..12].copy_from_slice(&first.to_le_bytes());
+        entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+    }
+    sector[510..512].copy_from_slice(&[0x55, 0xAA]);
+    sector
+}
+
+/// A disk of `sectors` 512-byte sectors with a protective MBR and a GPT whose 128 entries
+/// of 128 bytes start at LBA 2, listing (type GUID, first LBA, last LBA, name) partitions
+pub fn gpt(sectors: u64, partitions: &[([u8; 16], u64, u64, &str)]) -> Vec<u8> {
+    let mut disk = vec![0u8; sectors as usize * 512];
+    disk[..512].copy_from_slice(&mbr(false, &[(0xEE, 1, (sectors - 1) as u32)]));
+
+    let header = &mut disk[512..1024];
+    header[..8].copy_from_slice(b"EFI PART");
+    header[72..80].copy_from_slice(&2u64.to_le_bytes());
+    header[80..84].copy_from_slice(&128u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+
+    for (i, (kind, first, last, name)) in partitions.iter().enumerate() {
+        let entry = &mut disk[1024 + i * 128..1024 + (i + 1) * 128];
+        entry[..16].copy_from_slice(kind);
+        entry[16] = i as u8 + 1;
+        entry[32..40].copy_from_slice(&first.to_le_bytes());
+        entry[40..48].copy_from_slice(&last.to_le_bytes());
+        for (pair, unit) in entry[56..128].chunks_exact_mut(2).zip(name.encode_utf16()) {
+            pair.copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+    disk
+}
+
+/// The first 2 KiB of an ext4 filesystem with 4 KiB blocks, `free` of its `blocks` unused
+pub fn ext4(label: &str, blocks: u32, free: u32) -> Vec<u8> {
+    let mut fs = vec![0u8; 2048];
+    let sb = &mut fs[1024..];
+    sb[4..8].copy_from_slice(&blocks.to_le_bytes());
+    sb[12..16].copy_from_slice(&free.to_le_bytes());
+    sb[24..28].copy_from_slice(&2u32.to_le_bytes());
+    sb[0x38..0x3A].copy_from_slice(&[0x53, 0xEF]);
+    // has_journal; extents
+    sb[0x5C..0x60].copy_from_slice(&0x4u32.to_le_bytes());
+    sb[0x60..0x64].copy_from_slice(&0x40u32.to_le_bytes());
+    sb[0x78..0x78 + label.len()].copy_from_slice(label.as_bytes());
+    fs
+}
+
+/// The boot and FSInfo sectors of a FAT32 filesystem of `sectors` 512-byte sectors with
+/// 4 KiB clusters, one sector per FAT and `free` free clusters
+pub fn fat32(label: &str, sectors: u32, free: u32) -> Vec<u8> {
+    let mut fs = vec![0u8; 1024];
+    fs[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    fs[0x0B..0x0D].copy_from_slice(&512u16.to_le_bytes());
+    fs[0x0D] = 8;
+    fs[0x0E..0x10].copy_from_slice(&32u16.to_le_bytes());
+    fs[0x10] = 2;
+    fs[0x20..0x24].copy_from_slice(&sectors.to_le_bytes());
+    fs[0x24..0x28].copy_from_slice(&1u32.to_le_bytes());
+    fs[0x30..0x32].copy_from_slice(&1u16.to_le_bytes());
+    put(&mut fs[0x47..0x52], label.as_bytes());
+    fs[0x52..0x5A].copy_from_slice(b"FAT32   ");
+    fs[510..512].copy_from_slice(&[0x55, 0xAA]);
+    fs[512..516].copy_from_slice(b"RRaA");
+    fs[512 + 488..512 + 492].copy_from_slice(&free.to_le_bytes());
+    fs
+}
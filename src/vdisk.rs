@@ -0,0 +1,811 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+
+use crate::dmg;
+use crate::imgformat::Format;
+use crate::parttable;
+
+/// A virtual disk, read back as the raw bytes a VM would see
+pub trait Disk: Send {
+    /// Size of the virtual disk in bytes
+    fn size(&self) -> u64;
+    /// Fill `buf` with the disk's content at `offset`; unallocated space reads as zeros
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+}
+
+/// Sequential reader over the whole of a virtual disk
+pub struct Reader {
+    disk: Box<dyn Disk>,
+    pos: u64,
+}
+
+impl Reader {
+    pub fn new(disk: Box<dyn Disk>) -> Reader {
+        Reader { disk, pos: 0 }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.disk.size()
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = (self.disk.size() - self.pos).min(buf.len() as u64) as usize;
+        if n == 0 {
+            return Ok(0);
+        }
+        self.disk.read_at(self.pos, &mut buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+/// Open a qcow2, VHD, VHDX, VMDK or DMG image as a raw disk
+pub fn open(path: &Path, format: Format) -> io::Result<Box<dyn Disk>> {
+    match format {
+        Format::Qcow2 => Ok(Box::new(Qcow2::open(File::open(path)?)?)),
+        Format::Vhd => Ok(Box::new(Vhd::open(File::open(path)?)?)),
+        Format::Vhdx => Ok(Box::new(Vhdx::open(File::open(path)?)?)),
+        Format::Vmdk => open_vmdk(path),
+        Format::Dmg => Ok(Box::new(dmg::Dmg::open(File::open(path)?)?)),
+        other => Err(unsupported(format!("{} is not a virtual disk", other.description()))),
+    }
+}
+
+pub fn unsupported(why: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, why.into())
+}
+
+pub fn corrupt(why: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, why.into())
+}
+
+pub fn read_exact_at(file: &mut File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+/// Upper bound on any table read into memory because an image header asked for it
+const MAX_TABLE: u64 = 256 * 1024 * 1024;
+
+/// Read a `len`-byte table at `offset`, refusing lengths past the end of the file or above
+/// [`MAX_TABLE`], so a corrupt header cannot make us allocate whatever it claims
+fn read_table(file: &mut File, offset: u64, len: u64, what: &str) -> io::Result<Vec<u8>> {
+    let file_len = file.metadata()?.len();
+    if len > MAX_TABLE || offset.checked_add(len).is_none_or(|end| end > file_len) {
+        return Err(corrupt(format!("the {} does not fit in the image", what)));
+    }
+    let mut table = vec![0u8; len as usize];
+    read_exact_at(file, offset, &mut table)?;
+    Ok(table)
+}
+
+/// File position of `within` bytes into a block that a table entry puts at `start`, checked
+/// so that a corrupt entry cannot overflow or point `len` bytes past the end of the file
+fn block_position(file_len: u64, start: Option<u64>, within: u64, len: usize, what: &str) -> io::Result<u64> {
+    start
+        .and_then(|start| start.checked_add(within))
+        .filter(|pos| pos.checked_add(len as u64).is_some_and(|end| end <= file_len))
+        .ok_or_else(|| corrupt(format!("a {} entry points past the end of the image", what)))
+}
+
+/// Read up to `len` bytes at `offset`, stopping early at the end of the file
+pub fn read_up_to(file: &mut File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    // Grow as data arrives rather than trusting `len`, which may come from a corrupt header
+    let mut buf = Vec::new();
+    file.seek(SeekFrom::Start(offset))?;
+    file.take(len as u64).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Decompress into `out`, zero-filling whatever the stream does not cover
+pub fn inflate(mut decoder: impl Read, out: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < out.len() {
+        match decoder.read(&mut out[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    out[filled..].fill(0);
+    Ok(())
+}
+
+/// Split a read at `offset` on `block`-sized boundaries, calling `f(index, offset within block, part)`
+fn by_block(
+    offset: u64,
+    buf: &mut [u8],
+    block: u64,
+    mut f: impl FnMut(u64, u64, &mut [u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        let pos = offset + done as u64;
+        let within = pos % block;
+        let n = ((block - within) as usize).min(buf.len() - done);
+        f(pos / block, within, &mut buf[done..done + n])?;
+        done += n;
+    }
+    Ok(())
+}
+
+pub fn be32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn be64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+fn le32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn le64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// QEMU copy-on-write v2/v3
+struct Qcow2 {
+    file: File,
+    size: u64,
+    cluster_bits: u32,
+    l1: Vec<u64>,
+    /// Last L2 table used: sequential reads walk one table at a time
+    l2: Option<(u64, Vec<u64>)>,
+    /// Last compressed cluster, by host entry
+    compressed: Option<(u64, Vec<u8>)>,
+}
+
+impl Qcow2 {
+    /// Host offset bits of L1 and L2 entries
+    const OFFSET_MASK: u64 = 0x00FF_FFFF_FFFF_FE00;
+    const COMPRESSED: u64 = 1 << 62;
+    /// v3: the cluster reads as zeros
+    const ZERO: u64 = 1;
+
+    fn open(mut file: File) -> io::Result<Qcow2> {
+        let mut header = [0u8; 112];
+        read_exact_at(&mut file, 0, &mut header[..72])?;
+        let version = be32(&header, 4);
+        if version != 2 && version != 3 {
+            return Err(unsupported(format!("qcow2 version {} is not supported", version)));
+        }
+        if be64(&header, 8) != 0 {
+            return Err(unsupported("the qcow2 image has a backing file; flatten it with qemu-img convert"));
+        }
+        if be32(&header, 32) != 0 {
+            return Err(unsupported("encrypted qcow2 images are not supported"));
+        }
+        if version == 3 {
+            read_exact_at(&mut file, 0, &mut header)?;
+            let incompatible = be64(&header, 72);
+            if incompatible & 0b10 != 0 {
+                return Err(corrupt("the qcow2 image is marked corrupt"));
+            }
+            if incompatible & 0b100 != 0 {
+                return Err(unsupported("qcow2 images with an external data file are not supported"));
+            }
+            if incompatible & 0b1000 != 0 && header[104] != 0 {
+                return Err(unsupported("zstd-compressed qcow2 images are not supported"));
+            }
+            if incompatible & 0b1_0000 != 0 {
+                return Err(unsupported("qcow2 images with extended L2 entries are not supported"));
+            }
+        }
+
+        let cluster_bits = be32(&header, 20);
+        if !(9..=21).contains(&cluster_bits) {
+            return Err(corrupt(format!("invalid qcow2 cluster size 2^{}", cluster_bits)));
+        }
+        let size = be64(&header, 24);
+        let l1_size = be32(&header, 36) as u64;
+        let l1 = read_table(&mut file, be64(&header, 40), l1_size * 8, "qcow2 L1 table")?;
+        let l1 = l1.chunks_exact(8).map(|e| be64(e, 0)).collect();
+
+        Ok(Qcow2 { file, size, cluster_bits, l1, l2: None, compressed: None })
+    }
+
+    fn l2_entry(&mut self, cluster: u64) -> io::Result<u64> {
+        let per_table = 1u64 << (self.cluster_bits - 3);
+        let Some(&l1_entry) = self.l1.get((cluster / per_table) as usize) else { return Ok(0) };
+        let table = l1_entry & Self::OFFSET_MASK;
+        if table == 0 {
+            return Ok(0);
+        }
+        if self.l2.as_ref().is_none_or(|(offset, _)| *offset != table) {
+            let mut raw = vec![0u8; 1 << self.cluster_bits];
+            read_exact_at(&mut self.file, table, &mut raw)?;
+            self.l2 = Some((table, raw.chunks_exact(8).map(|e| be64(e, 0)).collect()));
+        }
+        Ok(self.l2.as_ref().unwrap().1[(cluster % per_table) as usize])
+    }
+
+    fn read_compressed(&mut self, entry: u64, within: u64, out: &mut [u8]) -> io::Result<()> {
+        if self.compressed.as_ref().is_none_or(|(cached, _)| *cached != entry) {
+            // The host offset and sector count share the entry; the split depends on the cluster size
+            let offset_bits = 62 - (self.cluster_bits - 8);
+            let host = entry & ((1 << offset_bits) - 1);
+            let sectors = ((entry >> offset_bits) & ((1 << (self.cluster_bits - 8)) - 1)) + 1;
+            let len = sectors * 512 - (host & 511);
+
+            let data = read_up_to(&mut self.file, host, len as usize)?;
+            let mut cluster = vec![0u8; 1 << self.cluster_bits];
+            inflate(DeflateDecoder::new(&data[..]), &mut cluster)?;
+            self.compressed = Some((entry, cluster));
+        }
+        let cluster = &self.compressed.as_ref().unwrap().1;
+        out.copy_from_slice(&cluster[within as usize..within as usize + out.len()]);
+        Ok(())
+    }
+}
+
+impl Disk for Qcow2 {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        by_block(offset, buf, 1 << self.cluster_bits, |cluster, within, out| {
+            let entry = self.l2_entry(cluster)?;
+            if entry & Self::COMPRESSED != 0 {
+                return self.read_compressed(entry & !(3 << 62), within, out);
+            }
+            let host = entry & Self::OFFSET_MASK;
+            if host == 0 || entry & Self::ZERO != 0 {
+                out.fill(0);
+                return Ok(());
+            }
+            read_exact_at(&mut self.file, host + within, out)
+        })
+    }
+}
+
+/// Dynamic Virtual PC / Hyper-V disk (fixed VHDs are already raw)
+struct Vhd {
+    file: File,
+    file_len: u64,
+    size: u64,
+    block_size: u64,
+    /// Sector offset of each block, or UNALLOCATED
+    bat: Vec<u32>,
+    /// Each block starts with a sector bitmap of this many bytes
+    bitmap_size: u64,
+}
+
+impl Vhd {
+    const UNALLOCATED: u32 = 0xFFFF_FFFF;
+
+    fn open(mut file: File) -> io::Result<Vhd> {
+        // Dynamic disks keep a copy of the footer at the start of the file
+        let mut footer = [0u8; 512];
+        read_exact_at(&mut file, 0, &mut footer)?;
+        if &footer[0..8] != b"conectix" {
+            return Err(corrupt("missing VHD footer"));
+        }
+        match be32(&footer, 60) {
+            3 => {}
+            4 => return Err(unsupported("differencing VHDs need their parent disk; merge them first")),
+            other => return Err(unsupported(format!("VHD disk type {} is not supported", other))),
+        }
+        let size = be64(&footer, 48);
+
+        let mut header = [0u8; 1024];
+        read_exact_at(&mut file, be64(&footer, 16), &mut header)?;
+        if &header[0..8] != b"cxsparse" {
+            return Err(corrupt("missing VHD dynamic disk header"));
+        }
+        let entries = be32(&header, 28) as u64;
+        let block_size = be32(&header, 32) as u64;
+        if block_size == 0 || !block_size.is_multiple_of(512) {
+            return Err(corrupt(format!("invalid VHD block size {}", block_size)));
+        }
+
+        let bat = read_table(&mut file, be64(&header, 16), entries * 4, "VHD block allocation table")?;
+        let bat = bat.chunks_exact(4).map(|e| be32(e, 0)).collect();
+        let bitmap_size = (block_size / 512).div_ceil(8).div_ceil(512) * 512;
+
+        let file_len = file.metadata()?.len();
+        Ok(Vhd { file, file_len, size, block_size, bat, bitmap_size })
+    }
+}
+
+impl Disk for Vhd {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        by_block(offset, buf, self.block_size, |block, within, out| {
+            match self.bat.get(block as usize) {
+                Some(&sector) if sector != Self::UNALLOCATED => {
+                    let start = (sector as u64).checked_mul(512).and_then(|s| s.checked_add(self.bitmap_size));
+                    let pos = block_position(self.file_len, start, within, out.len(), "VHD block allocation table")?;
+                    read_exact_at(&mut self.file, pos, out)
+                }
+                _ => {
+                    out.fill(0);
+                    Ok(())
+                }
+            }
+        })
+    }
+}
+
+/// Hyper-V VHDX
+struct Vhdx {
+    file: File,
+    file_len: u64,
+    size: u64,
+    block_size: u64,
+    /// Payload blocks per sector bitmap block; BAT entries for the bitmaps are interleaved
+    chunk_ratio: u64,
+    bat: Vec<u64>,
+}
+
+impl Vhdx {
+    const BAT_REGION: &str = "2DC27766-F623-4200-9D64-115E9BFD4A08";
+    const METADATA_REGION: &str = "8B7CA206-4790-4B9A-B8FE-575F050F886E";
+    const FILE_PARAMETERS: &str = "CAA16737-FA36-4D43-B3B6-33F0AA44E76B";
+    const VIRTUAL_DISK_SIZE: &str = "2FA54224-CD1B-4876-B211-5DBED83BF4B8";
+    const LOGICAL_SECTOR_SIZE: &str = "8141BF1D-A96F-4709-BA47-F233A8FAAB5F";
+    const PAYLOAD_FULLY_PRESENT: u64 = 6;
+    const PAYLOAD_PARTIALLY_PRESENT: u64 = 7;
+
+    fn open(mut file: File) -> io::Result<Vhdx> {
+        // Two copies of the header; the one with the higher sequence number is current
+        let mut current: Option<[u8; 80]> = None;
+        for offset in [64 * 1024, 128 * 1024] {
+            let mut header = [0u8; 80];
+            read_exact_at(&mut file, offset, &mut header)?;
+            if &header[0..4] == b"head" && current.is_none_or(|c| le64(&header, 8) > le64(&c, 8)) {
+                current = Some(header);
+            }
+        }
+        let header = current.ok_or_else(|| corrupt("missing VHDX header"))?;
+        if header[48..64].iter().any(|&b| b != 0) {
+            return Err(unsupported("the VHDX has an unreplayed log; open it once in Hyper-V or qemu-img first"));
+        }
+
+        let mut regions = [0u8; 64 * 1024];
+        read_exact_at(&mut file, 192 * 1024, &mut regions)?;
+        if &regions[0..4] != b"regi" {
+            return Err(corrupt("missing VHDX region table"));
+        }
+        let mut bat_region = None;
+        let mut metadata_region = None;
+        for i in 0..(le32(&regions, 8) as usize).min(2047) {
+            let entry = &regions[16 + i * 32..48 + i * 32];
+            let region = (le64(entry, 16), le32(entry, 24) as u64);
+            match parttable::guid(&entry[0..16]).as_str() {
+                Self::BAT_REGION => bat_region = Some(region),
+                Self::METADATA_REGION => metadata_region = Some(region),
+                _ => {}
+            }
+        }
+        let (bat_offset, bat_len) = bat_region.ok_or_else(|| corrupt("VHDX has no BAT region"))?;
+        let (meta_offset, meta_len) = metadata_region.ok_or_else(|| corrupt("VHDX has no metadata region"))?;
+
+        let metadata = read_table(&mut file, meta_offset, meta_len, "VHDX metadata region")?;
+        if metadata.get(0..8) != Some(b"metadata") {
+            return Err(corrupt("missing VHDX metadata table"));
+        }
+        let count = metadata.get(10..12).map_or(0, |c| u16::from_le_bytes([c[0], c[1]]) as usize);
+        let item = |guid: &str, len: usize| -> Option<&[u8]> {
+            (0..count.min(2047)).find_map(|i| {
+                let entry = metadata.get(32 + i * 32..64 + i * 32)?;
+                if parttable::guid(&entry[0..16]) != guid {
+                    return None;
+                }
+                let offset = le32(entry, 16) as usize;
+                // Items shorter than the fields we read count as missing
+                metadata.get(offset..offset + (le32(entry, 20) as usize).min(len)).filter(|item| item.len() == len)
+            })
+        };
+
+        let parameters = item(Self::FILE_PARAMETERS, 8).ok_or_else(|| corrupt("VHDX has no file parameters"))?;
+        let block_size = le32(parameters, 0) as u64;
+        if le32(parameters, 4) & 0b10 != 0 {
+            return Err(unsupported("differencing VHDX disks need their parent disk; merge them first"));
+        }
+        let size = le64(item(Self::VIRTUAL_DISK_SIZE, 8).ok_or_else(|| corrupt("VHDX has no disk size"))?, 0);
+        let sector_size = le32(item(Self::LOGICAL_SECTOR_SIZE, 4).ok_or_else(|| corrupt("VHDX has no sector size"))?, 0) as u64;
+        // The spec allows 512 or 4096 byte sectors and 1 MiB to 256 MiB blocks
+        if !matches!(sector_size, 512 | 4096) || !(1 << 20..=1 << 28).contains(&block_size) {
+            return Err(corrupt("invalid VHDX block or sector size"));
+        }
+        let chunk_ratio = (1u64 << 23) * sector_size / block_size;
+
+        let bat = read_table(&mut file, bat_offset, bat_len, "VHDX block allocation table")?;
+        let bat = bat.chunks_exact(8).map(|e| le64(e, 0)).collect();
+
+        let file_len = file.metadata()?.len();
+        Ok(Vhdx { file, file_len, size, block_size, chunk_ratio, bat })
+    }
+}
+
+impl Disk for Vhdx {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        by_block(offset, buf, self.block_size, |block, within, out| {
+            let entry = self.bat.get((block + block / self.chunk_ratio) as usize).copied().unwrap_or(0);
+            match entry & 7 {
+                Self::PAYLOAD_FULLY_PRESENT => {
+                    let start = (entry >> 20).checked_mul(1024 * 1024);
+                    let pos = block_position(self.file_len, start, within, out.len(), "VHDX block allocation table")?;
+                    read_exact_at(&mut self.file, pos, out)
+                }
+                Self::PAYLOAD_PARTIALLY_PRESENT => Err(unsupported("partially present VHDX blocks need the parent disk")),
+                // Not present, undefined, zero or unmapped
+                _ => {
+                    out.fill(0);
+                    Ok(())
+                }
+            }
+        })
+    }
+}
+
+/// VMware hosted sparse extent, including the streamOptimized variant used in OVAs
+struct VmdkSparse {
+    file: File,
+    size: u64,
+    grain_size: u64,
+    entries_per_table: u64,
+    /// Sector offset of each grain table
+    directory: Vec<u32>,
+    table: Option<(u32, Vec<u32>)>,
+    compressed: bool,
+    grain: Option<(u32, Vec<u8>)>,
+}
+
+impl VmdkSparse {
+    /// Directory location meaning "see the footer at the end of the file"
+    const GD_AT_END: u64 = u64::MAX;
+    const FLAG_COMPRESSED: u32 = 1 << 16;
+
+    fn open(mut file: File) -> io::Result<VmdkSparse> {
+        let mut header = [0u8; 512];
+        read_exact_at(&mut file, 0, &mut header)?;
+        if &header[0..4] != b"KDMV" {
+            return Err(corrupt("missing VMDK sparse header"));
+        }
+        if le64(&header, 56) == Self::GD_AT_END {
+            // streamOptimized: the real header is the footer, 1 KiB before the end
+            let end = file.seek(SeekFrom::End(0))?;
+            read_exact_at(&mut file, end.saturating_sub(1024), &mut header)?;
+            if &header[0..4] != b"KDMV" {
+                return Err(corrupt("missing VMDK footer"));
+            }
+        }
+
+        let flags = le32(&header, 8);
+        let size = le64(&header, 12).checked_mul(512).ok_or_else(|| corrupt("invalid VMDK capacity"))?;
+        let grain_size = le64(&header, 20).saturating_mul(512);
+        let entries_per_table = le32(&header, 44) as u64;
+        // Grains and grain tables are allocated whole, so bound them like any other table
+        if grain_size == 0 || grain_size > MAX_TABLE || entries_per_table == 0 || entries_per_table * 4 > MAX_TABLE {
+            return Err(corrupt("invalid VMDK grain size"));
+        }
+        let tables = size.div_ceil(grain_size).div_ceil(entries_per_table);
+        let directory_offset = le64(&header, 56).checked_mul(512).ok_or_else(|| corrupt("invalid VMDK grain directory offset"))?;
+        let directory = read_table(&mut file, directory_offset, tables * 4, "VMDK grain directory")?;
+        let directory = directory.chunks_exact(4).map(|e| le32(e, 0)).collect();
+
+        Ok(VmdkSparse {
+            file,
+            size,
+            grain_size,
+            entries_per_table,
+            directory,
+            table: None,
+            compressed: flags & Self::FLAG_COMPRESSED != 0,
+            grain: None,
+        })
+    }
+
+    fn grain_entry(&mut self, grain: u64) -> io::Result<u32> {
+        let Some(&table) = self.directory.get((grain / self.entries_per_table) as usize) else { return Ok(0) };
+        if table == 0 {
+            return Ok(0);
+        }
+        if self.table.as_ref().is_none_or(|(cached, _)| *cached != table) {
+            let raw = read_table(&mut self.file, table as u64 * 512, self.entries_per_table * 4, "VMDK grain table")?;
+            self.table = Some((table, raw.chunks_exact(4).map(|e| le32(e, 0)).collect()));
+        }
+        Ok(self.table.as_ref().unwrap().1[(grain % self.entries_per_table) as usize])
+    }
+
+    fn read_compressed(&mut self, sector: u32, within: u64, out: &mut [u8]) -> io::Result<()> {
+        if self.grain.as_ref().is_none_or(|(cached, _)| *cached != sector) {
+            // Grain marker: LBA (8 bytes), compressed size (4 bytes), then zlib data
+            let mut marker = [0u8; 12];
+            read_exact_at(&mut self.file, sector as u64 * 512, &mut marker)?;
+            let data = read_up_to(&mut self.file, sector as u64 * 512 + 12, le32(&marker, 8) as usize)?;
+            let mut grain = vec![0u8; self.grain_size as usize];
+            inflate(ZlibDecoder::new(&data[..]), &mut grain)?;
+            self.grain = Some((sector, grain));
+        }
+        let grain = &self.grain.as_ref().unwrap().1;
+        out.copy_from_slice(&grain[within as usize..within as usize + out.len()]);
+        Ok(())
+    }
+}
+
+impl Disk for VmdkSparse {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        by_block(offset, buf, self.grain_size, |grain, within, out| {
+            match self.grain_entry(grain)? {
+                // Unallocated, or an explicit zero grain
+                0 | 1 => {
+                    out.fill(0);
+                    Ok(())
+                }
+                sector if self.compressed => self.read_compressed(sector, within, out),
+                sector => read_exact_at(&mut self.file, sector as u64 * 512 + within, out),
+            }
+        })
+    }
+}
+
+/// Part of a disk stored as plain bytes in a file
+struct Flat {
+    file: File,
+    offset: u64,
+    size: u64,
+}
+
+impl Disk for Flat {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        read_exact_at(&mut self.file, self.offset + offset, buf)
+    }
+}
+
+/// Part of a disk that reads as zeros
+struct Zero {
+    size: u64,
+}
+
+impl Disk for Zero {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, _offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        buf.fill(0);
+        Ok(())
+    }
+}
+
+/// Extents laid end to end, as listed in a VMDK descriptor
+struct Concat {
+    extents: Vec<(u64, Box<dyn Disk>)>,
+    size: u64,
+}
+
+impl Disk for Concat {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut done = 0;
+        for (start, extent) in &mut self.extents {
+            let pos = offset + done as u64;
+            let end = start.saturating_add(extent.size());
+            if done == buf.len() {
+                break;
+            }
+            if pos >= end {
+                continue;
+            }
+            let n = ((end - pos) as usize).min(buf.len() - done);
+            extent.read_at(pos - *start, &mut buf[done..done + n])?;
+            done += n;
+        }
+        buf[done..].fill(0);
+        Ok(())
+    }
+}
+
+/// Resolve an extent file named in a descriptor, which must lie in the descriptor's directory
+/// or below it: an image must not make us read, and then flash, arbitrary files
+fn extent_path(dir: &Path, name: &str) -> io::Result<PathBuf> {
+    let relative = Path::new(name);
+    let plain = relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if name.is_empty() || !plain {
+        return Err(corrupt(format!("VMDK extent {:?} is outside the descriptor's directory", name)));
+    }
+    // Symlinks could still lead elsewhere
+    let path = dir.join(relative).canonicalize()?;
+    if !path.starts_with(dir.canonicalize()?) {
+        return Err(corrupt(format!("VMDK extent {:?} is outside the descriptor's directory", name)));
+    }
+    Ok(path)
+}
+
+/// A single-file sparse VMDK, or a text descriptor listing its extents
+fn open_vmdk(path: &Path) -> io::Result<Box<dyn Disk>> {
+    let mut file = File::open(path)?;
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic)?;
+    if &magic == b"KDMV" {
+        return Ok(Box::new(VmdkSparse::open(file)?));
+    }
+
+    let text = std::fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut extents: Vec<(u64, Box<dyn Disk>)> = Vec::new();
+    let mut size = 0;
+
+    // Extent lines look like: RW 4192256 SPARSE "disk-s001.vmdk"  or  RW 4192256 FLAT "disk-flat.vmdk" 0
+    for line in text.lines().map(str::trim) {
+        let mut words = line.split_whitespace();
+        if !matches!(words.next(), Some("RW" | "RDONLY" | "NOACCESS")) {
+            continue;
+        }
+        let sectors: u64 = words
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| corrupt(format!("bad VMDK extent line: {}", line)))?;
+        let kind = words.next().unwrap_or("");
+        let mut quoted = line.splitn(3, '"');
+        let name = quoted.nth(1).unwrap_or("");
+        let start: u64 = quoted.next().and_then(|rest| rest.trim().parse().ok()).unwrap_or(0);
+        // Sector counts are free text, so any of these can overflow
+        let overflow = || corrupt(format!("VMDK extent is too large: {}", line));
+        let bytes = sectors.checked_mul(512).ok_or_else(overflow)?;
+        let offset = start.checked_mul(512).filter(|o| o.checked_add(bytes).is_some()).ok_or_else(overflow)?;
+
+        let extent: Box<dyn Disk> = match kind {
+            "FLAT" | "VMFS" => Box::new(Flat { file: File::open(extent_path(dir, name)?)?, offset, size: bytes }),
+            "SPARSE" => Box::new(VmdkSparse::open(File::open(extent_path(dir, name)?)?)?),
+            "ZERO" => Box::new(Zero { size: bytes }),
+            other => return Err(unsupported(format!("VMDK extents of type {} are not supported", other))),
+        };
+        extents.push((size, extent));
+        size = size.checked_add(bytes).ok_or_else(overflow)?;
+    }
+
+    if extents.is_empty() {
+        return Err(corrupt("the VMDK descriptor lists no extents"));
+    }
+    Ok(Box::new(Concat { extents, size }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hand-made images of testdata/vdisk/disk.raw, see make_fixtures.py there
+    const FIXTURES: [(&str, Format); 7] = [
+        ("disk.qcow2", Format::Qcow2),
+        ("disk.vhd", Format::Vhd),
+        ("disk.vhdx", Format::Vhdx),
+        ("disk.vmdk", Format::Vmdk),
+        ("disk-stream.vmdk", Format::Vmdk),
+        ("disk-split.vmdk", Format::Vmdk),
+        ("disk.dmg", Format::Dmg),
+    ];
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/vdisk").join(name)
+    }
+
+    /// Copy of a fixture with `bytes` written at `offset`
+    fn patched(name: &str, offset: usize, bytes: &[u8]) -> PathBuf {
+        let mut data = std::fs::read(fixture(name)).unwrap();
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        let path = std::env::temp_dir().join(format!("tetcher-{}-{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    /// A VMDK descriptor listing `extents`, in a directory of its own below a scratch directory
+    fn descriptor(test: &str, extents: &str) -> (PathBuf, PathBuf) {
+        let scratch = std::env::temp_dir().join(format!("tetcher-{}-{}", std::process::id(), test));
+        std::fs::create_dir_all(scratch.join("image")).unwrap();
+        let path = scratch.join("image/disk.vmdk");
+        std::fs::write(&path, format!("# Disk DescriptorFile\nversion=1\ncreateType=\"custom\"\n{}\n", extents)).unwrap();
+        (scratch, path)
+    }
+
+    #[test]
+    fn expands_virtual_disks_to_their_raw_content() {
+        let raw = std::fs::read(fixture("disk.raw")).unwrap();
+        for (name, format) in FIXTURES {
+            let mut reader = Reader::new(open(&fixture(name), format).unwrap());
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content).unwrap();
+            assert!(content == raw, "{} does not expand to disk.raw", name);
+        }
+    }
+
+    #[test]
+    fn rejects_tables_larger_than_the_image() {
+        let huge: [(&str, Format, usize, &[u8]); 4] = [
+            // qcow2 L1 size, VHD BAT entries, VHDX BAT region length,
+            // and a VMDK capacity needing a 1 TiB grain directory
+            ("disk.qcow2", Format::Qcow2, 36, &0xFFFF_FFFFu32.to_be_bytes()),
+            ("disk.vhd", Format::Vhd, 512 + 28, &0xFFFF_FFFFu32.to_be_bytes()),
+            ("disk.vhdx", Format::Vhdx, 192 * 1024 + 16 + 24, &0xFFFF_FFFFu32.to_le_bytes()),
+            ("disk.vmdk", Format::Vmdk, 12, &(1u64 << 50).to_le_bytes()),
+        ];
+        for (name, format, offset, bytes) in huge {
+            let path = patched(name, offset, bytes);
+            let result = open(&path, format).map(|_| ());
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData, "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_short_vhdx_metadata_items() {
+        // Claim the file parameters item is 4 bytes long instead of 8
+        let path = patched("disk.vhdx", (2 << 20) + 32 + 20, &4u32.to_le_bytes());
+        let result = open(&path, Format::Vhdx).map(|_| ());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_extents_outside_the_descriptor_directory() {
+        let outside = fixture("disk.raw");
+        let (scratch, _) = descriptor("extents", "");
+        std::fs::copy(&outside, scratch.join("outside.raw")).unwrap();
+        let mut names = vec!["../outside.raw".to_string(), outside.display().to_string(), "image/../../outside.raw".to_string()];
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("../outside.raw", scratch.join("image/link.raw")).unwrap();
+            names.push("link.raw".to_string());
+        }
+        for name in names {
+            let (_, path) = descriptor("extents", &format!("RW 128 FLAT \"{}\" 0", name));
+            let result = open(&path, Format::Vmdk).map(|_| ());
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData, "{}", name);
+        }
+        std::fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[test]
+    fn rejects_extent_sizes_that_overflow() {
+        for extents in ["RW 36028797018963968 ZERO", "RW 18014398509481984 ZERO\nRW 18014398509481984 ZERO"] {
+            let (scratch, path) = descriptor("overflow", extents);
+            let result = open(&path, Format::Vmdk).map(|_| ());
+            std::fs::remove_dir_all(&scratch).unwrap();
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData, "{}", extents);
+        }
+    }
+
+    #[test]
+    fn rejects_blocks_past_the_end_of_the_image() {
+        // VHD block 0 at the last possible sector, and VHDX block 0 at the top of the offset field
+        let bad: [(&str, Format, usize, &[u8]); 2] = [
+            ("disk.vhd", Format::Vhd, 1536, &0xFFFF_FFFEu32.to_be_bytes()),
+            ("disk.vhdx", Format::Vhdx, 3 << 20, &(!0xF_FFFF_u64 | 6).to_le_bytes()),
+        ];
+        for (name, format, offset, bytes) in bad {
+            let path = patched(name, offset, bytes);
+            let result = open(&path, format).and_then(|mut disk| disk.read_at(0, &mut [0u8; 512]));
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::cache;
+use crate::devread::{self, ReadMethod};
+use crate::digest::{self, Algo};
+use crate::source;
+
+/// Digest used to compare the image and the device
+pub const ALGO: Algo = Algo::Sha256;
+
+/// How often the combined progress line is redrawn
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Result of comparing an image with a device
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub matched: bool,
+    pub image_digest: Vec<u8>,
+    pub device_digest: Vec<u8>,
+    /// How the device was read back
+    pub device_read: ReadMethod,
+}
+
+/// Compare `device` against `image` over the length of the image
+///
+/// The image and the device are hashed in parallel on separate threads. When
+/// `image_digest` is given (computed while flashing) or the digest cache has
+/// one for the unchanged image, only the device is read.
+/// Virtual disk images are compared by their expanded raw content.
+pub fn run(image: &Path, device: &str, image_digest: Option<Vec<u8>>) -> io::Result<Outcome> {
+    let source = source::open(image)?;
+    let size = source.size;
+    let cacheable = !source.expanded;
+    let from_flash = image_digest.is_some();
+    let image_digest = image_digest.or_else(|| if cacheable { cache::lookup(image, ALGO) } else { None });
+    let image_reused = image_digest.is_some();
+
+    let device_reader = devread::open(Path::new(device))?;
+    let device_read = device_reader.method();
+
+    let image_done = AtomicU64::new(if image_reused { size } else { 0 });
+    let device_done = AtomicU64::new(0);
+
+    println!("\nVerifying...");
+    if from_flash {
+        println!("Reusing the image digest computed while flashing");
+    } else if image_reused {
+        println!("Reusing the cached image digest");
+    }
+
+    let (image_hash, device_hash) = thread::scope(|s| {
+        let image_worker = match &image_digest {
+            Some(_) => None,
+            None => Some(s.spawn(|| {
+                digest::hash_reader(source, ALGO, size, |n| image_done.store(n, Ordering::Relaxed))
+            })),
+        };
+        let device_worker = s.spawn(|| {
+            digest::hash_reader(device_reader, ALGO, size, |n| device_done.store(n, Ordering::Relaxed))
+        });
+
+        loop {
+            let finished = device_worker.is_finished()
+                && image_worker.as_ref().is_none_or(|w| w.is_finished());
+
+            print_progress(
+                image_done.load(Ordering::Relaxed),
+                device_done.load(Ordering::Relaxed),
+                size,
+                image_reused,
+            );
+
+            if finished {
+                break;
+            }
+            thread::sleep(PROGRESS_INTERVAL);
+        }
+        println!();
+
+        let image_hash = match image_worker {
+            Some(worker) => worker.join().unwrap(),
+            None => Ok(image_digest.clone().unwrap_or_default()),
+        };
+        (image_hash, device_worker.join().unwrap())
+    });
+
+    let image_digest = image_hash?;
+    let device_digest = device_hash?;
+    if !image_reused && cacheable {
+        cache::store(image, ALGO, &image_digest);
+    }
+
+    Ok(Outcome {
+        matched: image_digest == device_digest,
+        image_digest,
+        device_digest,
+        device_read,
+    })
+}
+
+/// One progress line covering both streams
+fn print_progress(image_done: u64, device_done: u64, size: u64, image_reused: bool) {
+    let percent = |done: u64| done as f64 / size.max(1) as f64 * 100.0;
+
+    if image_reused {
+        print!("\rProgress: device {:6.2}%", percent(device_done));
+    } else {
+        print!(
+            "\rProgress: image {:6.2}% | device {:6.2}% | total {:6.2}%",
+            percent(image_done),
+            percent(device_done),
+            (percent(image_done) + percent(device_done)) / 2.0
+        );
+    }
+    let _ = io::stdout().flush();
+}
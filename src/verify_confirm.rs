@@ -26,7 +26,7 @@ pub fn menu(iso: &str, dev: &str) -> bool {
     enable_raw_mode().unwrap();
     let mut stdout = stdout();
 
-    let warn = vec!["Yes", "No"];
+    let warn = ["Yes", "No"];
     let mut selected = 0;
 
     loop {
@@ -51,8 +51,8 @@ pub fn menu(iso: &str, dev: &str) -> bool {
 
         if let Event::Key(key) = event::read().unwrap() {
             match key.code {
-                KeyCode::Up => if selected > 0 { selected -= 1 },
-                KeyCode::Down => if selected < warn.len() - 1 { selected += 1 },
+                KeyCode::Up => selected = selected.saturating_sub(1),
+                KeyCode::Down if selected < warn.len() - 1 => selected += 1,
                 KeyCode::Enter => {
                     disable_raw_mode().unwrap();
                     match selected {
@@ -0,0 +1,257 @@
+#!/usr/bin/env python3
+"""Write LZFSE test streams that all decompress to expected.txt.
+
+Apple's lzfse tool is not available where these were made, so the streams are
+encoded here, following the block formats of Apple's reference implementation
+(lzfse_internal.h, lzfse_encode_base.c, lzvn_encode_base.c):
+
+  bvx-.lzfse  one uncompressed block
+  bvxn.lzfse  one LZVN block, using every kind of literal, match and distance opcode
+  bvx2.lzfse  one LZFSE v2 block: FSE-coded literals and L, M, D values
+  multi.lzfse all three blocks in one stream, for the concatenated text
+"""
+
+import os
+import struct
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+TEXT = (b"tEtcher2 flashes images to USB drives. " * 3
+        + b"It reads ISO, IMG, qcow2, VHD, VHDX, VMDK and DMG images, and verifies what it wrote.\n"
+        + b"Verification reads the drive back and compares digests: digests, digests, digests.\n"
+        + b"Flashing also reads ISO, IMG, qcow2, VHD and more.\n"
+        + b"z" * 300 + b"\n")
+
+
+def parse(data, max_l, max_m, max_d):
+    """Greedy LZ77: (literals, match length, distance) triples, the last with no match"""
+    out = []
+    start = pos = 0
+    while pos < len(data):
+        best_m = best_d = 0
+        for d in range(1, min(pos, max_d) + 1):
+            m = 0
+            while pos + m < len(data) and m < max_m and data[pos + m] == data[pos + m - d]:
+                m += 1
+            if m > best_m:
+                best_m, best_d = m, d
+        if best_m >= 3 and pos - start <= max_l:
+            out.append((data[start:pos], best_m, best_d))
+            pos += best_m
+            start = pos
+        else:
+            pos += 1
+    out.append((data[start:], 0, 0))
+    return out
+
+
+def uncompressed(data):
+    return b"bvx-" + struct.pack("<I", len(data)) + data
+
+
+# LZVN
+
+def lzvn(data):
+    out = bytearray()
+
+    def literals(lits):
+        while lits:
+            n = min(len(lits), 271)
+            if n >= 16:
+                out.extend(bytes([0xE0, n - 16]))
+            else:
+                out.append(0xE0 | n)
+            out.extend(lits[:n])
+            lits = lits[n:]
+
+    def extend_match(m):
+        while m:
+            n = min(m, 271)
+            if n >= 16:
+                out.extend(bytes([0xF0, n - 16]))
+            else:
+                out.append(0xF0 | n)
+            m -= n
+
+    def first_length(l, m):
+        """Match length held by the opcode itself: with more literals, fewer lengths are left
+        once the medium distance, literal, match and undefined opcodes are taken out"""
+        return min(m - 3, (7, 5, 3, 1)[l])
+
+    previous = 0
+    for lits, m, d in parse(data, 1 << 30, 1 << 30, 0xFFFF):
+        if m == 0:
+            literals(lits)
+            break
+        literals(lits[:-3] if len(lits) > 3 else b"")
+        lits = lits[-3:] if len(lits) > 3 else lits
+        l = len(lits)
+        if d == previous and l > 0:
+            first = first_length(l, m)
+            out.append(l << 6 | first << 3 | 6)
+        elif d < 1536 and m <= 10 and first_length(l, m) == m - 3:
+            first = m - 3
+            out.extend(bytes([l << 6 | first << 3 | d >> 8, d & 0xFF]))
+        elif m <= 34 and d < 16384:
+            first = min(m - 3, 31)
+            out.extend(bytes([0xA0 | l << 3 | first >> 2]) + struct.pack("<H", d << 2 | (first & 3)))
+        else:
+            first = first_length(l, m)
+            out.extend(bytes([l << 6 | first << 3 | 7]) + struct.pack("<H", d))
+        out.extend(lits)
+        extend_match(m - 3 - first)
+        previous = d
+    # End of stream, padded to 8 bytes like the reference encoder
+    out.extend(b"\x06" + bytes(7))
+    return b"bvxn" + struct.pack("<II", len(data), len(out)) + bytes(out)
+
+
+# LZFSE v2
+
+L_EXTRA = [0] * 16 + [2, 3, 5, 8]
+L_BASE = list(range(16)) + [16, 20, 28, 60]
+M_EXTRA = [0] * 16 + [3, 5, 8, 11]
+M_BASE = list(range(16)) + [16, 24, 56, 312]
+D_EXTRA = [s // 4 for s in range(64)]
+D_BASE = [sum(1 << D_EXTRA[i] for i in range(s)) for s in range(64)]
+
+
+def symbol(value, base, extra):
+    for s in reversed(range(len(base))):
+        if value >= base[s]:
+            assert value - base[s] < 1 << extra[s]
+            return s
+    raise ValueError(value)
+
+
+def normalize(counts, nstates):
+    """Scale symbol counts to frequencies summing to nstates, keeping every used symbol"""
+    total = sum(counts)
+    freq = [max(1, c * nstates // total) if c else 0 for c in counts]
+    biggest = freq.index(max(freq))
+    freq[biggest] += nstates - sum(freq)
+    assert freq[biggest] > 0
+    return freq
+
+
+class Bits:
+    """Bits pushed LSB first into little-endian bytes, as fse_out_push does"""
+
+    def __init__(self):
+        self.value = 0
+        self.n = 0
+
+    def push(self, n, bits):
+        assert 0 <= bits < 1 << n or n == 0
+        self.value |= bits << self.n
+        self.n += n
+
+    def finish(self):
+        """Payload bytes and the padding count stored in the header (-7..0)"""
+        nbytes = (self.n + 7) // 8
+        # Leading zero bytes so the decoder always has 8 bytes to start from
+        return bytes(8) + self.value.to_bytes(nbytes, "little"), self.n - nbytes * 8
+
+
+def encoder_table(nstates, freq):
+    table = {}
+    offset = 0
+    for s, f in enumerate(freq):
+        if f:
+            k = (32 - f.bit_length()) - (32 - nstates.bit_length())
+            table[s] = ((f << k) - nstates, k, offset - f + (nstates >> k),
+                        offset - f + (nstates >> (k - 1)) if k else 0)
+            offset += f
+    return table
+
+
+def fse_encode(state, table, bits, s):
+    s0, k, delta0, delta1 = table[s]
+    hi = state >= s0
+    nbits = k if hi else k - 1
+    bits.push(nbits, state & ((1 << nbits) - 1))
+    return (delta0 if hi else delta1) + (state >> nbits)
+
+
+def freq_code(value):
+    small = {0: (2, 0), 1: (2, 2), 2: (3, 1), 3: (3, 5), 4: (5, 3), 5: (5, 11), 6: (5, 19), 7: (5, 27)}
+    if value in small:
+        return small[value]
+    if value < 24:
+        return 8, 7 + ((value - 8) << 4)
+    return 14, ((value - 24) << 4) + 15
+
+
+def lzfse_v2(data):
+    triples = parse(data, 315, 2359, 262139)
+    literals = b"".join(lits for lits, _, _ in triples)
+    l_values = [len(lits) for lits, _, _ in triples]
+    assert max(l_values) <= 315
+    m_values = [m for _, m, _ in triples]
+    d_values = []
+    previous = 0
+    for _, m, d in triples:
+        d_values.append(0 if d == previous or m == 0 else d)
+        if m:
+            previous = d
+    n_literals = len(literals)
+    literals += bytes(-len(literals) % 4)
+
+    l_syms = [symbol(v, L_BASE, L_EXTRA) for v in l_values]
+    m_syms = [symbol(v, M_BASE, M_EXTRA) for v in m_values]
+    d_syms = [symbol(v, D_BASE, D_EXTRA) for v in d_values]
+    l_freq = normalize([l_syms.count(s) for s in range(20)], 64)
+    m_freq = normalize([m_syms.count(s) for s in range(20)], 64)
+    d_freq = normalize([d_syms.count(s) for s in range(64)], 256)
+    lit_freq = normalize([literals.count(bytes([s])) for s in range(256)], 1024)
+
+    # Literals, last first, four interleaved states
+    table = encoder_table(1024, lit_freq)
+    bits = Bits()
+    states = [0, 0, 0, 0]
+    for i in reversed(range(0, len(literals), 4)):
+        for j in (3, 2, 1, 0):
+            states[j] = fse_encode(states[j], table, bits, literals[i + j])
+    literal_payload, literal_bits = bits.finish()
+
+    # L, M, D values, last first; each value's extra bits go below its state bits
+    tables = [encoder_table(64, l_freq), encoder_table(64, m_freq), encoder_table(256, d_freq)]
+    lmd = [0, 0, 0]
+    bits = Bits()
+    for i in reversed(range(len(triples))):
+        for which, value, sym, base, extra in ((2, d_values[i], d_syms[i], D_BASE, D_EXTRA),
+                                               (1, m_values[i], m_syms[i], M_BASE, M_EXTRA),
+                                               (0, l_values[i], l_syms[i], L_BASE, L_EXTRA)):
+            bits.push(extra[sym], value - base[sym])
+            lmd[which] = fse_encode(lmd[which], tables[which], bits, sym)
+    lmd_payload, lmd_bits = bits.finish()
+
+    freq_bits = Bits()
+    for f in l_freq + m_freq + d_freq + lit_freq:
+        freq_bits.push(*freq_code(f))
+    freq_table = freq_bits.value.to_bytes((freq_bits.n + 7) // 8, "little")
+    header_size = 32 + len(freq_table)
+
+    v0 = n_literals | len(literal_payload) << 20 | len(triples) << 40 | (literal_bits + 7) << 60
+    v1 = (states[0] | states[1] << 10 | states[2] << 20 | states[3] << 30
+          | len(lmd_payload) << 40 | (lmd_bits + 7) << 60)
+    v2 = header_size | lmd[0] << 32 | lmd[1] << 42 | lmd[2] << 52
+    return (b"bvx2" + struct.pack("<IQQQ", len(data), v0, v1, v2) + freq_table
+            + literal_payload + lmd_payload)
+
+
+END = b"bvx$"
+
+
+def main():
+    with open(os.path.join(HERE, "expected.txt"), "wb") as out:
+        out.write(TEXT)
+    for name, block in (("bvx-", uncompressed(TEXT)), ("bvxn", lzvn(TEXT)), ("bvx2", lzfse_v2(TEXT))):
+        with open(os.path.join(HERE, name + ".lzfse"), "wb") as out:
+            out.write(block + END)
+    with open(os.path.join(HERE, "multi.lzfse"), "wb") as out:
+        out.write(lzfse_v2(TEXT) + lzvn(TEXT) + uncompressed(TEXT) + END)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,391 @@
+#!/usr/bin/env python3
+"""Write small virtual disk fixtures that all expand to disk.raw.
+
+qemu-img and hdiutil are not available where these were made, so the images are
+laid out here from the format specifications, following what those tools write
+for a fresh image of this size:
+
+  disk.qcow2        v3, 4 KiB clusters, 16-bit refcounts; plain, zero-flagged
+                    and deflated clusters
+  disk.vhd          dynamic, 8 KiB blocks, checksummed footer and header
+  disk.vhdx         1 MiB-aligned log, metadata and BAT regions, both headers
+                    and region tables with CRC-32C checksums
+  disk.vmdk         monolithicSparse with a redundant grain directory and an
+                    explicit zero grain
+  disk-stream.vmdk  streamOptimized: zlib grains with markers, footer at the end
+  disk-split.vmdk   text descriptor over two FLAT extent files and a ZERO extent
+  disk.dmg          UDIF with two blkx tables and ADC, LZFSE, zlib, bzip2, raw,
+                    zero and free chunks
+
+disk.raw is 64 KiB of zeros except for three 4 KiB runs of data, at 0, 8 KiB
+and 36 KiB, so every format gets both allocated and unallocated areas.
+"""
+
+import base64
+import bz2
+import importlib.util
+import os
+import struct
+import zlib
+
+SIZE = 64 * 1024
+CHUNK = 4096
+A = bytes(i % 251 for i in range(CHUNK))
+B = (b"tEtcher2 " * 456)[:CHUNK]
+C = bytes([0xC3]) * CHUNK
+DATA = {0: A, 8 * 1024: B, 36 * 1024: C}
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+KIB = 1024
+MIB = 1024 * KIB
+
+
+def raw():
+    disk = bytearray(SIZE)
+    for offset, data in DATA.items():
+        disk[offset:offset + len(data)] = data
+    return bytes(disk)
+
+
+def put(buf, offset, data):
+    if len(buf) < offset + len(data):
+        buf.extend(bytes(offset + len(data) - len(buf)))
+    buf[offset:offset + len(data)] = data
+
+
+def guid(text):
+    """GUID bytes as stored on disk, first three fields little-endian"""
+    parts = text.split("-")
+    return (struct.pack("<IHH", int(parts[0], 16), int(parts[1], 16), int(parts[2], 16))
+            + bytes.fromhex(parts[3] + parts[4]))
+
+
+def crc32c(data):
+    crc = 0xFFFFFFFF
+    for byte in data:
+        crc ^= byte
+        for _ in range(8):
+            crc = (crc >> 1) ^ (0x82F63B78 if crc & 1 else 0)
+    return crc ^ 0xFFFFFFFF
+
+
+def qcow2():
+    """Header, refcount table, refcount block, L1 and L2 tables, then data clusters"""
+    disk = raw()
+    img = bytearray()
+    refcount_table, refcount_block, l1_table, l2_table = 0x1000, 0x2000, 0x3000, 0x4000
+    header = struct.pack(">4sIQIIQIIQQIIQQQQII", b"QFI\xfb", 3, 0, 0, 12, SIZE, 0, 1,
+                         l1_table, refcount_table, 1, 0, 0, 0, 0, 0, 4, 112)
+    # Header extensions end with a zero type and length
+    put(img, 0, header.ljust(112, b"\0") + bytes(8))
+    copied = 1 << 63
+    put(img, l1_table, struct.pack(">Q", l2_table | copied))
+
+    l2 = [0] * (CHUNK // 8)
+    l2[0] = 0x5000 | copied
+    put(img, 0x5000, disk[0:CHUNK])
+    l2[2] = 0x6000 | copied
+    put(img, 0x6000, disk[2 * CHUNK:3 * CHUNK])
+    # Deflated cluster: host offset and extra sector count share the entry
+    deflate = zlib.compressobj(9, zlib.DEFLATED, -15)
+    packed = deflate.compress(disk[9 * CHUNK:10 * CHUNK]) + deflate.flush()
+    host = 0x7000
+    sectors = (len(packed) + 511) // 512
+    offset_bits = 62 - (12 - 8)
+    l2[9] = (1 << 62) | ((sectors - 1) << offset_bits) | host
+    put(img, host, packed.ljust(sectors * 512, b"\0"))
+    # Preallocated but zero-flagged: the flag wins over the stale data it points at
+    l2[4] = 0x8000 | copied | 1
+    put(img, 0x8000, b"\xee" * CHUNK)
+    put(img, l2_table, struct.pack(">%dQ" % len(l2), *l2))
+
+    # Every cluster up to the last is in use exactly once
+    clusters = (len(img) + CHUNK - 1) // CHUNK
+    put(img, refcount_table, struct.pack(">Q", refcount_block))
+    put(img, refcount_block, struct.pack(">%dH" % clusters, *[1] * clusters))
+    return bytes(img.ljust(clusters * CHUNK, b"\0"))
+
+
+def vhd_footer():
+    footer = bytearray(struct.pack(">8sIIQI4sI4sQQIII16sB", b"conectix", 2, 0x00010000, 512, 0,
+                                   b"tetc", 0x00010000, b"Wi2k", SIZE, SIZE,
+                                   (2 << 16) | (16 << 8) | 4, 3, 0, bytes(16), 0)).ljust(512, b"\0")
+    struct.pack_into(">I", footer, 64, ~sum(footer) & 0xFFFFFFFF)
+    return bytes(footer)
+
+
+def vhd():
+    """Dynamic, 8 KiB blocks: blocks 0, 1 and 4 allocated"""
+    disk = raw()
+    block = 8 * KIB
+    img = bytearray(vhd_footer())
+    header = bytearray(struct.pack(">8sQQIII", b"cxsparse", 0xFFFFFFFFFFFFFFFF, 1536,
+                                   0x00010000, SIZE // block, block)).ljust(1024, b"\0")
+    struct.pack_into(">I", header, 36, ~sum(header) & 0xFFFFFFFF)
+    put(img, 512, header)
+
+    bat = [0xFFFFFFFF] * (SIZE // block)
+    sector = 2048 // 512
+    for index in (0, 1, 4):
+        bat[index] = sector
+        put(img, sector * 512, b"\xff" * 512 + disk[index * block:(index + 1) * block])
+        sector += (512 + block) // 512
+    put(img, 1536, struct.pack(">%dI" % len(bat), *bat).ljust(512, b"\xff"))
+    img.extend(vhd_footer())
+    return bytes(img)
+
+
+def vhdx():
+    """Log at 1 MiB, metadata at 2 MiB, BAT at 3 MiB and the one 1 MiB payload block at 4 MiB"""
+    disk = raw()
+    img = bytearray()
+    put(img, 0, b"vhdxfile" + "tEtcher2 fixtures".encode("utf-16-le"))
+    log_offset, meta_offset, bat_offset, payload_offset = MIB, 2 * MIB, 3 * MIB, 4 * MIB
+
+    # Both headers, the second one current; an all-zero log GUID means nothing to replay
+    for sequence, offset in ((1, 64 * KIB), (2, 128 * KIB)):
+        header = bytearray(struct.pack("<4sIQ16s16s16sHHIQ", b"head", 0, sequence,
+                                       guid("4C9D9F83-0B5A-4C5E-8D73-6E0D2B9E1A01"),
+                                       guid("4C9D9F83-0B5A-4C5E-8D73-6E0D2B9E1A02"),
+                                       bytes(16), 0, 1, MIB, log_offset)).ljust(4 * KIB, b"\0")
+        struct.pack_into("<I", header, 4, crc32c(header))
+        put(img, offset, header)
+
+    regions = bytearray(struct.pack("<4sIII", b"regi", 0, 2, 0))
+    regions += guid("2DC27766-F623-4200-9D64-115E9BFD4A08") + struct.pack("<QII", bat_offset, MIB, 1)
+    regions += guid("8B7CA206-4790-4B9A-B8FE-575F050F886E") + struct.pack("<QII", meta_offset, MIB, 1)
+    regions = regions.ljust(64 * KIB, b"\0")
+    struct.pack_into("<I", regions, 4, crc32c(regions))
+    put(img, 192 * KIB, regions)
+    put(img, 256 * KIB, regions)
+    put(img, log_offset + MIB - 1, b"\0")
+
+    is_virtual_disk, is_required = 2, 4
+    items = [
+        ("CAA16737-FA36-4D43-B3B6-33F0AA44E76B", struct.pack("<II", MIB, 0), is_required),
+        ("2FA54224-CD1B-4876-B211-5DBED83BF4B8", struct.pack("<Q", SIZE), is_virtual_disk | is_required),
+        ("BECA12AB-B2E6-4523-93EF-C309E000C746", guid("4C9D9F83-0B5A-4C5E-8D73-6E0D2B9E1A03"),
+         is_virtual_disk | is_required),
+        ("8141BF1D-A96F-4709-BA47-F233A8FAAB5F", struct.pack("<I", 512), is_virtual_disk | is_required),
+        ("CDA348C7-445D-4471-9CC9-E9885251C556", struct.pack("<I", 4096), is_virtual_disk | is_required),
+    ]
+    table = struct.pack("<8sHH20s", b"metadata", 0, len(items), bytes(20))
+    data_at = 64 * KIB
+    for item, data, flags in items:
+        table += guid(item) + struct.pack("<IIII", data_at, len(data), flags, 0)
+        put(img, meta_offset + data_at, data)
+        data_at += len(data)
+    put(img, meta_offset, table)
+    put(img, meta_offset + MIB - 1, b"\0")
+
+    # Block 0 fully present; the sector bitmap entry after every chunk of payload entries stays zero
+    payload_fully_present = 6
+    put(img, bat_offset, struct.pack("<Q", (payload_offset // MIB) << 20 | payload_fully_present))
+    put(img, bat_offset + MIB - 1, b"\0")
+    put(img, payload_offset, disk.ljust(MIB, b"\0"))
+    return bytes(img)
+
+
+VMDK_NEWLINES = b"\n \r\n"
+
+
+def vmdk_header(flags, gd, rgd, overhead, version=1, compress=0):
+    grain = CHUNK // 512
+    return struct.pack("<4sIIQQQQIQQQB4sH", b"KDMV", version, flags, SIZE // 512, grain, 1, 20, 512,
+                       rgd, gd, overhead, 0, VMDK_NEWLINES, compress).ljust(512, b"\0")
+
+
+def vmdk_descriptor(create_type, extents):
+    return ('# Disk DescriptorFile\nversion=1\nCID=7a3c1d2e\nparentCID=ffffffff\n'
+            'createType="%s"\n\n# Extent description\n%s\n\n# The Disk Data Base\n#DDB\n\n'
+            'ddb.virtualHWVersion = "4"\nddb.geometry.cylinders = "0"\n'
+            'ddb.geometry.heads = "16"\nddb.geometry.sectors = "63"\nddb.adapterType = "ide"\n'
+            % (create_type, extents)).encode()
+
+
+def vmdk():
+    """Monolithic sparse, 4 KiB grains: grains 0, 2 and 9 allocated, 5 an explicit zero grain"""
+    disk = raw()
+    grain = CHUNK // 512
+    valid_newlines, redundant_gd = 1, 2
+    # Descriptor in sectors 1-20, redundant directory and table, then the primary pair
+    rgd, rgt, gd, gt, overhead = 21, 22, 26, 27, 32
+    img = bytearray(vmdk_header(valid_newlines | redundant_gd, gd, rgd, overhead))
+    put(img, 512, vmdk_descriptor("monolithicSparse", 'RW %d SPARSE "disk.vmdk"' % (SIZE // 512))
+        .ljust(20 * 512, b"\0"))
+
+    table = [0] * 512
+    sector = overhead
+    for index in (0, 2, 9):
+        table[index] = sector
+        put(img, sector * 512, disk[index * CHUNK:(index + 1) * CHUNK])
+        sector += grain
+    table[5] = 1
+    for directory, at in ((rgd, rgt), (gd, gt)):
+        put(img, directory * 512, struct.pack("<I", at).ljust(512, b"\0"))
+        put(img, at * 512, struct.pack("<512I", *table))
+    return bytes(img)
+
+
+def vmdk_stream():
+    """streamOptimized, as in an OVA: every grain deflated behind a marker, tables at the end"""
+    disk = raw()
+    grain = CHUNK // 512
+    flags = 1 | 1 << 16 | 1 << 17
+    gd_at_end = 0xFFFFFFFFFFFFFFFF
+    img = bytearray(vmdk_header(flags, gd_at_end, 0, 128, version=3, compress=1))
+    put(img, 512, vmdk_descriptor("streamOptimized", 'RW %d SPARSE "disk-stream.vmdk"' % (SIZE // 512))
+        .ljust(20 * 512, b"\0"))
+
+    def sectors():
+        return len(img) // 512
+
+    def marker(value, size, kind):
+        img.extend(struct.pack("<QII", value, size, kind).ljust(512, b"\0"))
+
+    img.extend(bytes(128 * 512 - len(img)))
+    table = [0] * 512
+    for index in range(SIZE // CHUNK):
+        data = disk[index * CHUNK:(index + 1) * CHUNK]
+        if not any(data):
+            continue
+        table[index] = sectors()
+        packed = zlib.compress(data, 9)
+        body = struct.pack("<QI", index * grain, len(packed)) + packed
+        img.extend(body.ljust((len(body) + 511) // 512 * 512, b"\0"))
+
+    grain_table, grain_directory, footer = 1, 2, 3
+    marker(4, 0, grain_table)
+    gt = sectors()
+    img.extend(struct.pack("<512I", *table))
+    marker(1, 0, grain_directory)
+    gd = sectors()
+    img.extend(struct.pack("<I", gt).ljust(512, b"\0"))
+    marker(1, 0, footer)
+    img.extend(vmdk_header(flags, gd, 0, 128, version=3, compress=1))
+    # End-of-stream marker
+    img.extend(bytes(512))
+    return bytes(img)
+
+
+def vmdk_split():
+    """Descriptor over FLAT 0-16 KiB, ZERO 16-32 KiB and FLAT 32-64 KiB one sector into its file"""
+    disk = raw()
+    extents = ('RW 32 FLAT "disk-split-f001.vmdk" 0\n'
+               'RW 32 ZERO\n'
+               'RW 64 FLAT "disk-split-f002.vmdk" 1')
+    return {
+        "disk-split.vmdk": vmdk_descriptor("custom", extents),
+        "disk-split-f001.vmdk": disk[:16 * KIB],
+        "disk-split-f002.vmdk": b"\xee" * 512 + disk[32 * KIB:],
+    }
+
+
+def adc(data):
+    """Greedy Apple Data Compression, using every opcode length"""
+    out = bytearray()
+    literals = bytearray()
+
+    def flush():
+        while literals:
+            n = min(len(literals), 128)
+            out.append(0x80 | (n - 1))
+            out.extend(literals[:n])
+            del literals[:n]
+
+    pos = 0
+    while pos < len(data):
+        best_m = best_d = 0
+        for d in range(1, min(pos, 65536) + 1):
+            m = 0
+            while pos + m < len(data) and m < 67 and data[pos + m] == data[pos + m - d]:
+                m += 1
+            if m > best_m:
+                best_m, best_d = m, d
+        if best_m >= 3 and best_m <= 18 and best_d <= 1024:
+            flush()
+            out.extend(bytes([(best_m - 3) << 2 | (best_d - 1) >> 8, (best_d - 1) & 0xFF]))
+            pos += best_m
+        elif best_m >= 4:
+            flush()
+            out.extend(bytes([0x40 | (best_m - 4)]) + struct.pack(">H", best_d - 1))
+            pos += best_m
+        else:
+            literals.append(data[pos])
+            pos += 1
+    flush()
+    return bytes(out)
+
+
+def lzfse(data):
+    spec = importlib.util.spec_from_file_location("make_vectors", os.path.join(HERE, "../lzfse/make_vectors.py"))
+    vectors = importlib.util.module_from_spec(spec)
+    spec.loader.exec_module(vectors)
+    return vectors.lzfse_v2(data) + vectors.END
+
+
+def dmg():
+    """Two blkx tables of 64 sectors each, like the partition tables hdiutil writes"""
+    disk = raw()
+    zero, raw_, ignore, adc_, zlib_, bzip2, lzfse_, comment, end = (
+        0, 1, 2, 0x80000004, 0x80000005, 0x80000006, 0x80000007, 0x7FFFFFFE, 0xFFFFFFFF)
+    # (type, first sector, sector count, stored data)
+    tables = [
+        [(adc_, 0, 8, adc(disk[0:4 * KIB])),
+         (zero, 8, 8, b""),
+         (comment, 16, 0, b""),
+         (lzfse_, 16, 8, lzfse(disk[8 * KIB:12 * KIB])),
+         (ignore, 24, 40, b"")],
+        [(zero, 0, 8, b""),
+         (zlib_, 8, 8, zlib.compress(disk[36 * KIB:40 * KIB], 9)),
+         (raw_, 16, 16, disk[40 * KIB:48 * KIB]),
+         (bzip2, 32, 32, bz2.compress(disk[48 * KIB:64 * KIB]))],
+    ]
+
+    data_fork = bytearray()
+    blkx = []
+    for number, chunks in enumerate(tables):
+        first_sector = number * 64
+        table_start = len(data_fork)
+        entries = b""
+        for kind, sector, count, stored in chunks:
+            entries += struct.pack(">IIQQQQ", kind, 0, sector, count, len(data_fork) - table_start, len(stored))
+            data_fork.extend(stored)
+        entries += struct.pack(">IIQQQQ", end, 0, 64, 0, len(data_fork) - table_start, 0)
+        mish = (struct.pack(">4sIQQQII24s", b"mish", 1, first_sector, 64, table_start, 0, len(chunks) + 1, bytes(24))
+                + bytes(136) + struct.pack(">I", len(chunks) + 1) + entries)
+        blkx.append(mish)
+
+    xml = ['<?xml version="1.0" encoding="UTF-8"?>',
+           '<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">',
+           '<plist version="1.0">', '<dict>', '\t<key>resource-fork</key>', '\t<dict>', '\t\t<key>blkx</key>',
+           '\t\t<array>']
+    for number, mish in enumerate(blkx):
+        xml += ['\t\t\t<dict>', '\t\t\t\t<key>Attributes</key>', '\t\t\t\t<string>0x0050</string>',
+                '\t\t\t\t<key>CFName</key>', '\t\t\t\t<string>disk image (Apple_HFS : %d)</string>' % (number + 1),
+                '\t\t\t\t<key>Data</key>', '\t\t\t\t<data>', '\t\t\t\t' + base64.b64encode(mish).decode(),
+                '\t\t\t\t</data>', '\t\t\t\t<key>ID</key>', '\t\t\t\t<string>%d</string>' % number,
+                '\t\t\t\t<key>Name</key>', '\t\t\t\t<string>disk image (Apple_HFS : %d)</string>' % (number + 1),
+                '\t\t\t</dict>']
+    xml += ['\t\t</array>', '\t</dict>', '</dict>', '</plist>', '']
+    xml = "\n".join(xml).encode()
+
+    xml_offset = len(data_fork)
+    koly = struct.pack(">4sIIIQQQQQII16sII128sQQ120sII128sIQ12s", b"koly", 4, 512, 1, 0, 0, len(data_fork), 0, 0,
+                       1, 1, bytes(range(16)), 0, 0, bytes(128), xml_offset, len(xml), bytes(120), 0, 0,
+                       bytes(128), 1, SIZE // 512, bytes(12))
+    assert len(koly) == 512
+    return bytes(data_fork) + xml + koly
+
+
+def main():
+    files = {"disk.raw": raw(), "disk.qcow2": qcow2(), "disk.vhd": vhd(), "disk.vhdx": vhdx(),
+             "disk.vmdk": vmdk(), "disk-stream.vmdk": vmdk_stream(), "disk.dmg": dmg()}
+    files.update(vmdk_split())
+    for name, data in files.items():
+        with open(os.path.join(HERE, name), "wb") as out:
+            out.write(data)
+
+
+if __name__ == "__main__":
+    main()